@@ -1,11 +1,13 @@
-use image::{GrayImage, ImageError};
+use image::{GrayImage, ImageError, Luma};
 
 use convolve2d::*;
 use rust_for_multimedia_canny::{
+    corners::{compute_corner_responses, perform_corner_suppression, Corner, CornerResponse},
     drog::perform_drog_convolution,
+    hog::{compute_hog, BlockNormalization, HogParameters},
     edge::{Edge, ThresholdedEdge},
-    hysteresis::perform_hysteresis_thresholding,
-    nonmax::perform_nonmax_suppression,
+    hysteresis::{perform_adaptive_hysteresis_thresholding, perform_hysteresis_thresholding},
+    nonmax::{perform_nonmax_suppression, perform_quantized_nonmax_suppression},
 };
 
 fn main() -> Result<(), ImageError> {
@@ -35,6 +37,25 @@ fn main() -> Result<(), ImageError> {
     .unwrap();
     count_nonzero_edges(&drog_edges);
 
+    // Histogram of oriented gradients
+    println!("Computing HOG descriptor...");
+    let hog = compute_hog(
+        normalized_image_matrix.get_width(),
+        normalized_image_matrix.get_height(),
+        &drog_edges,
+        &HogParameters {
+            cell_size: 8,
+            bin_count: 9,
+            signed_orientation: false,
+            block_size: 2,
+            normalization: BlockNormalization::L2HYS,
+        },
+    );
+    println!("HOG features: {}", hog.features.len());
+    hog.visualization
+        .save("test_outputs/myownlena_hog.png")
+        .unwrap();
+
     // Non-maximum suppression
     println!("Applying non-maximum suppression...");
     let (width, height) = (
@@ -54,6 +75,18 @@ fn main() -> Result<(), ImageError> {
     .save("test_outputs/myownlena_nonmax.png")
     .unwrap();
 
+    println!("Applying quantized non-maximum suppression...");
+    let quantized_nonmax_edges = perform_quantized_nonmax_suppression(width, height, &drog_edges);
+    count_nonzero_edges(&quantized_nonmax_edges);
+
+    GrayImage::from(
+        quantized_nonmax_edges
+            .map(edge_to_subpixel)
+            .map_subpixels(denormalize_subpixel),
+    )
+    .save("test_outputs/myownlena_nonmax_quantized.png")
+    .unwrap();
+
     // Hysteresis thresholding
     let thresholded_edges =
         perform_hysteresis_thresholding(width, height, &nonmax_edges, 0.05, 0.1, 3);
@@ -63,6 +96,41 @@ fn main() -> Result<(), ImageError> {
         .save("test_outputs/myownlena_hysteresis.png")
         .unwrap();
 
+    // Adaptive hysteresis thresholding
+    println!("Applying adaptive hysteresis thresholding...");
+    let adaptive_thresholded_edges =
+        perform_adaptive_hysteresis_thresholding(width, height, &nonmax_edges, 64, 0.8, 0.5, 3);
+    count_edge_types(&adaptive_thresholded_edges);
+
+    GrayImage::from(adaptive_thresholded_edges.map(thresholded_edge_to_subpixels))
+        .save("test_outputs/myownlena_hysteresis_adaptive.png")
+        .unwrap();
+
+    // Corner detection, reusing the DroG derivatives
+    println!("Detecting Harris corners...");
+    let corner_responses =
+        compute_corner_responses(width, height, &drog_edges, CornerResponse::HARRIS(0.04), 5, 1.0);
+    let harris_corners = perform_corner_suppression(width, height, &corner_responses, 1e-5, 5);
+    println!("Harris corners: {}", harris_corners.len());
+
+    println!("Detecting Shi-Tomasi corners...");
+    let corner_responses =
+        compute_corner_responses(width, height, &drog_edges, CornerResponse::SHITOMASI, 5, 1.0);
+    let shi_tomasi_corners = perform_corner_suppression(width, height, &corner_responses, 1e-3, 5);
+    println!("Shi-Tomasi corners: {}", shi_tomasi_corners.len());
+
+    let mut corners_image = GrayImage::from(
+        drog_edges
+            .map(edge_to_subpixel)
+            .map_subpixels(denormalize_subpixel),
+    );
+    for corner in shi_tomasi_corners.iter().take(100) {
+        mark_corner(&mut corners_image, corner);
+    }
+    corners_image
+        .save("test_outputs/myownlena_corners.png")
+        .unwrap();
+
     Ok(())
 }
 
@@ -86,6 +154,17 @@ fn thresholded_edge_to_subpixels(edge: ThresholdedEdge) -> SubPixels<u8, 1> {
     }])
 }
 
+fn mark_corner(image: &mut GrayImage, corner: &Corner) {
+    let (x, y) = (corner.col as i64, corner.row as i64);
+    for offset in -2..=2 {
+        for (px, py) in [(x + offset, y), (x, y + offset)] {
+            if px >= 0 && py >= 0 && px < image.width() as i64 && py < image.height() as i64 {
+                image.put_pixel(px as u32, py as u32, Luma([255]));
+            }
+        }
+    }
+}
+
 fn count_nonzero_edges(edges: &DynamicMatrix<Edge>) {
     println!(
         "Non-zero magnitudes: {}",
@@ -0,0 +1,87 @@
+use std::{env, fs::File, io, io::BufReader, io::BufWriter};
+
+use convolve2d::*;
+use image::GrayImage;
+use rust_for_multimedia_canny::{
+    drog::perform_drog_convolution,
+    edge::ThresholdedEdge,
+    hysteresis::{perform_hysteresis_thresholding, perform_temporal_hysteresis_thresholding},
+    nonmax::perform_nonmax_suppression,
+    video::{FrameWriter, PngSequenceWriter, Y4mReader, Y4mWriter},
+};
+
+fn main() -> io::Result<()> {
+    // Params
+    let sigma = 2.0;
+    let kernel_size = 10;
+
+    let args: Vec<String> = env::args().collect();
+    let input_path = args
+        .get(1)
+        .map(String::as_str)
+        .unwrap_or("test_assets/video.y4m");
+    let output_path = args
+        .get(2)
+        .map(String::as_str)
+        .unwrap_or("test_outputs/video_edges");
+    let temporal_smoothing = !args.iter().any(|arg| arg == "--no-temporal");
+
+    let reader = Y4mReader::new(BufReader::new(File::open(input_path)?))?;
+    let (width, height) = (reader.width(), reader.height());
+
+    let mut writer: Box<dyn FrameWriter> = if output_path.ends_with(".y4m") {
+        Box::new(Y4mWriter::new(
+            BufWriter::new(File::create(output_path)?),
+            width as u32,
+            height as u32,
+            reader.framerate(),
+        )?)
+    } else {
+        Box::new(PngSequenceWriter::new(output_path)?)
+    };
+
+    let mut previous_edges: Option<DynamicMatrix<ThresholdedEdge>> = None;
+
+    for (frame_index, frame) in reader.enumerate() {
+        println!("Processing frame {}...", frame_index + 1);
+
+        let image_matrix: DynamicMatrix<SubPixels<u8, 1>> = frame?.into();
+        let normalized_image_matrix = image_matrix.map_subpixels(normalize_subpixel);
+
+        let drog_edges = perform_drog_convolution(&normalized_image_matrix, kernel_size, sigma);
+        let nonmax_edges = perform_nonmax_suppression(width, height, &drog_edges, 3);
+
+        let thresholded_edges = match (&previous_edges, temporal_smoothing) {
+            (Some(previous_edges), true) => perform_temporal_hysteresis_thresholding(
+                width,
+                height,
+                &nonmax_edges,
+                0.05,
+                0.1,
+                3,
+                previous_edges,
+            ),
+            _ => perform_hysteresis_thresholding(width, height, &nonmax_edges, 0.05, 0.1, 3),
+        };
+
+        writer.write_frame(&GrayImage::from(
+            thresholded_edges.clone().map(thresholded_edge_to_subpixels),
+        ))?;
+
+        previous_edges = Some(thresholded_edges);
+    }
+
+    Ok(())
+}
+
+fn normalize_subpixel(x: u8) -> f64 {
+    (x as f64) / 255.0
+}
+
+fn thresholded_edge_to_subpixels(edge: ThresholdedEdge) -> SubPixels<u8, 1> {
+    SubPixels([match edge {
+        ThresholdedEdge::STRONG => 255,
+        ThresholdedEdge::WEAK => 32,
+        ThresholdedEdge::NULL => 0,
+    }])
+}
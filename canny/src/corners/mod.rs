@@ -0,0 +1,245 @@
+use convolve2d::{DynamicMatrix, Matrix};
+use itertools::Itertools;
+
+use crate::edge::Edge;
+
+#[derive(Copy, Clone, Debug)]
+pub enum CornerResponse {
+    HARRIS(f64),
+    SHITOMASI,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Corner {
+    pub row: usize,
+    pub col: usize,
+    pub response: f64,
+}
+
+pub fn compute_corner_responses(
+    width: usize,
+    height: usize,
+    drog_edges: &DynamicMatrix<Edge>,
+    response_type: CornerResponse,
+    window_size: usize,
+    window_sigma: f64,
+) -> DynamicMatrix<f64> {
+    // Products of the derivatives, i.e. the per-pixel structure tensor entries
+    let gradient_products: Vec<(f64, f64, f64)> = drog_edges
+        .get_data()
+        .iter()
+        .map(|edge| {
+            let (grad_x, grad_y) = edge.dir();
+            (grad_x * grad_x, grad_y * grad_y, grad_x * grad_y)
+        })
+        .collect();
+
+    let window = gaussian_window(window_size, window_sigma);
+    let stride = (window_size >> 1) as i32;
+
+    let responses_data = (0..width * height)
+        .map(|index| {
+            let row = (index / width) as i32;
+            let col = (index % width) as i32;
+
+            let (mut a, mut b, mut c) = (0.0, 0.0, 0.0);
+
+            for (row_offset, col_offset) in (-stride..=stride).cartesian_product(-stride..=stride) {
+                let neighbour_row = row + row_offset;
+                let neighbour_col = col + col_offset;
+
+                if neighbour_row < 0
+                    || neighbour_row >= height as i32
+                    || neighbour_col < 0
+                    || neighbour_col >= width as i32
+                {
+                    continue;
+                }
+
+                let weight = window[((row_offset + stride) * (2 * stride + 1)
+                    + col_offset
+                    + stride) as usize];
+                let (xx, yy, xy) =
+                    gradient_products[neighbour_row as usize * width + neighbour_col as usize];
+
+                a += weight * xx;
+                b += weight * yy;
+                c += weight * xy;
+            }
+
+            let trace = a + b;
+            let determinant = a * b - c * c;
+
+            match response_type {
+                CornerResponse::HARRIS(k) => determinant - k * trace * trace,
+                CornerResponse::SHITOMASI => {
+                    // Smallest eigenvalue of the 2x2 structure tensor
+                    0.5 * (trace - f64::hypot(a - b, 2.0 * c))
+                }
+            }
+        })
+        .collect();
+
+    DynamicMatrix::new(width, height, responses_data).unwrap()
+}
+
+pub fn perform_corner_suppression(
+    width: usize,
+    height: usize,
+    responses: &DynamicMatrix<f64>,
+    response_threshold: f64,
+    suppression_radius: usize,
+) -> Vec<Corner> {
+    let mut corners: Vec<Corner> = (0..width * height)
+        .filter(|index| responses.get_data()[*index] > response_threshold)
+        .filter(|index| is_local_max(*index, width, height, responses, suppression_radius))
+        .map(|index| Corner {
+            row: index / width,
+            col: index % width,
+            response: responses.get_data()[index],
+        })
+        .collect();
+
+    corners.sort_by(|first, second| {
+        second
+            .response
+            .partial_cmp(&first.response)
+            .expect("Comparing NaN responses")
+    });
+
+    corners
+}
+
+fn gaussian_window(window_size: usize, sigma: f64) -> Vec<f64> {
+    let stride = (window_size >> 1) as i32;
+    let exp_coefficient = -0.5 / (sigma * sigma);
+
+    let weights: Vec<f64> = (-stride..=stride)
+        .cartesian_product(-stride..=stride)
+        .map(|(r, c)| f64::exp(((r * r + c * c) as f64) * exp_coefficient))
+        .collect();
+
+    let weights_sum: f64 = weights.iter().sum();
+
+    weights.into_iter().map(|w| w / weights_sum).collect()
+}
+
+fn is_local_max(
+    index: usize,
+    width: usize,
+    height: usize,
+    responses: &DynamicMatrix<f64>,
+    suppression_radius: usize,
+) -> bool {
+    let row = (index / width) as i32;
+    let col = (index % width) as i32;
+    let radius = suppression_radius as i32;
+    let response = responses.get_data()[index];
+
+    (-radius..=radius)
+        .cartesian_product(-radius..=radius)
+        .all(|(row_offset, col_offset)| {
+            let near_row = row + row_offset;
+            let near_col = col + col_offset;
+
+            if near_row < 0 || near_row >= height as i32 || near_col < 0 || near_col >= width as i32 {
+                return true;
+            }
+
+            let near_index = near_row as usize * width + near_col as usize;
+
+            // Ties are broken by position so that plateaus yield a single corner
+            let near_response = responses.get_data()[near_index];
+            near_response < response || (near_response == response && near_index >= index)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: usize = 9;
+
+    // A vertical line of x gradients crossing a horizontal line of y gradients, the crossing
+    // itself being left empty to keep the pattern symmetric
+    fn crossing_edges() -> DynamicMatrix<Edge> {
+        let centre = SIZE / 2;
+        let data = (0..SIZE)
+            .cartesian_product(0..SIZE)
+            .map(|(row, col)| match (row == centre, col == centre) {
+                (true, true) => Edge::zero(),
+                (false, true) => Edge::new(1.0, 0.0),
+                (true, false) => Edge::new(0.0, 1.0),
+                _ => Edge::zero(),
+            })
+            .collect();
+
+        DynamicMatrix::new(SIZE, SIZE, data).unwrap()
+    }
+
+    fn response_at(responses: &DynamicMatrix<f64>, row: usize, col: usize) -> f64 {
+        responses.get_data()[row * SIZE + col]
+    }
+
+    #[test]
+    fn harris_separates_corners_from_straight_edges() {
+        let responses = compute_corner_responses(
+            SIZE,
+            SIZE,
+            &crossing_edges(),
+            CornerResponse::HARRIS(0.04),
+            5,
+            1.0,
+        );
+
+        assert!(response_at(&responses, 4, 4) > 0.0);
+        assert!(response_at(&responses, 0, 4) < 0.0);
+        assert_eq!(response_at(&responses, 0, 0), 0.0);
+    }
+
+    #[test]
+    fn shi_tomasi_is_zero_along_straight_edges() {
+        let responses = compute_corner_responses(
+            SIZE,
+            SIZE,
+            &crossing_edges(),
+            CornerResponse::SHITOMASI,
+            5,
+            1.0,
+        );
+
+        assert!(response_at(&responses, 4, 4) > 0.0);
+        assert!(response_at(&responses, 0, 4).abs() < 1e-12);
+    }
+
+    #[test]
+    fn suppression_keeps_one_corner_per_neighbourhood() {
+        let responses = compute_corner_responses(
+            SIZE,
+            SIZE,
+            &crossing_edges(),
+            CornerResponse::SHITOMASI,
+            5,
+            1.0,
+        );
+        let corners = perform_corner_suppression(SIZE, SIZE, &responses, 1e-6, 2);
+
+        assert_eq!(corners.len(), 1);
+        assert_eq!((corners[0].row, corners[0].col), (4, 4));
+    }
+
+    #[test]
+    fn plateaus_yield_a_single_corner_and_corners_are_sorted() {
+        let mut data = vec![0.0; SIZE * SIZE];
+        data[10] = 2.0;
+        data[11] = 2.0;
+        data[60] = 5.0;
+        let responses = DynamicMatrix::new(SIZE, SIZE, data).unwrap();
+
+        let corners = perform_corner_suppression(SIZE, SIZE, &responses, 1.0, 1);
+
+        let positions: Vec<(usize, usize)> =
+            corners.iter().map(|corner| (corner.row, corner.col)).collect();
+        assert_eq!(positions, [(6, 6), (1, 1)]);
+    }
+}
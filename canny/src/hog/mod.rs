@@ -0,0 +1,301 @@
+use std::f64::consts::PI;
+
+use convolve2d::{DynamicMatrix, Matrix};
+use image::{GrayImage, Luma};
+use itertools::Itertools;
+
+use crate::edge::Edge;
+
+const NORMALIZATION_EPSILON: f64 = 1e-6;
+const L2HYS_CLIP: f64 = 0.2;
+
+#[derive(Copy, Clone, Debug)]
+pub enum BlockNormalization {
+    L2,
+    L2HYS,
+    L1SQRT,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct HogParameters {
+    pub cell_size: usize,
+    pub bin_count: usize,
+    pub signed_orientation: bool,
+    pub block_size: usize,
+    pub normalization: BlockNormalization,
+}
+
+pub struct HogDescriptor {
+    pub cells_x: usize,
+    pub cells_y: usize,
+    pub cell_histograms: Vec<Vec<f64>>,
+    pub features: Vec<f64>,
+    pub visualization: GrayImage,
+}
+
+pub fn compute_hog(
+    width: usize,
+    height: usize,
+    edges: &DynamicMatrix<Edge>,
+    parameters: &HogParameters,
+) -> HogDescriptor {
+    assert!(
+        parameters.cell_size > 0,
+        "HOG cells must be at least one pixel wide"
+    );
+    assert!(
+        parameters.bin_count > 0,
+        "HOG histograms need at least one bin"
+    );
+    assert!(
+        parameters.block_size > 0,
+        "HOG blocks must span at least one cell"
+    );
+
+    let cell_size = parameters.cell_size;
+    let (cells_x, cells_y) = (width / cell_size, height / cell_size);
+
+    let cell_histograms: Vec<Vec<f64>> = (0..cells_y)
+        .cartesian_product(0..cells_x)
+        .map(|(cell_row, cell_col)| {
+            let mut histogram = vec![0.0; parameters.bin_count];
+
+            let rows = cell_row * cell_size..(cell_row + 1) * cell_size;
+            let cols = cell_col * cell_size..(cell_col + 1) * cell_size;
+
+            for (row, col) in rows.cartesian_product(cols) {
+                let edge = edges.get_data()[row * width + col];
+                vote_orientation(&mut histogram, &edge, parameters);
+            }
+
+            histogram
+        })
+        .collect();
+
+    let features = normalize_blocks(&cell_histograms, cells_x, cells_y, parameters);
+    let visualization = visualize_cells(&cell_histograms, cells_x, cells_y, parameters);
+
+    HogDescriptor {
+        cells_x,
+        cells_y,
+        cell_histograms,
+        features,
+        visualization,
+    }
+}
+
+fn orientation_range(parameters: &HogParameters) -> f64 {
+    if parameters.signed_orientation {
+        2.0 * PI
+    } else {
+        PI
+    }
+}
+
+fn vote_orientation(histogram: &mut [f64], edge: &Edge, parameters: &HogParameters) {
+    let magnitude = edge.get_magnitude();
+    if magnitude == 0.0 {
+        return;
+    }
+
+    let range = orientation_range(parameters);
+    let bin_width = range / parameters.bin_count as f64;
+    let angle = edge.angle().rem_euclid(range);
+
+    // Each vote is split between the two bins whose centres surround the angle
+    let position = angle / bin_width - 0.5;
+    let lower_bin = position.floor();
+    let upper_weight = position - lower_bin;
+
+    let bin_count = parameters.bin_count as i64;
+    let lower_index = (lower_bin as i64).rem_euclid(bin_count) as usize;
+    let upper_index = (lower_bin as i64 + 1).rem_euclid(bin_count) as usize;
+
+    histogram[lower_index] += magnitude * (1.0 - upper_weight);
+    histogram[upper_index] += magnitude * upper_weight;
+}
+
+fn normalize_blocks(
+    cell_histograms: &[Vec<f64>],
+    cells_x: usize,
+    cells_y: usize,
+    parameters: &HogParameters,
+) -> Vec<f64> {
+    let block_size = parameters.block_size;
+    if cells_x < block_size || cells_y < block_size {
+        return Vec::new();
+    }
+
+    (0..=cells_y - block_size)
+        .cartesian_product(0..=cells_x - block_size)
+        .flat_map(|(block_row, block_col)| {
+            let block: Vec<f64> = (block_row..block_row + block_size)
+                .cartesian_product(block_col..block_col + block_size)
+                .flat_map(|(cell_row, cell_col)| {
+                    cell_histograms[cell_row * cells_x + cell_col].clone()
+                })
+                .collect();
+
+            normalize_block(block, parameters.normalization)
+        })
+        .collect()
+}
+
+fn normalize_block(block: Vec<f64>, normalization: BlockNormalization) -> Vec<f64> {
+    let l2_normalize = |block: Vec<f64>| {
+        let norm = f64::sqrt(
+            block.iter().map(|v| v * v).sum::<f64>()
+                + NORMALIZATION_EPSILON * NORMALIZATION_EPSILON,
+        );
+        block.into_iter().map(|v| v / norm).collect::<Vec<f64>>()
+    };
+
+    match normalization {
+        BlockNormalization::L2 => l2_normalize(block),
+        BlockNormalization::L2HYS => {
+            let clipped = l2_normalize(block)
+                .into_iter()
+                .map(|v| v.min(L2HYS_CLIP))
+                .collect();
+            l2_normalize(clipped)
+        }
+        BlockNormalization::L1SQRT => {
+            let norm = block.iter().map(|v| v.abs()).sum::<f64>() + NORMALIZATION_EPSILON;
+            block.into_iter().map(|v| f64::sqrt(v / norm)).collect()
+        }
+    }
+}
+
+fn visualize_cells(
+    cell_histograms: &[Vec<f64>],
+    cells_x: usize,
+    cells_y: usize,
+    parameters: &HogParameters,
+) -> GrayImage {
+    let cell_size = parameters.cell_size;
+    let mut visualization =
+        GrayImage::new((cells_x * cell_size) as u32, (cells_y * cell_size) as u32);
+
+    let max_vote = cell_histograms
+        .iter()
+        .flatten()
+        .cloned()
+        .fold(0.0, f64::max);
+
+    if max_vote == 0.0 {
+        return visualization;
+    }
+
+    let range = orientation_range(parameters);
+    let bin_width = range / parameters.bin_count as f64;
+    let half_length = cell_size as f64 * 0.5;
+
+    for (cell_index, histogram) in cell_histograms.iter().enumerate() {
+        let centre_row = ((cell_index / cells_x) * cell_size) as f64 + half_length;
+        let centre_col = ((cell_index % cells_x) * cell_size) as f64 + half_length;
+
+        for (bin, vote) in histogram.iter().enumerate() {
+            let intensity = f64::round(vote / max_vote * 255.0) as u8;
+            if intensity == 0 {
+                continue;
+            }
+
+            // Gradients follow the DroG convention of x along rows, and the edge
+            // is drawn perpendicular to the gradient
+            let angle = (bin as f64 + 0.5) * bin_width;
+            let (row_step, col_step) = (-angle.sin(), angle.cos());
+
+            let steps = cell_size * 2;
+            for step in 0..=steps {
+                let t = (step as f64 / steps as f64 - 0.5) * (half_length * 2.0 - 1.0);
+                let row = (centre_row + t * row_step).floor();
+                let col = (centre_col + t * col_step).floor();
+
+                if row < 0.0 || col < 0.0 {
+                    continue;
+                }
+
+                let (x, y) = (col as u32, row as u32);
+                if x >= visualization.width() || y >= visualization.height() {
+                    continue;
+                }
+
+                let Luma([current]) = *visualization.get_pixel(x, y);
+                visualization.put_pixel(x, y, Luma([current.max(intensity)]));
+            }
+        }
+    }
+
+    visualization
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PARAMETERS: HogParameters = HogParameters {
+        cell_size: 4,
+        bin_count: 9,
+        signed_orientation: false,
+        block_size: 2,
+        normalization: BlockNormalization::L2,
+    };
+
+    fn uniform_edges(width: usize, height: usize, angle: f64) -> DynamicMatrix<Edge> {
+        let edge = Edge::new(angle.cos(), angle.sin());
+        DynamicMatrix::new(width, height, vec![edge; width * height]).unwrap()
+    }
+
+    #[test]
+    fn votes_at_a_bin_centre_fill_that_bin() {
+        let descriptor = compute_hog(8, 8, &uniform_edges(8, 8, 30f64.to_radians()), &PARAMETERS);
+
+        assert_eq!((descriptor.cells_x, descriptor.cells_y), (2, 2));
+        for histogram in &descriptor.cell_histograms {
+            let total: f64 = histogram.iter().sum();
+            assert!((histogram[1] - total).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn votes_between_bin_centres_are_split() {
+        // 0 degrees lies halfway between the centres of the last and the first bin
+        let descriptor = compute_hog(4, 4, &uniform_edges(4, 4, 0.0), &PARAMETERS);
+        let histogram = &descriptor.cell_histograms[0];
+
+        assert!(histogram[0] > 0.0);
+        assert!((histogram[0] - histogram[8]).abs() < 1e-9);
+    }
+
+    #[test]
+    fn l2_blocks_have_unit_norm() {
+        let descriptor = compute_hog(12, 8, &uniform_edges(12, 8, 1.0), &PARAMETERS);
+
+        // Two blocks of 2x2 cells with 9 bins each
+        assert_eq!(descriptor.features.len(), 2 * 4 * 9);
+        for block in descriptor.features.chunks(4 * 9) {
+            let norm = block.iter().map(|v| v * v).sum::<f64>().sqrt();
+            assert!((norm - 1.0).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one pixel wide")]
+    fn zero_cell_size_is_rejected() {
+        let parameters = HogParameters {
+            cell_size: 0,
+            ..PARAMETERS
+        };
+        compute_hog(4, 4, &uniform_edges(4, 4, 0.0), &parameters);
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one bin")]
+    fn zero_bin_count_is_rejected() {
+        let parameters = HogParameters {
+            bin_count: 0,
+            ..PARAMETERS
+        };
+        compute_hog(4, 4, &uniform_edges(4, 4, 0.0), &parameters);
+    }
+}
@@ -10,60 +10,222 @@ pub fn perform_hysteresis_thresholding(
     weak_edge_threshold: f64,
     strong_edge_threshold: f64,
     neighbourhood_size: usize,
+) -> DynamicMatrix<ThresholdedEdge> {
+    threshold_and_link_edges(
+        width,
+        height,
+        input_edges,
+        weak_edge_threshold,
+        strong_edge_threshold,
+        neighbourhood_size,
+        None,
+    )
+}
+
+pub fn perform_temporal_hysteresis_thresholding(
+    width: usize,
+    height: usize,
+    input_edges: &DynamicMatrix<Edge>,
+    weak_edge_threshold: f64,
+    strong_edge_threshold: f64,
+    neighbourhood_size: usize,
+    previous_edges: &DynamicMatrix<ThresholdedEdge>,
+) -> DynamicMatrix<ThresholdedEdge> {
+    // Weak edges are also kept when the previous frame had a strong edge nearby
+    threshold_and_link_edges(
+        width,
+        height,
+        input_edges,
+        weak_edge_threshold,
+        strong_edge_threshold,
+        neighbourhood_size,
+        Some(previous_edges),
+    )
+}
+
+fn threshold_and_link_edges(
+    width: usize,
+    height: usize,
+    input_edges: &DynamicMatrix<Edge>,
+    weak_edge_threshold: f64,
+    strong_edge_threshold: f64,
+    neighbourhood_size: usize,
+    previous_edges: Option<&DynamicMatrix<ThresholdedEdge>>,
 ) -> DynamicMatrix<ThresholdedEdge> {
     let image_size = width * height;
-    let edges_indices = 0..image_size;
-    let neighbourhood_size = neighbourhood_size as i32;
 
-    let thresholds_data = edges_indices
-        .into_iter()
+    let thresholds_data = (0..image_size)
         .map(|index| {
-            let edge = input_edges.get_data()[index];
-
-            if edge.get_magnitude() < weak_edge_threshold {
-                ThresholdedEdge::NULL
-            } else if edge.get_magnitude() > strong_edge_threshold {
-                ThresholdedEdge::STRONG
-            } else {
-                ThresholdedEdge::WEAK
-            }
+            threshold_edge(
+                &input_edges.get_data()[index],
+                weak_edge_threshold,
+                strong_edge_threshold,
+            )
         })
         .collect();
 
-    let thresholds: DynamicMatrix<ThresholdedEdge> =
-        DynamicMatrix::new(width, height, thresholds_data).unwrap();
+    link_weak_edges(
+        width,
+        height,
+        thresholds_data,
+        neighbourhood_size,
+        previous_edges,
+    )
+}
 
-    let thresholded_edges_data = thresholds
+pub fn perform_adaptive_hysteresis_thresholding(
+    width: usize,
+    height: usize,
+    input_edges: &DynamicMatrix<Edge>,
+    tile_size: usize,
+    strong_edge_percentile: f64,
+    weak_edge_ratio: f64,
+    neighbourhood_size: usize,
+) -> DynamicMatrix<ThresholdedEdge> {
+    let strong_edge_thresholds = compute_local_thresholds(
+        width,
+        height,
+        input_edges,
+        tile_size,
+        strong_edge_percentile,
+    );
+
+    let thresholds_data = strong_edge_thresholds
         .get_data()
         .iter()
         .enumerate()
-        .map(|(index, edge_type)| match edge_type {
-            ThresholdedEdge::STRONG => ThresholdedEdge::STRONG,
-            ThresholdedEdge::WEAK => {
-                let row: usize = index / width;
-                let col: usize = index - (row * width);
+        .map(|(index, strong_edge_threshold)| {
+            threshold_edge(
+                &input_edges.get_data()[index],
+                strong_edge_threshold * weak_edge_ratio,
+                *strong_edge_threshold,
+            )
+        })
+        .collect();
+
+    link_weak_edges(width, height, thresholds_data, neighbourhood_size, None)
+}
+
+pub fn compute_local_thresholds(
+    width: usize,
+    height: usize,
+    input_edges: &DynamicMatrix<Edge>,
+    tile_size: usize,
+    percentile: f64,
+) -> DynamicMatrix<f64> {
+    assert!(
+        tile_size > 0,
+        "Threshold tiles must be at least one pixel wide"
+    );
+
+    let tiles_x = width.div_ceil(tile_size);
+    let tiles_y = height.div_ceil(tile_size);
+
+    let global_threshold = magnitude_percentile(
+        input_edges.get_data().iter().map(Edge::get_magnitude),
+        percentile,
+    )
+    .unwrap_or(0.0);
+
+    // Tiles without any edge fall back to the statistics of the whole image
+    let tile_thresholds: Vec<f64> = (0..tiles_y)
+        .cartesian_product(0..tiles_x)
+        .map(|(tile_row, tile_col)| {
+            let rows = tile_row * tile_size..usize::min((tile_row + 1) * tile_size, height);
+            let cols = tile_col * tile_size..usize::min((tile_col + 1) * tile_size, width);
+
+            let magnitudes = rows
+                .cartesian_product(cols)
+                .map(|(row, col)| input_edges.get_data()[row * width + col].get_magnitude());
+
+            magnitude_percentile(magnitudes, percentile).unwrap_or(global_threshold)
+        })
+        .collect();
+
+    // Bilinear interpolation between the tile centres
+    let tile_coordinate = |position: usize, tiles_count: usize| {
+        let coordinate = (position as f64 + 0.5) / tile_size as f64 - 0.5;
+        coordinate.clamp(0.0, (tiles_count - 1) as f64)
+    };
+
+    let thresholds_data = (0..height)
+        .cartesian_product(0..width)
+        .map(|(row, col)| {
+            let tile_y = tile_coordinate(row, tiles_y);
+            let tile_x = tile_coordinate(col, tiles_x);
+
+            let (top, left) = (tile_y.floor() as usize, tile_x.floor() as usize);
+            let (bottom, right) = (
+                usize::min(top + 1, tiles_y - 1),
+                usize::min(left + 1, tiles_x - 1),
+            );
+            let (weight_y, weight_x) = (tile_y - top as f64, tile_x - left as f64);
+
+            let tile_threshold =
+                |tile_row: usize, tile_col: usize| tile_thresholds[tile_row * tiles_x + tile_col];
+
+            let top_threshold = tile_threshold(top, left) * (1.0 - weight_x)
+                + tile_threshold(top, right) * weight_x;
+            let bottom_threshold = tile_threshold(bottom, left) * (1.0 - weight_x)
+                + tile_threshold(bottom, right) * weight_x;
 
-                let neighbourhood_range = -neighbourhood_size..neighbourhood_size;
+            top_threshold * (1.0 - weight_y) + bottom_threshold * weight_y
+        })
+        .collect();
 
-                let has_strong_neighbour = neighbourhood_range
-                    .clone()
-                    .cartesian_product(neighbourhood_range)
-                    .any(|(row_offset, col_offset)| {
-                        let neighbour_row = row as i32 + row_offset;
-                        let neighbour_col = col as i32 + col_offset;
+    DynamicMatrix::new(width, height, thresholds_data).unwrap()
+}
 
-                        let neighbour_index = neighbour_row * (width as i32) + neighbour_col;
+fn magnitude_percentile(magnitudes: impl Iterator<Item = f64>, percentile: f64) -> Option<f64> {
+    let mut magnitudes: Vec<f64> = magnitudes.filter(|magnitude| *magnitude > 0.0).collect();
 
-                        if neighbour_index < 0 || neighbour_index >= image_size as i32 {
-                            return false;
-                        }
+    if magnitudes.is_empty() {
+        return None;
+    }
 
-                        let neighbour_index = neighbour_index as usize;
+    magnitudes
+        .sort_by(|first, second| first.partial_cmp(second).expect("Comparing NaN magnitudes"));
 
-                        let neighbour = thresholds.get_data()[neighbour_index];
+    let rank = (percentile.clamp(0.0, 1.0) * (magnitudes.len() - 1) as f64).round() as usize;
+    Some(magnitudes[rank])
+}
+
+fn threshold_edge(
+    edge: &Edge,
+    weak_edge_threshold: f64,
+    strong_edge_threshold: f64,
+) -> ThresholdedEdge {
+    if edge.get_magnitude() < weak_edge_threshold {
+        ThresholdedEdge::NULL
+    } else if edge.get_magnitude() > strong_edge_threshold {
+        ThresholdedEdge::STRONG
+    } else {
+        ThresholdedEdge::WEAK
+    }
+}
 
-                        matches!(neighbour, ThresholdedEdge::STRONG)
-                    });
+fn link_weak_edges(
+    width: usize,
+    height: usize,
+    thresholds_data: Vec<ThresholdedEdge>,
+    neighbourhood_size: usize,
+    previous_edges: Option<&DynamicMatrix<ThresholdedEdge>>,
+) -> DynamicMatrix<ThresholdedEdge> {
+    let thresholds: DynamicMatrix<ThresholdedEdge> =
+        DynamicMatrix::new(width, height, thresholds_data).unwrap();
+
+    let thresholded_edges_data = thresholds
+        .get_data()
+        .iter()
+        .enumerate()
+        .map(|(index, edge_type)| match edge_type {
+            ThresholdedEdge::STRONG => ThresholdedEdge::STRONG,
+            ThresholdedEdge::WEAK => {
+                let has_strong_neighbour =
+                    has_strong_neighbour(index, width, &thresholds, neighbourhood_size)
+                        || previous_edges.is_some_and(|previous_edges| {
+                            has_strong_neighbour(index, width, previous_edges, neighbourhood_size)
+                        });
 
                 if has_strong_neighbour {
                     ThresholdedEdge::STRONG
@@ -80,3 +242,145 @@ pub fn perform_hysteresis_thresholding(
 
     thresholded_edges
 }
+
+fn has_strong_neighbour(
+    index: usize,
+    width: usize,
+    edges: &DynamicMatrix<ThresholdedEdge>,
+    neighbourhood_size: usize,
+) -> bool {
+    let image_size = edges.get_data().len();
+    let neighbourhood_size = neighbourhood_size as i32;
+
+    let row: usize = index / width;
+    let col: usize = index - (row * width);
+
+    let neighbourhood_range = -neighbourhood_size..neighbourhood_size;
+
+    neighbourhood_range
+        .clone()
+        .cartesian_product(neighbourhood_range)
+        .any(|(row_offset, col_offset)| {
+            let neighbour_row = row as i32 + row_offset;
+            let neighbour_col = col as i32 + col_offset;
+
+            let neighbour_index = neighbour_row * (width as i32) + neighbour_col;
+
+            if neighbour_index < 0 || neighbour_index >= image_size as i32 {
+                return false;
+            }
+
+            let neighbour_index = neighbour_index as usize;
+
+            let neighbour = edges.get_data()[neighbour_index];
+
+            matches!(neighbour, ThresholdedEdge::STRONG)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edges(
+        width: usize,
+        height: usize,
+        magnitude: impl Fn(usize, usize) -> f64,
+    ) -> DynamicMatrix<Edge> {
+        let data = (0..height)
+            .cartesian_product(0..width)
+            .map(|(row, col)| Edge::new(magnitude(row, col) * std::f64::consts::SQRT_2, 0.0))
+            .collect();
+
+        DynamicMatrix::new(width, height, data).unwrap()
+    }
+
+    #[test]
+    fn uniform_magnitudes_give_uniform_thresholds() {
+        let thresholds = compute_local_thresholds(8, 6, &edges(8, 6, |_, _| 10.0), 4, 0.5);
+
+        assert!(thresholds
+            .get_data()
+            .iter()
+            .all(|threshold| (threshold - 10.0).abs() < 1e-9));
+    }
+
+    #[test]
+    fn tiles_without_edges_use_the_global_threshold() {
+        // Only the left tile has edges, so both tiles end up with its percentile
+        let input_edges = edges(8, 4, |_, col| if col < 4 { 6.0 } else { 0.0 });
+        let thresholds = compute_local_thresholds(8, 4, &input_edges, 4, 0.5);
+
+        assert!(thresholds
+            .get_data()
+            .iter()
+            .all(|threshold| (threshold - 6.0).abs() < 1e-9));
+    }
+
+    #[test]
+    fn thresholds_follow_local_contrast() {
+        let input_edges = edges(16, 4, |_, col| if col < 8 { 2.0 } else { 20.0 });
+        let thresholds = compute_local_thresholds(16, 4, &input_edges, 8, 0.5);
+
+        assert!((thresholds.get_data()[0] - 2.0).abs() < 1e-9);
+        assert!((thresholds.get_data()[15] - 20.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn weak_edges_next_to_strong_ones_are_kept() {
+        let input_edges = edges(6, 1, |_, col| [0.0, 1.0, 5.0, 1.0, 0.0, 1.0][col]);
+        let thresholded = perform_hysteresis_thresholding(6, 1, &input_edges, 0.5, 2.0, 2);
+
+        let strong: Vec<bool> = thresholded
+            .get_data()
+            .iter()
+            .map(|edge| matches!(edge, ThresholdedEdge::STRONG))
+            .collect();
+        assert_eq!(strong, [false, true, true, true, false, false]);
+    }
+
+    #[test]
+    fn weak_edges_near_previous_strong_edges_are_kept() {
+        let input_edges = edges(6, 1, |_, col| if col == 4 { 1.0 } else { 0.0 });
+        let previous_edges = DynamicMatrix::new(
+            6,
+            1,
+            (0..6)
+                .map(|col| {
+                    if col == 3 {
+                        ThresholdedEdge::STRONG
+                    } else {
+                        ThresholdedEdge::NULL
+                    }
+                })
+                .collect(),
+        )
+        .unwrap();
+
+        let without_history = perform_hysteresis_thresholding(6, 1, &input_edges, 0.5, 2.0, 2);
+        let with_history = perform_temporal_hysteresis_thresholding(
+            6,
+            1,
+            &input_edges,
+            0.5,
+            2.0,
+            2,
+            &previous_edges,
+        );
+
+        assert!(matches!(
+            without_history.get_data()[4],
+            ThresholdedEdge::NULL
+        ));
+        assert!(matches!(
+            with_history.get_data()[4],
+            ThresholdedEdge::STRONG
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "Threshold tiles must be at least one pixel wide")]
+    fn zero_tile_size_is_rejected() {
+        perform_adaptive_hysteresis_thresholding(4, 4, &edges(4, 4, |_, _| 1.0), 0, 0.9, 0.5, 1);
+    }
+}
@@ -1,4 +1,7 @@
 pub mod drog;
 pub mod edge;
 pub mod nonmax;
-pub mod hysteresis;
\ No newline at end of file
+pub mod hysteresis;
+pub mod corners;
+pub mod hog;
+pub mod video;
\ No newline at end of file
@@ -26,7 +26,7 @@ pub fn perform_nonmax_suppression(
                     col,
                     width,
                     image_size,
-                    &drog_edges,
+                    drog_edges,
                     &edge,
                     distance_range,
                 ) {
@@ -41,6 +41,78 @@ pub fn perform_nonmax_suppression(
     nonmax_edges
 }
 
+pub fn perform_quantized_nonmax_suppression(
+    width: usize,
+    height: usize,
+    drog_edges: &DynamicMatrix<Edge>,
+) -> DynamicMatrix<Edge> {
+    let image_size = width * height;
+    let edges_indices = 0..image_size;
+    let nonmax_edges: DynamicMatrix<Edge> = DynamicMatrix::new(
+        width,
+        height,
+        edges_indices
+            .into_iter()
+            .map(|index| {
+                let row: usize = index / width;
+                let col: usize = index - (row * width);
+
+                let edge = drog_edges.get_data()[index];
+
+                if is_sector_max(row, col, width, height, drog_edges, &edge) {
+                    edge
+                } else {
+                    Edge::zero()
+                }
+            })
+            .collect(),
+    )
+    .unwrap();
+    nonmax_edges
+}
+
+fn is_sector_max(
+    row: usize,
+    col: usize,
+    width: usize,
+    height: usize,
+    drog_edges: &DynamicMatrix<Edge>,
+    edge: &Edge,
+) -> bool {
+    // Gradient direction folded into [0, 180) degrees and quantised into the
+    // 0/45/90/135 sectors, with rows following the x component as in is_max
+    let mut angle = edge.angle().to_degrees();
+    if angle < 0.0 {
+        angle += 180.0;
+    }
+
+    let (row_offset, col_offset): (i32, i32) = if !(22.5..157.5).contains(&angle) {
+        (1, 0)
+    } else if angle < 67.5 {
+        (1, 1)
+    } else if angle < 112.5 {
+        (0, 1)
+    } else {
+        (-1, 1)
+    };
+
+    [(row_offset, col_offset), (-row_offset, -col_offset)]
+        .iter()
+        .all(|(near_row_offset, near_col_offset)| {
+            let near_row = row as i32 + near_row_offset;
+            let near_col = col as i32 + near_col_offset;
+
+            if near_row < 0 || near_row >= height as i32 || near_col < 0 || near_col >= width as i32 {
+                return true;
+            }
+
+            let near_index = near_row as usize * width + near_col as usize;
+            let near_edge = drog_edges.get_data()[near_index];
+
+            edge.get_magnitude() >= near_edge.get_magnitude()
+        })
+}
+
 fn is_max(
     row: usize,
     col: usize,
@@ -78,3 +150,47 @@ fn is_max(
 
     true
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kept(edges: &DynamicMatrix<Edge>) -> Vec<bool> {
+        edges
+            .get_data()
+            .iter()
+            .map(|edge| edge.get_magnitude() > 0.0)
+            .collect()
+    }
+
+    #[test]
+    fn quantised_suppression_thins_across_the_gradient() {
+        // x gradients are compared with their neighbours along the rows
+        let magnitudes = [1.0, 3.0, 2.0];
+        let data = magnitudes
+            .iter()
+            .flat_map(|magnitude| [Edge::new(*magnitude, 0.0); 3])
+            .collect();
+        let edges = DynamicMatrix::new(3, 3, data).unwrap();
+
+        let thinned = perform_quantized_nonmax_suppression(3, 3, &edges);
+
+        assert_eq!(
+            kept(&thinned),
+            [false, false, false, true, true, true, false, false, false]
+        );
+    }
+
+    #[test]
+    fn diagonal_gradients_use_the_diagonal_neighbours() {
+        let mut data = vec![Edge::new(1.0, 1.0); 9];
+        data[4] = Edge::new(2.0, 2.0);
+        data[0] = Edge::new(3.0, 3.0);
+        let edges = DynamicMatrix::new(3, 3, data).unwrap();
+
+        let thinned = perform_quantized_nonmax_suppression(3, 3, &edges);
+
+        assert!(!kept(&thinned)[4]);
+        assert!(kept(&thinned)[0]);
+    }
+}
@@ -0,0 +1,13 @@
+use std::io;
+
+use image::GrayImage;
+
+mod png_sequence;
+mod y4m;
+
+pub use png_sequence::PngSequenceWriter;
+pub use y4m::{Y4mReader, Y4mWriter};
+
+pub trait FrameWriter {
+    fn write_frame(&mut self, frame: &GrayImage) -> io::Result<()>;
+}
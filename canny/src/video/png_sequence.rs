@@ -0,0 +1,34 @@
+use std::{fs, io, path::PathBuf};
+
+use image::GrayImage;
+
+use super::FrameWriter;
+
+pub struct PngSequenceWriter {
+    output_folder: PathBuf,
+    frame_index: usize,
+}
+
+impl PngSequenceWriter {
+    pub fn new(output_folder: impl Into<PathBuf>) -> io::Result<Self> {
+        let output_folder = output_folder.into();
+        fs::create_dir_all(&output_folder)?;
+
+        Ok(Self {
+            output_folder,
+            frame_index: 0,
+        })
+    }
+}
+
+impl FrameWriter for PngSequenceWriter {
+    fn write_frame(&mut self, frame: &GrayImage) -> io::Result<()> {
+        self.frame_index += 1;
+
+        let path = self
+            .output_folder
+            .join(format!("{:05}.png", self.frame_index));
+
+        frame.save(path).map_err(io::Error::other)
+    }
+}
@@ -0,0 +1,334 @@
+use std::io::{self, BufRead, Write};
+
+use image::GrayImage;
+
+use super::FrameWriter;
+
+const STREAM_MAGIC: &str = "YUV4MPEG2";
+const FRAME_MAGIC: &str = "FRAME";
+
+pub struct Y4mReader<R: BufRead> {
+    reader: R,
+    width: usize,
+    height: usize,
+    framerate: (u32, u32),
+    bytes_per_sample: usize,
+    bit_depth: u32,
+    chroma_size: usize,
+}
+
+impl<R: BufRead> Y4mReader<R> {
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let header =
+            read_line(&mut reader)?.ok_or_else(|| invalid_data("Missing Y4M stream header"))?;
+
+        let mut tokens = header.split_ascii_whitespace();
+        if tokens.next() != Some(STREAM_MAGIC) {
+            return Err(invalid_data("Not a YUV4MPEG2 stream"));
+        }
+
+        let (mut width, mut height) = (None, None);
+        let mut framerate = (25, 1);
+        let mut colorspace = "420jpeg";
+
+        for token in tokens {
+            let mut chars = token.chars();
+            let tag = chars.next();
+            let value = chars.as_str();
+            match tag {
+                Some('W') => width = Some(parse_number(value)?),
+                Some('H') => height = Some(parse_number(value)?),
+                Some('F') => {
+                    let (num, den) = value
+                        .split_once(':')
+                        .ok_or_else(|| invalid_data("Malformed Y4M framerate"))?;
+                    framerate = (parse_number(num)? as u32, parse_number(den)? as u32);
+                }
+                Some('C') => colorspace = value,
+                _ => {}
+            }
+        }
+
+        let width = width.ok_or_else(|| invalid_data("Missing Y4M width"))?;
+        let height = height.ok_or_else(|| invalid_data("Missing Y4M height"))?;
+
+        let (subsampling, bit_depth) = parse_colorspace(colorspace)?;
+        let bytes_per_sample = if bit_depth > 8 { 2 } else { 1 };
+
+        let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+        let chroma_samples = match subsampling {
+            "420" => 2 * chroma_width * chroma_height,
+            "422" => 2 * chroma_width * height,
+            "444" => 2 * width * height,
+            "444alpha" => 3 * width * height,
+            "mono" => 0,
+            _ => {
+                return Err(invalid_data(&format!(
+                    "Unsupported Y4M colorspace {}",
+                    colorspace
+                )))
+            }
+        };
+
+        Ok(Self {
+            reader,
+            width,
+            height,
+            framerate,
+            bytes_per_sample,
+            bit_depth,
+            chroma_size: chroma_samples * bytes_per_sample,
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn framerate(&self) -> (u32, u32) {
+        self.framerate
+    }
+
+    pub fn read_luma_frame(&mut self) -> io::Result<Option<GrayImage>> {
+        let frame_header = match read_line(&mut self.reader)? {
+            Some(frame_header) => frame_header,
+            None => return Ok(None),
+        };
+
+        if !frame_header.starts_with(FRAME_MAGIC) {
+            return Err(invalid_data("Malformed Y4M frame header"));
+        }
+
+        let mut luma = vec![0; self.width * self.height * self.bytes_per_sample];
+        self.reader.read_exact(&mut luma)?;
+
+        let mut chroma = vec![0; self.chroma_size];
+        self.reader.read_exact(&mut chroma)?;
+
+        // High bit depth samples are little endian and are scaled down to 8 bits
+        let luma = if self.bytes_per_sample == 2 {
+            luma.chunks_exact(2)
+                .map(|sample| {
+                    (u16::from_le_bytes([sample[0], sample[1]]) >> (self.bit_depth - 8)) as u8
+                })
+                .collect()
+        } else {
+            luma
+        };
+
+        Ok(GrayImage::from_raw(
+            self.width as u32,
+            self.height as u32,
+            luma,
+        ))
+    }
+}
+
+impl<R: BufRead> Iterator for Y4mReader<R> {
+    type Item = io::Result<GrayImage>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_luma_frame().transpose()
+    }
+}
+
+pub struct Y4mWriter<W: Write> {
+    writer: W,
+    width: u32,
+    height: u32,
+}
+
+impl<W: Write> Y4mWriter<W> {
+    pub fn new(mut writer: W, width: u32, height: u32, framerate: (u32, u32)) -> io::Result<Self> {
+        writeln!(
+            writer,
+            "{} W{} H{} F{}:{} Ip A1:1 Cmono",
+            STREAM_MAGIC, width, height, framerate.0, framerate.1
+        )?;
+
+        Ok(Self {
+            writer,
+            width,
+            height,
+        })
+    }
+}
+
+impl<W: Write> FrameWriter for Y4mWriter<W> {
+    fn write_frame(&mut self, frame: &GrayImage) -> io::Result<()> {
+        if frame.dimensions() != (self.width, self.height) {
+            return Err(invalid_data("Frame size does not match the Y4M stream"));
+        }
+
+        writeln!(self.writer, "{}", FRAME_MAGIC)?;
+        self.writer.write_all(frame.as_raw())
+    }
+}
+
+// The named 4:2:0 variants only differ in chroma siting, other colorspaces may carry a bit
+// depth suffix such as 420p10. The 8 bit 444alpha and mono names are matched whole, since
+// the first one contains a 'p' too.
+fn parse_colorspace(colorspace: &str) -> io::Result<(&str, u32)> {
+    let (subsampling, bit_depth) = match colorspace {
+        "420jpeg" | "420paldv" | "420mpeg2" => ("420", 8),
+        "444alpha" | "mono" => (colorspace, 8),
+        _ => match colorspace.split_once('p') {
+            Some((subsampling, bit_depth)) => (subsampling, parse_number(bit_depth)? as u32),
+            None => (colorspace, 8),
+        },
+    };
+
+    if !(8..=16).contains(&bit_depth) {
+        return Err(invalid_data(&format!(
+            "Unsupported Y4M colorspace {}",
+            colorspace
+        )));
+    }
+
+    Ok((subsampling, bit_depth))
+}
+
+fn read_line<R: BufRead>(reader: &mut R) -> io::Result<Option<String>> {
+    let mut line = Vec::new();
+    if reader.read_until(b'\n', &mut line)? == 0 {
+        return Ok(None);
+    }
+
+    if line.last() == Some(&b'\n') {
+        line.pop();
+    }
+
+    String::from_utf8(line)
+        .map(Some)
+        .map_err(|_| invalid_data("Y4M header is not valid ASCII"))
+}
+
+fn parse_number(value: &str) -> io::Result<usize> {
+    value
+        .parse()
+        .map_err(|_| invalid_data(&format!("Invalid Y4M number {}", value)))
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    // A 4x2 frame whose luma samples are 0..8, followed by its 4:2:0 chroma
+    fn stream_420(header: &str) -> Vec<u8> {
+        let mut stream = format!("{}\n{}\n", header, FRAME_MAGIC).into_bytes();
+        stream.extend(0..8);
+        stream.extend([128; 4]);
+        stream
+    }
+
+    fn read_frames(stream: Vec<u8>) -> io::Result<Vec<GrayImage>> {
+        Y4mReader::new(Cursor::new(stream))?.collect()
+    }
+
+    fn assert_420_frame(header: &str) {
+        let frames = read_frames(stream_420(header)).unwrap();
+
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].dimensions(), (4, 2));
+        assert_eq!(frames[0].as_raw(), &(0..8).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn reads_420jpeg() {
+        assert_420_frame("YUV4MPEG2 W4 H2 F25:1 Ip A1:1 C420jpeg");
+    }
+
+    #[test]
+    fn missing_colorspace_defaults_to_420jpeg() {
+        assert_420_frame("YUV4MPEG2 W4 H2 F25:1");
+    }
+
+    #[test]
+    fn reads_420paldv() {
+        assert_420_frame("YUV4MPEG2 W4 H2 C420paldv");
+    }
+
+    #[test]
+    fn reads_420mpeg2() {
+        assert_420_frame("YUV4MPEG2 W4 H2 C420mpeg2");
+    }
+
+    #[test]
+    fn reads_420p10_scaled_to_8_bits() {
+        let mut stream = format!("YUV4MPEG2 W4 H2 C420p10\n{}\n", FRAME_MAGIC).into_bytes();
+        for sample in [0u16, 4, 512, 1023, 1, 2, 3, 1020] {
+            stream.extend(sample.to_le_bytes());
+        }
+        stream.extend([0; 8]);
+
+        let frames = read_frames(stream).unwrap();
+        assert_eq!(frames[0].as_raw(), &[0, 1, 128, 255, 0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn reads_444alpha_and_mono() {
+        let mut stream = format!("YUV4MPEG2 W4 H2 C444alpha\n{}\n", FRAME_MAGIC).into_bytes();
+        stream.extend(0..8);
+        stream.extend([128; 3 * 8]);
+        stream.extend(format!("{}\n", FRAME_MAGIC).into_bytes());
+        stream.extend(8..16);
+        stream.extend([128; 3 * 8]);
+
+        let frames = read_frames(stream).unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[1].as_raw(), &(8..16).collect::<Vec<u8>>());
+
+        let mut stream = format!("YUV4MPEG2 W4 H2 Cmono\n{}\n", FRAME_MAGIC).into_bytes();
+        stream.extend(0..8);
+        assert_eq!(
+            read_frames(stream).unwrap()[0].as_raw(),
+            &(0..8).collect::<Vec<u8>>()
+        );
+    }
+
+    #[test]
+    fn reads_the_framerate() {
+        let reader =
+            Y4mReader::new(Cursor::new(stream_420("YUV4MPEG2 W4 H2 F30000:1001"))).unwrap();
+        assert_eq!(reader.framerate(), (30000, 1001));
+    }
+
+    #[test]
+    fn multibyte_tags_are_ignored() {
+        assert_420_frame("YUV4MPEG2 W4 H2 \u{e9}t\u{e9} C420jpeg");
+    }
+
+    #[test]
+    fn rejects_unknown_colorspaces() {
+        assert!(read_frames(stream_420("YUV4MPEG2 W4 H2 C411")).is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_frames() {
+        let mut stream = stream_420("YUV4MPEG2 W4 H2");
+        stream.truncate(stream.len() - 1);
+
+        assert!(read_frames(stream).is_err());
+    }
+
+    #[test]
+    fn written_streams_read_back() {
+        let frame = GrayImage::from_raw(4, 2, (10..18).collect()).unwrap();
+        let mut stream = Vec::new();
+        let mut writer = Y4mWriter::new(&mut stream, 4, 2, (25, 1)).unwrap();
+        writer.write_frame(&frame).unwrap();
+        writer.write_frame(&frame).unwrap();
+
+        assert_eq!(read_frames(stream).unwrap(), vec![frame.clone(), frame]);
+    }
+}
@@ -0,0 +1,129 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use image::{DynamicImage, GenericImageView, GrayImage};
+use itertools::Itertools;
+use motion_estimation::{
+    bma::exhaustive::ExhaustiveBlockMatcher,
+    metrics::kernels::{sad, sad_scalar, ssd, ssd_scalar},
+    plane::LumaPlane,
+    utils::tile_frame,
+    BlockMatcher, SearchContext,
+};
+
+const BLOCK_SIZE: u32 = 16;
+const SEARCH_RANGE: i32 = 16;
+const BLOCK_POSITION: (u32, u32) = (144, 112);
+
+fn load_frame(name: &str) -> GrayImage {
+    image::open(format!("assets/meatthezoo_frames/{}", name))
+        .expect("Failed to open benchmark frame")
+        .to_luma8()
+}
+
+// The matching path before plane views: every candidate is cropped and converted to luma,
+// and the mean absolute difference is accumulated in floating point
+fn legacy_mad(anchor_block: &DynamicImage, target_block: &DynamicImage) -> f64 {
+    let anchor_pixels = anchor_block.to_luma8().into_raw();
+    let target_pixels = target_block.to_luma8().into_raw();
+    let error_weight = 1.0 / anchor_pixels.len() as f64;
+
+    anchor_pixels
+        .into_iter()
+        .zip(target_pixels)
+        .map(|(anchor_pixel, target_pixel)| {
+            (anchor_pixel as i32 - target_pixel as i32).abs() as f64 * error_weight
+        })
+        .sum()
+}
+
+fn legacy_exhaustive_search(
+    block: &DynamicImage,
+    (x_offset, y_offset): (u32, u32),
+    frame: &DynamicImage,
+) -> (i32, i32, f64) {
+    (-SEARCH_RANGE..SEARCH_RANGE)
+        .cartesian_product(-SEARCH_RANGE..SEARCH_RANGE)
+        .filter_map(|(dx, dy)| {
+            let (x, y) = (x_offset as i32 + dx, y_offset as i32 + dy);
+            if x < 0
+                || y < 0
+                || x as u32 + BLOCK_SIZE > frame.width()
+                || y as u32 + BLOCK_SIZE > frame.height()
+            {
+                return None;
+            }
+
+            let candidate = frame.crop_imm(x as u32, y as u32, BLOCK_SIZE, BLOCK_SIZE);
+            Some((dx, dy, legacy_mad(block, &candidate)))
+        })
+        .min_by(|first, second| first.2.partial_cmp(&second.2).unwrap())
+        .unwrap()
+}
+
+fn block_distortion(c: &mut Criterion) {
+    let (anchor_frame, target_frame) = (load_frame("001.jpeg"), load_frame("002.jpeg"));
+    let (anchor_plane, target_plane) = (
+        LumaPlane::from_image(&anchor_frame),
+        LumaPlane::from_image(&target_frame),
+    );
+    let (x, y) = BLOCK_POSITION;
+    let anchor_block = anchor_plane
+        .view(x as i32, y as i32, BLOCK_SIZE, BLOCK_SIZE)
+        .unwrap();
+    let target_block = target_plane
+        .view(x as i32 + 3, y as i32 - 2, BLOCK_SIZE, BLOCK_SIZE)
+        .unwrap();
+
+    let (anchor_image, target_image) = (
+        DynamicImage::ImageLuma8(anchor_frame.clone()),
+        DynamicImage::ImageLuma8(target_frame.clone()),
+    );
+
+    let mut group = c.benchmark_group("block_distortion_16x16");
+    group.bench_function("legacy_mad", |b| {
+        b.iter(|| {
+            let anchor = anchor_image.crop_imm(x, y, BLOCK_SIZE, BLOCK_SIZE);
+            let target = target_image.crop_imm(x + 3, y - 2, BLOCK_SIZE, BLOCK_SIZE);
+            legacy_mad(black_box(&anchor), black_box(&target))
+        })
+    });
+    group.bench_function("sad_scalar", |b| {
+        b.iter(|| sad_scalar(black_box(&anchor_block), black_box(&target_block)))
+    });
+    group.bench_function("sad", |b| {
+        b.iter(|| sad(black_box(&anchor_block), black_box(&target_block)))
+    });
+    group.bench_function("ssd_scalar", |b| {
+        b.iter(|| ssd_scalar(black_box(&anchor_block), black_box(&target_block)))
+    });
+    group.bench_function("ssd", |b| {
+        b.iter(|| ssd(black_box(&anchor_block), black_box(&target_block)))
+    });
+    group.finish();
+}
+
+fn exhaustive_search(c: &mut Criterion) {
+    let (anchor_frame, target_frame) = (load_frame("001.jpeg"), load_frame("002.jpeg"));
+    let target_plane = LumaPlane::from_image(&target_frame);
+    let (anchor_image, target_image) = (
+        DynamicImage::ImageLuma8(anchor_frame),
+        DynamicImage::ImageLuma8(target_frame.clone()),
+    );
+
+    let block = tile_frame(&anchor_image, BLOCK_SIZE)
+        .into_iter()
+        .find(|block| (block.x_offset, block.y_offset) == BLOCK_POSITION)
+        .unwrap();
+    let matcher = ExhaustiveBlockMatcher::new(SEARCH_RANGE as u16);
+
+    let mut group = c.benchmark_group("exhaustive_search_16x16");
+    group.bench_function("legacy", |b| {
+        b.iter(|| legacy_exhaustive_search(black_box(&block.pixels), BLOCK_POSITION, &target_image))
+    });
+    group.bench_function("plane", |b| {
+        b.iter(|| matcher.match_block(black_box(&block), &target_plane, &SearchContext::default()))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, block_distortion, exhaustive_search);
+criterion_main!(benches);
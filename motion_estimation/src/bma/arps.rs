@@ -0,0 +1,157 @@
+use log::debug;
+
+use crate::{
+    metrics::{sad::MadMetric, DistortionMetric},
+    plane::LumaPlane,
+    rate::RateConstraint,
+    types::{MotionVector, SearchContext},
+    ExtractedBlock,
+};
+
+use super::{pattern_search::PatternSearch, BlockMatcher};
+
+const UNIT_ROOD: [(i32, i32); 4] = [(0, -1), (-1, 0), (1, 0), (0, 1)];
+const DEFAULT_ARM_LENGTH: i32 = 2;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PredictorMode {
+    Left,
+    Median,
+}
+
+pub struct AdaptiveRoodPatternBlockMatcher {
+    search_region_size: i32,
+    predictor_mode: PredictorMode,
+    metric: Box<dyn DistortionMetric>,
+    lambda: f64,
+}
+
+impl AdaptiveRoodPatternBlockMatcher {
+    pub fn new(search_region_size: u16) -> Self {
+        Self {
+            search_region_size: search_region_size as i32,
+            predictor_mode: PredictorMode::Left,
+            metric: Box::new(MadMetric::new()),
+            lambda: 0.0,
+        }
+    }
+
+    pub fn with_predictor_mode(self, predictor_mode: PredictorMode) -> Self {
+        Self {
+            predictor_mode,
+            ..self
+        }
+    }
+
+    pub fn with_metric(self, metric: Box<dyn DistortionMetric>) -> Self {
+        Self { metric, ..self }
+    }
+
+    pub fn with_lambda(self, lambda: f64) -> Self {
+        Self { lambda, ..self }
+    }
+}
+
+impl BlockMatcher for AdaptiveRoodPatternBlockMatcher {
+    fn match_block(
+        &self,
+        block: &ExtractedBlock,
+        frame: &LumaPlane,
+        context: &SearchContext,
+    ) -> MotionVector {
+        let mut search = PatternSearch::new(
+            block,
+            frame,
+            self.metric.as_ref(),
+            self.search_region_size,
+            RateConstraint::new(self.lambda, context),
+        );
+
+        let start_cost = search
+            .evaluate((0, 0))
+            .expect("Anchor block lies outside the target frame");
+
+        let predicted_vector = match self.predictor_mode {
+            PredictorMode::Left => context.left_predictor(),
+            PredictorMode::Median => context.median_predictor(),
+        };
+
+        // The rood arm is as long as the largest component of the predicted vector
+        let arm_length = predicted_vector
+            .map(|(dx, dy)| i32::max(dx.abs(), dy.abs()))
+            .unwrap_or(DEFAULT_ARM_LENGTH);
+
+        let mut initial_pattern = vec![
+            (0, -arm_length),
+            (-arm_length, 0),
+            (arm_length, 0),
+            (0, arm_length),
+        ];
+        initial_pattern.extend(predicted_vector);
+
+        let coarse = search.descend((0, 0, start_cost), &initial_pattern, false);
+        let refined = search.descend(coarse, &UNIT_ROOD, true);
+
+        debug!(
+            "Prediction: ({}, {}) from predictor {:?}",
+            refined.0, refined.1, predicted_vector
+        );
+
+        search.into_motion_vector(refined)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::match_shifted_block;
+
+    fn neighbour(dx: i32, dy: i32) -> Option<MotionVector> {
+        Some(MotionVector {
+            dx,
+            dy,
+            cost: 0.0,
+            points_evaluated: 1,
+            points_pruned: 0,
+        })
+    }
+
+    #[test]
+    fn jumps_to_the_left_predictor() {
+        let context = SearchContext {
+            left: neighbour(9, 4),
+            ..SearchContext::default()
+        };
+        let vector =
+            match_shifted_block(&AdaptiveRoodPatternBlockMatcher::new(16), (9, 4), &context);
+
+        assert_eq!((vector.dx, vector.dy, vector.cost), (9, 4, 0.0));
+        // The rood, the predictor and one unit rood around it
+        assert!(vector.points_evaluated <= 10);
+    }
+
+    #[test]
+    fn median_mode_ignores_an_outlying_left_neighbour() {
+        let context = SearchContext {
+            left: neighbour(-12, 7),
+            top: neighbour(9, 4),
+            top_right: neighbour(9, 4),
+        };
+        let matcher =
+            AdaptiveRoodPatternBlockMatcher::new(16).with_predictor_mode(PredictorMode::Median);
+        let vector = match_shifted_block(&matcher, (9, 4), &context);
+
+        assert_eq!((vector.dx, vector.dy), (9, 4));
+    }
+
+    #[test]
+    fn finds_small_shifts_without_neighbours() {
+        let vector = match_shifted_block(
+            &AdaptiveRoodPatternBlockMatcher::new(16),
+            (1, -2),
+            &SearchContext::default(),
+        );
+
+        assert_eq!((vector.dx, vector.dy, vector.cost), (1, -2, 0.0));
+    }
+}
@@ -0,0 +1,104 @@
+use log::debug;
+
+use crate::{
+    metrics::{sad::MadMetric, DistortionMetric},
+    plane::LumaPlane,
+    rate::RateConstraint,
+    types::{MotionVector, SearchContext},
+    ExtractedBlock,
+};
+
+use super::{pattern_search::PatternSearch, BlockMatcher};
+
+const LARGE_DIAMOND: [(i32, i32); 8] = [
+    (0, -2),
+    (-1, -1),
+    (1, -1),
+    (-2, 0),
+    (2, 0),
+    (-1, 1),
+    (1, 1),
+    (0, 2),
+];
+
+const SMALL_DIAMOND: [(i32, i32); 4] = [(0, -1), (-1, 0), (1, 0), (0, 1)];
+
+pub struct DiamondSearchBlockMatcher {
+    search_region_size: i32,
+    metric: Box<dyn DistortionMetric>,
+    lambda: f64,
+}
+
+impl DiamondSearchBlockMatcher {
+    pub fn new(search_region_size: u16) -> Self {
+        Self {
+            search_region_size: search_region_size as i32,
+            metric: Box::new(MadMetric::new()),
+            lambda: 0.0,
+        }
+    }
+
+    pub fn with_metric(self, metric: Box<dyn DistortionMetric>) -> Self {
+        Self { metric, ..self }
+    }
+
+    pub fn with_lambda(self, lambda: f64) -> Self {
+        Self { lambda, ..self }
+    }
+}
+
+impl BlockMatcher for DiamondSearchBlockMatcher {
+    fn match_block(
+        &self,
+        block: &ExtractedBlock,
+        frame: &LumaPlane,
+        context: &SearchContext,
+    ) -> MotionVector {
+        let mut search = PatternSearch::new(
+            block,
+            frame,
+            self.metric.as_ref(),
+            self.search_region_size,
+            RateConstraint::new(self.lambda, context),
+        );
+
+        let start_cost = search
+            .evaluate((0, 0))
+            .expect("Anchor block lies outside the target frame");
+
+        let coarse = search.descend((0, 0, start_cost), &LARGE_DIAMOND, true);
+        let refined = search.descend(coarse, &SMALL_DIAMOND, false);
+
+        debug!("Prediction: ({}, {})", refined.0, refined.1);
+
+        search.into_motion_vector(refined)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{bma::exhaustive::ExhaustiveBlockMatcher, test_utils::match_shifted_block};
+
+    #[test]
+    fn descends_to_the_shift_with_fewer_points_than_exhaustive() {
+        let context = SearchContext::default();
+        let vector = match_shifted_block(&DiamondSearchBlockMatcher::new(16), (5, -3), &context);
+        let exhaustive_vector =
+            match_shifted_block(&ExhaustiveBlockMatcher::new(16), (5, -3), &context);
+
+        assert_eq!((vector.dx, vector.dy, vector.cost), (5, -3, 0.0));
+        assert!(vector.points_evaluated < exhaustive_vector.points_evaluated / 10);
+    }
+
+    #[test]
+    fn stays_inside_the_search_range() {
+        let vector = match_shifted_block(
+            &DiamondSearchBlockMatcher::new(2),
+            (6, 0),
+            &SearchContext::default(),
+        );
+
+        assert!((-2..2).contains(&vector.dx) && (-2..2).contains(&vector.dy));
+    }
+}
@@ -1,84 +1,131 @@
-use image::{DynamicImage, GenericImageView};
 use itertools::Itertools;
 use log::debug;
 
-use crate::{utils::calculate_block_prediction_error, ExtractedBlock};
+use crate::{
+    metrics::{sad::MadMetric, DistortionMetric},
+    plane::LumaPlane,
+    rate::RateConstraint,
+    types::{MotionVector, SearchContext},
+    utils::calculate_candidate_error,
+    ExtractedBlock,
+};
 
 use super::BlockMatcher;
 
 pub struct ExhaustiveBlockMatcher {
     search_region_size: i32,
+    metric: Box<dyn DistortionMetric>,
+    lambda: f64,
 }
 
 impl ExhaustiveBlockMatcher {
     pub fn new(search_region_size: u16) -> Self {
         Self {
             search_region_size: search_region_size as i32,
+            metric: Box::new(MadMetric::new()),
+            lambda: 0.0,
         }
     }
+
+    pub fn with_metric(self, metric: Box<dyn DistortionMetric>) -> Self {
+        Self { metric, ..self }
+    }
+
+    pub fn with_lambda(self, lambda: f64) -> Self {
+        Self { lambda, ..self }
+    }
 }
 
 impl BlockMatcher for ExhaustiveBlockMatcher {
-    fn match_block(&self, block: &ExtractedBlock, frame: &DynamicImage) -> ExtractedBlock {
-        let mb_size = block.pixels.width();
-        let anchor_block = &block.pixels;
-
-        let prediction_offsets = (-self.search_region_size..self.search_region_size)
-            .cartesian_product(-self.search_region_size..self.search_region_size);
-
-        let predicted_block = prediction_offsets
-            .map(|(x_offset, y_offset)| {
-                extract_block_from_offset(block, x_offset, y_offset, frame, mb_size)
-            })
-            .filter(Option::is_some)
-            .map(Option::unwrap)
-            .map(|extracted_block| {
-                let error = calculate_block_prediction_error(anchor_block, &extracted_block.pixels);
-                (extracted_block, error)
-            })
-            .min_by(|(_, first_error), (_, second_error)| {
-                first_error
-                    .partial_cmp(second_error)
-                    .expect("Comparing NaN errors")
-            })
-            .map(|(predicted_block, _)| predicted_block)
-            .unwrap();
-
-        debug!(
-            "Prediction: ({}, {})",
-            predicted_block.x_offset, predicted_block.y_offset
-        );
-
-        predicted_block
+    fn match_block(
+        &self,
+        block: &ExtractedBlock,
+        frame: &LumaPlane,
+        context: &SearchContext,
+    ) -> MotionVector {
+        exhaustive_search(
+            block,
+            frame,
+            self.search_region_size,
+            self.metric.as_ref(),
+            &RateConstraint::new(self.lambda, context),
+        )
     }
 }
 
-fn extract_block_from_offset(
+// Candidates are visited column by column, the first one with the lowest cost wins
+pub(super) fn search_offsets(search_region_size: i32) -> impl Iterator<Item = (i32, i32)> {
+    (-search_region_size..search_region_size)
+        .cartesian_product(-search_region_size..search_region_size)
+}
+
+pub(super) fn exhaustive_search(
     block: &ExtractedBlock,
-    block_x_offset: i32,
-    block_y_offset: i32,
-    frame: &DynamicImage,
-    mb_size: u32,
-) -> Option<ExtractedBlock> {
-    let x_offset = block.x_offset as i32 + block_x_offset;
-    let y_offset = block.y_offset as i32 + block_y_offset;
-    if is_pos_in_frame(x_offset, y_offset, frame) {
-        None
-    } else {
-        let x_offset = x_offset as u32;
-        let y_offset = y_offset as u32;
-
-        Some(ExtractedBlock {
-            x_offset,
-            y_offset,
-            pixels: frame.crop_imm(x_offset, y_offset, mb_size, mb_size),
+    frame: &LumaPlane,
+    search_region_size: i32,
+    metric: &dyn DistortionMetric,
+    rate: &RateConstraint,
+) -> MotionVector {
+    let mut points_evaluated = 0;
+
+    let (dx, dy, cost, _) = search_offsets(search_region_size)
+        .filter_map(|offset| {
+            calculate_candidate_error(block, frame, offset, metric)
+                .map(|error| (offset.0, offset.1, error, rate.cost(offset, error)))
+        })
+        .inspect(|_| points_evaluated += 1)
+        .min_by(|(_, _, _, first_cost), (_, _, _, second_cost)| {
+            first_cost
+                .partial_cmp(second_cost)
+                .expect("Comparing NaN errors")
         })
+        .unwrap();
+
+    debug!("Prediction: ({}, {})", dx, dy);
+
+    MotionVector {
+        dx,
+        dy,
+        cost,
+        points_evaluated,
+        points_pruned: 0,
     }
 }
 
-fn is_pos_in_frame(x: i32, y: i32, frame: &DynamicImage) -> bool {
-    x < 0
-        || x >= frame.width() as i32
-        || y < 0
-        || y >= frame.height() as i32
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_utils::shifted_pair, utils::tile_frame};
+
+    #[test]
+    fn finds_the_shift_and_reports_its_cost() {
+        let (anchor_frame, target_frame) = shifted_pair(64, 64, (3, -2));
+        let block = &tile_frame(&anchor_frame, 16)[5];
+        let target_frame = target_frame.to_luma8();
+
+        let vector = ExhaustiveBlockMatcher::new(7).match_block(
+            block,
+            &LumaPlane::from_image(&target_frame),
+            &SearchContext::default(),
+        );
+
+        assert_eq!((vector.dx, vector.dy, vector.cost), (3, -2, 0.0));
+        assert_eq!(vector.points_evaluated, 14 * 14);
+    }
+
+    #[test]
+    fn candidates_outside_the_frame_are_skipped() {
+        let (anchor_frame, target_frame) = shifted_pair(32, 32, (0, 0));
+        let block = &tile_frame(&anchor_frame, 16)[0];
+        let target_frame = target_frame.to_luma8();
+
+        let vector = ExhaustiveBlockMatcher::new(4).match_block(
+            block,
+            &LumaPlane::from_image(&target_frame),
+            &SearchContext::default(),
+        );
+
+        assert_eq!((vector.dx, vector.dy, vector.cost), (0, 0, 0.0));
+        assert_eq!(vector.points_evaluated, 4 * 4);
+    }
 }
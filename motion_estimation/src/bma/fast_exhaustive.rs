@@ -0,0 +1,390 @@
+use log::debug;
+
+use crate::{
+    metrics::{
+        kernels::{sad, sad_within},
+        sad::MadMetric,
+        DistortionMetric,
+    },
+    plane::{luma_frame, LumaPlane},
+    rate::RateConstraint,
+    types::{MotionVector, SearchContext},
+    utils::calculate_candidate_error,
+    ExtractedBlock,
+};
+
+use super::{
+    exhaustive::{exhaustive_search, search_offsets},
+    BlockMatcher,
+};
+
+// Finds the same vectors as the exhaustive search while skipping most of the distortion
+// computations. Candidates are pruned with the successive elimination bound
+// |sum(block) - sum(candidate)| <= SAD and abandoned mid-block by partial distortion
+// elimination, so the pruning only applies to SAD based metrics.
+pub struct FastExhaustiveBlockMatcher {
+    search_region_size: i32,
+    metric: Box<dyn DistortionMetric>,
+    lambda: f64,
+    zero_motion_threshold: Option<f64>,
+}
+
+impl FastExhaustiveBlockMatcher {
+    pub fn new(search_region_size: u16) -> Self {
+        Self {
+            search_region_size: search_region_size as i32,
+            metric: Box::new(MadMetric::new()),
+            lambda: 0.0,
+            zero_motion_threshold: None,
+        }
+    }
+
+    pub fn with_metric(self, metric: Box<dyn DistortionMetric>) -> Self {
+        Self { metric, ..self }
+    }
+
+    pub fn with_lambda(self, lambda: f64) -> Self {
+        Self { lambda, ..self }
+    }
+
+    // Blocks whose zero vector costs at most the threshold stop there. Unlike the pruning, this
+    // may miss a better vector, so it is disabled by default.
+    pub fn with_zero_motion_threshold(self, zero_motion_threshold: f64) -> Self {
+        Self {
+            zero_motion_threshold: Some(zero_motion_threshold),
+            ..self
+        }
+    }
+
+    fn search_sad(
+        &self,
+        block: &ExtractedBlock,
+        block_pixels: &LumaPlane,
+        frame: &LumaPlane,
+        rate: &RateConstraint,
+    ) -> MotionVector {
+        let (width, height) = block_pixels.dimensions();
+        let area = width * height;
+        let origin = (block.x_offset as i32, block.y_offset as i32);
+        let window = WindowSums::new(frame, origin, (width, height), self.search_region_size);
+        let block_sum: u64 = block_pixels
+            .rows()
+            .flatten()
+            .map(|pixel| *pixel as u64)
+            .sum();
+
+        let candidate_at =
+            |(dx, dy): (i32, i32)| frame.view(origin.0 + dx, origin.1 + dy, width, height);
+        let candidate_cost =
+            |vector, sad| rate.cost(vector, self.metric.cost_from_sad(sad, area).unwrap());
+
+        // Zero motion is evaluated first since it usually gives a tight bound
+        let zero_index =
+            search_offsets(self.search_region_size).position(|vector| vector == (0, 0));
+        let mut best: Option<(usize, (i32, i32), u64, f64)> = zero_index.and_then(|index| {
+            candidate_at((0, 0)).map(|candidate| {
+                let sad = sad(block_pixels, &candidate);
+                (index, (0, 0), sad, candidate_cost((0, 0), sad))
+            })
+        });
+        let (mut points_evaluated, mut points_pruned) = (best.is_some() as u32, 0);
+
+        for (index, vector) in search_offsets(self.search_region_size).enumerate() {
+            if Some(index) == zero_index {
+                continue;
+            }
+            let candidate = match candidate_at(vector) {
+                Some(candidate) => candidate,
+                None => continue,
+            };
+
+            // Earlier candidates win ties in the exhaustive search, later ones must be better
+            let limit = match best {
+                None => Some(u64::MAX),
+                Some((best_index, _, _, best_cost)) => {
+                    self.sad_limit(area, rate.rate_cost(vector), best_cost, index < best_index)
+                }
+            };
+
+            let candidate_sum = window.sum(origin.0 + vector.0, origin.1 + vector.1);
+            let sad = limit
+                .filter(|limit| block_sum.abs_diff(candidate_sum) <= *limit)
+                .and_then(|limit| sad_within(block_pixels, &candidate, limit));
+
+            match sad {
+                Some(sad) => {
+                    points_evaluated += 1;
+                    best = Some((index, vector, sad, candidate_cost(vector, sad)));
+                }
+                None => points_pruned += 1,
+            }
+        }
+
+        let (_, (dx, dy), sad, _) = best.expect("Search window lies outside the target frame");
+        debug!(
+            "Prediction: ({}, {}), {} candidates pruned",
+            dx, dy, points_pruned
+        );
+
+        MotionVector {
+            dx,
+            dy,
+            cost: self.metric.cost_from_sad(sad, area).unwrap(),
+            points_evaluated,
+            points_pruned,
+        }
+    }
+
+    // Largest SAD for which a candidate with the given rate cost still beats the best cost
+    fn sad_limit(&self, area: u32, rate_cost: f64, best_cost: f64, wins_ties: bool) -> Option<u64> {
+        let beats_best = |sad: u64| {
+            let cost = self.metric.cost_from_sad(sad, area).unwrap() + rate_cost;
+            if wins_ties {
+                cost <= best_cost
+            } else {
+                cost < best_cost
+            }
+        };
+
+        // SAD based costs are linear, the estimate only needs correcting for rounding
+        let unit_cost = self.metric.cost_from_sad(1, area).unwrap();
+        let mut limit = ((best_cost - rate_cost) / unit_cost).floor().max(0.0) as u64;
+        while limit > 0 && !beats_best(limit) {
+            limit -= 1;
+        }
+        if !beats_best(limit) {
+            return None;
+        }
+        while beats_best(limit + 1) {
+            limit += 1;
+        }
+
+        Some(limit)
+    }
+}
+
+impl BlockMatcher for FastExhaustiveBlockMatcher {
+    fn match_block(
+        &self,
+        block: &ExtractedBlock,
+        frame: &LumaPlane,
+        context: &SearchContext,
+    ) -> MotionVector {
+        let rate = RateConstraint::new(self.lambda, context);
+        let block_pixels = luma_frame(&block.pixels);
+        let block_pixels = LumaPlane::from_image(&block_pixels);
+        let (width, height) = block_pixels.dimensions();
+
+        if let Some(threshold) = self.zero_motion_threshold {
+            let zero_motion_cost =
+                calculate_candidate_error(block, frame, (0, 0), self.metric.as_ref())
+                    .filter(|cost| *cost <= threshold);
+
+            if let Some(cost) = zero_motion_cost {
+                let candidates = search_offsets(self.search_region_size)
+                    .filter(|(dx, dy)| {
+                        frame
+                            .view(
+                                block.x_offset as i32 + dx,
+                                block.y_offset as i32 + dy,
+                                width,
+                                height,
+                            )
+                            .is_some()
+                    })
+                    .count() as u32;
+
+                return MotionVector {
+                    dx: 0,
+                    dy: 0,
+                    cost,
+                    points_evaluated: 1,
+                    points_pruned: candidates.saturating_sub(1),
+                };
+            }
+        }
+
+        match self.metric.cost_from_sad(0, width * height) {
+            Some(_) => self.search_sad(block, &block_pixels, frame, &rate),
+            None => exhaustive_search(
+                block,
+                frame,
+                self.search_region_size,
+                self.metric.as_ref(),
+                &rate,
+            ),
+        }
+    }
+}
+
+// Integral image of the part of the frame covered by the search window, giving the pixel sum
+// of any candidate block in constant time
+struct WindowSums {
+    x_offset: i32,
+    y_offset: i32,
+    block_size: (u32, u32),
+    stride: usize,
+    sums: Vec<u64>,
+}
+
+impl WindowSums {
+    fn new(
+        frame: &LumaPlane,
+        (x_origin, y_origin): (i32, i32),
+        block_size: (u32, u32),
+        search_region_size: i32,
+    ) -> Self {
+        let x_offset = (x_origin - search_region_size).max(0);
+        let y_offset = (y_origin - search_region_size).max(0);
+        let x_end =
+            (x_origin + search_region_size - 1 + block_size.0 as i32).min(frame.width() as i32);
+        let y_end =
+            (y_origin + search_region_size - 1 + block_size.1 as i32).min(frame.height() as i32);
+        let (width, height) = (
+            (x_end - x_offset).max(0) as usize,
+            (y_end - y_offset).max(0) as usize,
+        );
+
+        let stride = width + 1;
+        let mut sums = vec![0; stride * (height + 1)];
+
+        for y in 0..height {
+            let row = &frame.row(y_offset as u32 + y as u32)
+                [x_offset as usize..x_offset as usize + width];
+            let mut row_sum = 0;
+            for (x, pixel) in row.iter().enumerate() {
+                row_sum += *pixel as u64;
+                sums[(y + 1) * stride + x + 1] = sums[y * stride + x + 1] + row_sum;
+            }
+        }
+
+        Self {
+            x_offset,
+            y_offset,
+            block_size,
+            stride,
+            sums,
+        }
+    }
+
+    // The block at (x, y) must lie inside the window
+    fn sum(&self, x: i32, y: i32) -> u64 {
+        let left = (x - self.x_offset) as usize;
+        let top = (y - self.y_offset) as usize;
+        let (right, bottom) = (
+            left + self.block_size.0 as usize,
+            top + self.block_size.1 as usize,
+        );
+
+        self.sums[bottom * self.stride + right] + self.sums[top * self.stride + left]
+            - self.sums[top * self.stride + right]
+            - self.sums[bottom * self.stride + left]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bma::exhaustive::ExhaustiveBlockMatcher,
+        metrics::sad::SadMetric,
+        motion_field::MotionField,
+        test_utils::{match_shifted_block, shifted_pair, textured_frame},
+        utils::tile_frame,
+    };
+
+    #[test]
+    fn finds_the_exhaustive_search_vectors() {
+        // A fractional shift leaves non-zero costs for the rate to trade against, and partial
+        // border blocks are searched through clipped windows
+        let anchor_frame = textured_frame(72, 56, (0.0, 0.0));
+        let target_frame = textured_frame(72, 56, (2.5, -1.5));
+
+        for metric in ["mad", "sad"] {
+            for lambda in [0.0, 1.0, 40.0] {
+                let build_metric = || -> Box<dyn DistortionMetric> {
+                    match metric {
+                        "mad" => Box::new(MadMetric::new()),
+                        _ => Box::new(SadMetric::new()),
+                    }
+                };
+                let exhaustive = ExhaustiveBlockMatcher::new(6)
+                    .with_metric(build_metric())
+                    .with_lambda(lambda);
+                let fast_exhaustive = FastExhaustiveBlockMatcher::new(6)
+                    .with_metric(build_metric())
+                    .with_lambda(lambda);
+
+                let expected = MotionField::estimate(&anchor_frame, &target_frame, 16, &exhaustive);
+                let actual =
+                    MotionField::estimate(&anchor_frame, &target_frame, 16, &fast_exhaustive);
+
+                for (expected, actual) in expected.blocks.iter().zip(&actual.blocks) {
+                    let (expected, actual) = (&expected.vector, &actual.vector);
+                    assert_eq!(
+                        (actual.dx, actual.dy, actual.cost),
+                        (expected.dx, expected.dy, expected.cost),
+                        "{} metric, lambda {}",
+                        metric,
+                        lambda
+                    );
+                    assert_eq!(
+                        actual.points_evaluated + actual.points_pruned,
+                        expected.points_evaluated
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn prunes_candidates_on_textured_blocks() {
+        let vector = match_shifted_block(
+            &FastExhaustiveBlockMatcher::new(7),
+            (3, -2),
+            &SearchContext::default(),
+        );
+
+        assert_eq!((vector.dx, vector.dy, vector.cost), (3, -2, 0.0));
+        assert_eq!(vector.points_evaluated + vector.points_pruned, 14 * 14);
+        assert!(vector.points_pruned > 0);
+    }
+
+    #[test]
+    fn zero_motion_below_the_threshold_stops_the_search() {
+        let (anchor_frame, target_frame) = shifted_pair(64, 64, (1, 0));
+        let block = &tile_frame(&anchor_frame, 16)[5];
+        let target_frame = target_frame.to_luma8();
+        let target_plane = LumaPlane::from_image(&target_frame);
+
+        let matcher = FastExhaustiveBlockMatcher::new(4).with_zero_motion_threshold(1000.0);
+        let vector = matcher.match_block(block, &target_plane, &SearchContext::default());
+        assert_eq!((vector.dx, vector.dy), (0, 0));
+        assert_eq!(
+            (vector.points_evaluated, vector.points_pruned),
+            (1, 8 * 8 - 1)
+        );
+
+        let matcher = FastExhaustiveBlockMatcher::new(4).with_zero_motion_threshold(0.0);
+        let vector = matcher.match_block(block, &target_plane, &SearchContext::default());
+        assert_eq!((vector.dx, vector.dy, vector.cost), (1, 0, 0.0));
+    }
+
+    #[test]
+    fn an_empty_search_window_prunes_nothing() {
+        let (anchor_frame, target_frame) = shifted_pair(32, 32, (0, 0));
+        let block = &tile_frame(&anchor_frame, 16)[0];
+        let target_frame = target_frame.to_luma8();
+
+        let vector = FastExhaustiveBlockMatcher::new(0)
+            .with_zero_motion_threshold(0.0)
+            .match_block(
+                block,
+                &LumaPlane::from_image(&target_frame),
+                &SearchContext::default(),
+            );
+
+        assert_eq!((vector.dx, vector.dy, vector.cost), (0, 0, 0.0));
+        assert_eq!(vector.points_pruned, 0);
+    }
+}
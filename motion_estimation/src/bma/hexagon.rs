@@ -0,0 +1,96 @@
+use log::debug;
+
+use crate::{
+    metrics::{sad::MadMetric, DistortionMetric},
+    plane::LumaPlane,
+    rate::RateConstraint,
+    types::{MotionVector, SearchContext},
+    ExtractedBlock,
+};
+
+use super::{pattern_search::PatternSearch, BlockMatcher};
+
+const LARGE_HEXAGON: [(i32, i32); 6] = [(-1, -2), (1, -2), (-2, 0), (2, 0), (-1, 2), (1, 2)];
+
+const SMALL_HEXAGON: [(i32, i32); 4] = [(0, -1), (-1, 0), (1, 0), (0, 1)];
+
+pub struct HexagonSearchBlockMatcher {
+    search_region_size: i32,
+    metric: Box<dyn DistortionMetric>,
+    lambda: f64,
+}
+
+impl HexagonSearchBlockMatcher {
+    pub fn new(search_region_size: u16) -> Self {
+        Self {
+            search_region_size: search_region_size as i32,
+            metric: Box::new(MadMetric::new()),
+            lambda: 0.0,
+        }
+    }
+
+    pub fn with_metric(self, metric: Box<dyn DistortionMetric>) -> Self {
+        Self { metric, ..self }
+    }
+
+    pub fn with_lambda(self, lambda: f64) -> Self {
+        Self { lambda, ..self }
+    }
+}
+
+impl BlockMatcher for HexagonSearchBlockMatcher {
+    fn match_block(
+        &self,
+        block: &ExtractedBlock,
+        frame: &LumaPlane,
+        context: &SearchContext,
+    ) -> MotionVector {
+        let mut search = PatternSearch::new(
+            block,
+            frame,
+            self.metric.as_ref(),
+            self.search_region_size,
+            RateConstraint::new(self.lambda, context),
+        );
+
+        let start_cost = search
+            .evaluate((0, 0))
+            .expect("Anchor block lies outside the target frame");
+
+        let coarse = search.descend((0, 0, start_cost), &LARGE_HEXAGON, true);
+        let refined = search.descend(coarse, &SMALL_HEXAGON, false);
+
+        debug!("Prediction: ({}, {})", refined.0, refined.1);
+
+        search.into_motion_vector(refined)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{bma::exhaustive::ExhaustiveBlockMatcher, test_utils::match_shifted_block};
+
+    // The small hexagon is only applied once, so the shift is reachable by large steps
+    #[test]
+    fn descends_to_the_shift_with_fewer_points_than_exhaustive() {
+        let context = SearchContext::default();
+        let vector = match_shifted_block(&HexagonSearchBlockMatcher::new(16), (6, -4), &context);
+        let exhaustive_vector =
+            match_shifted_block(&ExhaustiveBlockMatcher::new(16), (6, -4), &context);
+
+        assert_eq!((vector.dx, vector.dy, vector.cost), (6, -4, 0.0));
+        assert!(vector.points_evaluated < exhaustive_vector.points_evaluated / 10);
+    }
+
+    #[test]
+    fn stays_inside_the_search_range() {
+        let vector = match_shifted_block(
+            &HexagonSearchBlockMatcher::new(2),
+            (6, 0),
+            &SearchContext::default(),
+        );
+
+        assert!((-2..2).contains(&vector.dx) && (-2..2).contains(&vector.dy));
+    }
+}
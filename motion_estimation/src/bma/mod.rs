@@ -1,12 +1,24 @@
-use image::{DynamicImage};
-
-use crate::ExtractedBlock;
+use crate::{
+    plane::LumaPlane,
+    types::{MotionVector, SearchContext},
+    ExtractedBlock,
+};
 
 pub mod naive;
 pub mod exhaustive;
+pub mod fast_exhaustive;
 pub mod three_step;
+pub mod diamond;
+pub mod hexagon;
+pub mod arps;
 
-pub trait BlockMatcher {
-    fn match_block(&self, block: &ExtractedBlock, frame: &DynamicImage) -> ExtractedBlock;
-}
+mod pattern_search;
 
+pub trait BlockMatcher: Send + Sync {
+    fn match_block(
+        &self,
+        block: &ExtractedBlock,
+        frame: &LumaPlane,
+        context: &SearchContext,
+    ) -> MotionVector;
+}
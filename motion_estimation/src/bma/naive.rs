@@ -1,26 +1,51 @@
-use image::{DynamicImage, GenericImageView};
-
-use crate::ExtractedBlock;
+use crate::{
+    metrics::{sad::MadMetric, DistortionMetric},
+    plane::LumaPlane,
+    types::{MotionVector, SearchContext},
+    utils::calculate_candidate_error,
+    ExtractedBlock,
+};
 
 use super::BlockMatcher;
 
-pub struct NaiveBlockMatcher {}
+pub struct NaiveBlockMatcher {
+    metric: Box<dyn DistortionMetric>,
+}
 
 impl NaiveBlockMatcher {
     pub fn new() -> Self {
-        Self { }
+        Self {
+            metric: Box::new(MadMetric::new()),
+        }
+    }
+
+    pub fn with_metric(self, metric: Box<dyn DistortionMetric>) -> Self {
+        Self { metric }
+    }
+}
+
+impl Default for NaiveBlockMatcher {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 impl BlockMatcher for NaiveBlockMatcher {
-    fn match_block(&self, block: &ExtractedBlock, frame: &DynamicImage) -> ExtractedBlock {
-        let mb_size = block.pixels.width();
-        let predicted_pixels = frame.crop_imm(block.x_offset, block.y_offset, mb_size, mb_size);
+    fn match_block(
+        &self,
+        block: &ExtractedBlock,
+        frame: &LumaPlane,
+        _context: &SearchContext,
+    ) -> MotionVector {
+        let cost = calculate_candidate_error(block, frame, (0, 0), self.metric.as_ref())
+            .expect("Anchor block lies outside the target frame");
 
-        ExtractedBlock {
-            x_offset: 0,
-            y_offset: 0,
-            pixels: predicted_pixels,
+        MotionVector {
+            dx: 0,
+            dy: 0,
+            cost,
+            points_evaluated: 1,
+            points_pruned: 0,
         }
     }
 }
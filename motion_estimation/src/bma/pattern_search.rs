@@ -0,0 +1,92 @@
+use std::collections::HashMap;
+
+use crate::{
+    metrics::DistortionMetric, plane::LumaPlane, rate::RateConstraint, types::MotionVector,
+    utils::calculate_candidate_error, ExtractedBlock,
+};
+
+pub struct PatternSearch<'a> {
+    block: &'a ExtractedBlock,
+    frame: &'a LumaPlane<'a>,
+    metric: &'a dyn DistortionMetric,
+    search_region_size: i32,
+    rate: RateConstraint,
+    evaluated_points: HashMap<(i32, i32), Option<f64>>,
+}
+
+impl<'a> PatternSearch<'a> {
+    pub fn new(
+        block: &'a ExtractedBlock,
+        frame: &'a LumaPlane<'a>,
+        metric: &'a dyn DistortionMetric,
+        search_region_size: i32,
+        rate: RateConstraint,
+    ) -> Self {
+        Self {
+            block,
+            frame,
+            metric,
+            search_region_size,
+            rate,
+            evaluated_points: HashMap::new(),
+        }
+    }
+
+    // Returns the rate-constrained cost, the distortion alone is kept for the motion vector
+    pub fn evaluate(&mut self, vector: (i32, i32)) -> Option<f64> {
+        let range = -self.search_region_size..self.search_region_size;
+        if !range.contains(&vector.0) || !range.contains(&vector.1) {
+            return None;
+        }
+
+        let (block, frame, metric) = (self.block, self.frame, self.metric);
+
+        let distortion = *self
+            .evaluated_points
+            .entry(vector)
+            .or_insert_with(|| calculate_candidate_error(block, frame, vector, metric));
+
+        distortion.map(|error| self.rate.cost(vector, error))
+    }
+
+    // Moves to the best point of the pattern until the centre is the best one
+    pub fn descend(
+        &mut self,
+        start: (i32, i32, f64),
+        pattern: &[(i32, i32)],
+        repeat: bool,
+    ) -> (i32, i32, f64) {
+        let mut best = start;
+
+        loop {
+            let centre = best;
+
+            for (x_offset, y_offset) in pattern {
+                let vector = (centre.0 + x_offset, centre.1 + y_offset);
+                if let Some(cost) = self.evaluate(vector) {
+                    if cost < best.2 {
+                        best = (vector.0, vector.1, cost);
+                    }
+                }
+            }
+
+            if !repeat || (best.0, best.1) == (centre.0, centre.1) {
+                return best;
+            }
+        }
+    }
+
+    pub fn into_motion_vector(self, best: (i32, i32, f64)) -> MotionVector {
+        MotionVector {
+            dx: best.0,
+            dy: best.1,
+            cost: self.evaluated_points[&(best.0, best.1)].unwrap(),
+            points_evaluated: self
+                .evaluated_points
+                .values()
+                .filter(|error| error.is_some())
+                .count() as u32,
+            points_pruned: 0,
+        }
+    }
+}
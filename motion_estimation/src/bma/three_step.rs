@@ -1,30 +1,53 @@
-use image::{DynamicImage, GenericImageView};
 use log::debug;
 
-use crate::{utils::calculate_block_prediction_error, ExtractedBlock};
+use crate::{
+    metrics::{sad::MadMetric, DistortionMetric},
+    plane::LumaPlane,
+    rate::RateConstraint,
+    types::{MotionVector, SearchContext},
+    utils::calculate_candidate_error,
+    ExtractedBlock,
+};
 
 use super::BlockMatcher;
 
 pub struct ThreeStepBlockMatcher {
     search_region_size: i32,
+    metric: Box<dyn DistortionMetric>,
+    lambda: f64,
 }
 
 impl ThreeStepBlockMatcher {
     pub fn new(search_region_size: u16) -> Self {
         Self {
             search_region_size: search_region_size as i32,
+            metric: Box::new(MadMetric::new()),
+            lambda: 0.0,
         }
     }
-}
 
-impl BlockMatcher for ThreeStepBlockMatcher {
-    fn match_block(&self, block: &ExtractedBlock, frame: &DynamicImage) -> ExtractedBlock {
-        let mb_size = block.pixels.width();
+    pub fn with_metric(self, metric: Box<dyn DistortionMetric>) -> Self {
+        Self { metric, ..self }
+    }
 
-        let mut r = self.search_region_size as i32;
-        let mut anchor_pos = (block.x_offset as i32, block.y_offset as i32);
+    pub fn with_lambda(self, lambda: f64) -> Self {
+        Self { lambda, ..self }
+    }
+}
 
-        let predicted_block = loop {
+impl BlockMatcher for ThreeStepBlockMatcher {
+    fn match_block(
+        &self,
+        block: &ExtractedBlock,
+        frame: &LumaPlane,
+        context: &SearchContext,
+    ) -> MotionVector {
+        let rate = RateConstraint::new(self.lambda, context);
+        let mut r = self.search_region_size;
+        let mut anchor_vector = (0, 0);
+        let mut points_evaluated = 0;
+
+        let predicted_vector = loop {
             let prediction_offsets = vec![
                 (-r, -r),
                 (0, -r),
@@ -37,84 +60,65 @@ impl BlockMatcher for ThreeStepBlockMatcher {
                 (r, r),
             ];
 
-            let new_anchor =
-                get_best_prediction_in_offsets(&block, &frame, anchor_pos, prediction_offsets, mb_size);
+            let new_anchor = get_best_prediction_in_offsets(
+                block,
+                frame,
+                anchor_vector,
+                prediction_offsets,
+                self.metric.as_ref(),
+                &rate,
+                &mut points_evaluated,
+            );
 
             r /= 2;
 
             if r <= 1 {
                 break new_anchor;
             } else {
-                anchor_pos = (new_anchor.x_offset as i32, new_anchor.y_offset as i32)
+                anchor_vector = (new_anchor.0, new_anchor.1)
             }
         };
 
-        debug!(
-            "Prediction: ({}, {})",
-            predicted_block.x_offset, predicted_block.y_offset
-        );
+        let (dx, dy, cost) = predicted_vector;
+
+        debug!("Prediction: ({}, {})", dx, dy);
 
-        predicted_block
+        MotionVector {
+            dx,
+            dy,
+            cost,
+            points_evaluated,
+            points_pruned: 0,
+        }
     }
 }
 
 fn get_best_prediction_in_offsets(
     block: &ExtractedBlock,
-    frame: &DynamicImage,
-    anchor_pos: (i32, i32),
+    frame: &LumaPlane,
+    anchor_vector: (i32, i32),
     prediction_offsets: Vec<(i32, i32)>,
-    mb_size: u32,
-) -> ExtractedBlock {
-    let anchor_block = &block.pixels;
-
-    let predicted_block = prediction_offsets
+    metric: &dyn DistortionMetric,
+    rate: &RateConstraint,
+    points_evaluated: &mut u32,
+) -> (i32, i32, f64) {
+    prediction_offsets
         .iter()
-        .map(|(x_offset, y_offset)| {
+        .filter_map(|(x_offset, y_offset)| {
             debug!(
-                "Extracting block from offset ({}, {})...",
+                "Evaluating candidate at offset ({}, {})...",
                 x_offset, y_offset
             );
-            extract_block_from_offset(anchor_pos, (*x_offset, *y_offset), frame, mb_size)
+            let vector = (anchor_vector.0 + x_offset, anchor_vector.1 + y_offset);
+            calculate_candidate_error(block, frame, vector, metric)
+                .map(|error| (vector.0, vector.1, error, rate.cost(vector, error)))
         })
-        .filter(Option::is_some)
-        .map(Option::unwrap)
-        .map(|extracted_block| {
-            let error = calculate_block_prediction_error(anchor_block, &extracted_block.pixels);
-            (extracted_block, error)
-        })
-        .min_by(|(_, first_error), (_, second_error)| {
-            first_error
-                .partial_cmp(second_error)
+        .inspect(|_| *points_evaluated += 1)
+        .min_by(|(_, _, _, first_cost), (_, _, _, second_cost)| {
+            first_cost
+                .partial_cmp(second_cost)
                 .expect("Comparing NaN errors")
         })
-        .map(|(predicted_block, _)| predicted_block)
-        .unwrap();
-
-    predicted_block
-}
-
-fn extract_block_from_offset(
-    anchor_pos: (i32, i32),
-    block_offset: (i32, i32),
-    frame: &DynamicImage,
-    mb_size: u32,
-) -> Option<ExtractedBlock> {
-    let (x_offset, y_offset) = (anchor_pos.0 + block_offset.0, anchor_pos.1 + block_offset.1);
-
-    if is_pos_in_frame(x_offset, y_offset, frame) {
-        None
-    } else {
-        let x_offset = x_offset as u32;
-        let y_offset = y_offset as u32;
-
-        Some(ExtractedBlock {
-            x_offset,
-            y_offset,
-            pixels: frame.crop_imm(x_offset, y_offset, mb_size, mb_size),
-        })
-    }
-}
-
-fn is_pos_in_frame(x: i32, y: i32, frame: &DynamicImage) -> bool {
-    x < 0 || x >= frame.width() as i32 || y < 0 || y >= frame.height() as i32
+        .map(|(dx, dy, error, _)| (dx, dy, error))
+        .unwrap()
 }
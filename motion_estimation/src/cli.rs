@@ -0,0 +1,494 @@
+use std::path::PathBuf;
+
+use clap::{
+    builder::RangedU64ValueParser, Args, CommandFactory, ErrorKind, Parser, Subcommand, ValueEnum,
+};
+use motion_estimation::{
+    formats::ExportFormat,
+    pairing::FramePairing,
+    registry,
+    source::{raw_yuv::RawYuvFormat, ChromaSubsampling},
+    subpel::{interpolation::InterpolationFilter, SubpixelPrecision},
+};
+
+#[derive(Parser)]
+#[clap(
+    name = "motion_estimation",
+    about = "Block-based motion estimation experiments"
+)]
+pub struct Cli {
+    /// Number of worker threads, all available cores if omitted
+    #[clap(short = 'j', long, global = true)]
+    pub threads: Option<usize>,
+
+    #[clap(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Estimate motion fields between frame pairs
+    Estimate(EstimateArgs),
+    /// Reconstruct frames from their motion fields and export residuals
+    Compensate(CompensateArgs),
+    /// Compare matchers and metrics on the same frame pairs
+    Compare(CompareArgs),
+    /// Estimate dense optical flow between frame pairs
+    Flow(FlowArgs),
+    /// Estimate motion against several past frames and, for B-frames, the next frame
+    References(ReferencesArgs),
+}
+
+#[derive(Args)]
+pub struct FrameArgs {
+    /// Image sequence directory, Y4M file or raw YUV file
+    #[clap(short, long, default_value = "assets/meatthezoo_frames")]
+    pub input: PathBuf,
+
+    /// Frame size of raw YUV input, as WIDTHxHEIGHT
+    #[clap(long, value_parser = parse_frame_size)]
+    pub raw_size: Option<(u32, u32)>,
+
+    /// Chroma subsampling of raw YUV input: 420, 444 or mono
+    #[clap(long, default_value = "420", value_parser)]
+    pub raw_subsampling: ChromaSubsampling,
+
+    /// Bit depth of raw YUV input
+    #[clap(long, default_value_t = 8)]
+    pub raw_bit_depth: u8,
+
+    /// Macroblock size in pixels
+    #[clap(short, long, default_value_t = 16, value_parser = clap::value_parser!(u32).range(1..))]
+    pub block_size: u32,
+
+    /// Search range in pixels around each block
+    #[clap(short, long, default_value_t = 25, value_parser = clap::value_parser!(u16).range(1..))]
+    pub search_range: u16,
+
+    /// Zero-motion cost at or below which fast_exhaustive skips the search, never if omitted
+    #[clap(long)]
+    pub zero_motion_threshold: Option<f64>,
+
+    /// Frame pairing strategy: consecutive, first or all, references pick their own frames
+    #[clap(short, long, default_value = "consecutive", value_parser)]
+    pub pairing: FramePairing,
+
+    /// Directory written to by exports
+    #[clap(short, long, default_value = "output")]
+    pub output: PathBuf,
+}
+
+impl FrameArgs {
+    pub fn raw_format(&self) -> Option<RawYuvFormat> {
+        self.raw_size.map(|(width, height)| RawYuvFormat {
+            width,
+            height,
+            subsampling: self.raw_subsampling,
+            bit_depth: self.raw_bit_depth,
+        })
+    }
+}
+
+#[derive(Args)]
+pub struct EstimateArgs {
+    #[clap(flatten)]
+    pub frames: FrameArgs,
+
+    /// Block matcher name
+    #[clap(short, long, default_value = "diamond", value_parser = parse_matcher_name)]
+    pub matcher: String,
+
+    /// Distortion metric name
+    #[clap(long, default_value = "mad", value_parser = parse_metric_name)]
+    pub metric: String,
+
+    /// Position of a single block, as x,y, whose predictions are exported
+    #[clap(long, value_parser = parse_block_position)]
+    pub block_position: Option<(u32, u32)>,
+
+    /// Number of pyramid levels, more than one enables hierarchical estimation, 1 if omitted
+    #[clap(
+        long,
+        value_parser = RangedU64ValueParser::<usize>::new().range(1..),
+        conflicts_with_all = &["block-position", "subpel", "visualise"]
+    )]
+    pub levels: Option<usize>,
+
+    /// Comma-separated search ranges of the hierarchical refinement, from the finest level up
+    /// to the one below the coarsest, 2 pixels at every level if omitted
+    #[clap(long, use_value_delimiter = true)]
+    pub refinement_ranges: Vec<u16>,
+
+    /// Smallest quadtree partition, enables variable block-size estimation. At least 4 pixels
+    /// and the block size divided by a power of two
+    #[clap(
+        long,
+        value_parser = clap::value_parser!(u32).range(4..),
+        conflicts_with_all = &["levels", "refinement-ranges", "subpel", "export"]
+    )]
+    pub min_block_size: Option<u32>,
+
+    /// Lagrangian multiplier weighting motion vector bits against distortion, 0 for block
+    /// matchers and 4 for quadtree partitioning if omitted
+    #[clap(long)]
+    pub lambda: Option<f64>,
+
+    /// Subpixel refinement precision: half or quarter
+    #[clap(long, value_parser)]
+    pub subpel: Option<SubpixelPrecision>,
+
+    /// Subpixel interpolation filter: bilinear, h264 or hevc
+    #[clap(long, default_value = "h264", value_parser)]
+    pub filter: InterpolationFilter,
+
+    /// Export vector overlays and colour-coded flow images
+    #[clap(long)]
+    pub visualise: bool,
+
+    /// Also assemble the visualisations into animated GIFs
+    #[clap(long, requires = "visualise")]
+    pub animate: bool,
+
+    /// Delay between animation frames in milliseconds
+    #[clap(long, default_value_t = 200)]
+    pub frame_delay: u32,
+
+    /// Comma-separated formats the motion fields are saved in: json, csv or flo
+    #[clap(long, use_value_delimiter = true, value_parser)]
+    pub export: Vec<ExportFormat>,
+}
+
+impl EstimateArgs {
+    pub fn pyramid_levels(&self) -> usize {
+        self.levels.unwrap_or(1)
+    }
+
+    // Checks between arguments that clap cannot express
+    pub fn validate(&self) -> Result<(), clap::Error> {
+        let levels = self.pyramid_levels();
+        if !self.refinement_ranges.is_empty() && self.refinement_ranges.len() != levels - 1 {
+            return Err(Cli::command().error(
+                ErrorKind::WrongNumberOfValues,
+                format!(
+                    "--refinement-ranges needs one range for each of the {} levels below the \
+                     coarsest, {} given",
+                    levels - 1,
+                    self.refinement_ranges.len()
+                ),
+            ));
+        }
+
+        if let Some(min_block_size) = self.min_block_size {
+            let block_size = self.frames.block_size;
+            if !block_size.is_multiple_of(min_block_size)
+                || !(block_size / min_block_size).is_power_of_two()
+            {
+                return Err(Cli::command().error(
+                    ErrorKind::ValueValidation,
+                    format!(
+                        "--min-block-size {} does not split {} pixel blocks by a power of two",
+                        min_block_size, block_size
+                    ),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Args)]
+pub struct CompensateArgs {
+    #[clap(flatten)]
+    pub frames: FrameArgs,
+
+    /// Block matcher name
+    #[clap(short, long, default_value = "diamond", value_parser = parse_matcher_name)]
+    pub matcher: String,
+
+    /// Distortion metric name
+    #[clap(long, default_value = "mad", value_parser = parse_metric_name)]
+    pub metric: String,
+
+    /// Lagrangian multiplier weighting motion vector bits against distortion
+    #[clap(long, default_value_t = 0.0)]
+    pub lambda: f64,
+
+    /// Subpixel refinement precision of the predictions: half or quarter
+    #[clap(long, value_parser, conflicts_with = "motion")]
+    pub subpel: Option<SubpixelPrecision>,
+
+    /// Subpixel interpolation filter: bilinear, h264 or hevc
+    #[clap(long, default_value = "h264", value_parser)]
+    pub filter: InterpolationFilter,
+
+    /// Amplification applied to exported residuals
+    #[clap(long, default_value_t = 4.0)]
+    pub gain: f64,
+
+    /// Exported JSON or CSV motion fields, or a folder of .flo files, used instead of
+    /// estimating the motion again
+    #[clap(long)]
+    pub motion: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct CompareArgs {
+    #[clap(flatten)]
+    pub frames: FrameArgs,
+
+    /// Comma-separated block matcher names, all registered matchers if neither matchers nor
+    /// motion fields are given
+    #[clap(short, long, use_value_delimiter = true, value_parser = parse_matcher_name)]
+    pub matchers: Vec<String>,
+
+    /// Comma-separated exported motion fields, as JSON or CSV files or .flo folders
+    #[clap(long, use_value_delimiter = true)]
+    pub motion: Vec<PathBuf>,
+
+    /// Comma-separated distortion metric names
+    #[clap(long, default_value = "mad", use_value_delimiter = true, value_parser = parse_metric_name)]
+    pub metrics: Vec<String>,
+
+    /// Lagrangian multiplier weighting motion vector bits against distortion
+    #[clap(long, default_value_t = 0.0)]
+    pub lambda: f64,
+}
+
+#[derive(Args)]
+pub struct FlowArgs {
+    #[clap(flatten)]
+    pub frames: FrameArgs,
+
+    /// Dense flow method: lucas-kanade or horn-schunck
+    #[clap(long, value_enum, default_value = "lucas-kanade")]
+    pub method: FlowMethod,
+
+    /// Lucas-Kanade integration window size in pixels
+    #[clap(long, default_value_t = 9)]
+    pub window_size: u32,
+
+    /// Number of pyramid levels
+    #[clap(long, default_value_t = 3)]
+    pub levels: usize,
+
+    /// Iterations per pyramid level, 5 for Lucas-Kanade and 100 for Horn-Schunck if omitted
+    #[clap(long)]
+    pub iterations: Option<usize>,
+
+    /// Horn-Schunck smoothness weight
+    #[clap(long, default_value_t = 15.0)]
+    pub alpha: f32,
+
+    /// Export vector overlays and colour-coded flow images
+    #[clap(long)]
+    pub visualise: bool,
+
+    /// Also assemble the visualisations into animated GIFs
+    #[clap(long, requires = "visualise")]
+    pub animate: bool,
+
+    /// Delay between animation frames in milliseconds
+    #[clap(long, default_value_t = 200)]
+    pub frame_delay: u32,
+
+    /// Save the dense fields as .flo files
+    #[clap(long)]
+    pub export_flo: bool,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum FlowMethod {
+    LucasKanade,
+    HornSchunck,
+}
+
+#[derive(Args)]
+pub struct ReferencesArgs {
+    #[clap(flatten)]
+    pub frames: FrameArgs,
+
+    /// Block matcher name
+    #[clap(short, long, default_value = "diamond", value_parser = parse_matcher_name)]
+    pub matcher: String,
+
+    /// Distortion metric name
+    #[clap(long, default_value = "mad", value_parser = parse_metric_name)]
+    pub metric: String,
+
+    /// Lagrangian multiplier weighting motion vector bits against distortion, for both the
+    /// searches and the choice of reference and prediction mode
+    #[clap(long, default_value_t = 0.0)]
+    pub lambda: f64,
+
+    /// Number of previous frames searched as references
+    #[clap(long, default_value_t = 1, value_parser = parse_reference_count)]
+    pub references: usize,
+
+    /// Also search the next frame and the average of both directions, as for B-frames
+    #[clap(long)]
+    pub bidirectional: bool,
+}
+
+fn parse_matcher_name(name: &str) -> Result<String, String> {
+    let names = registry::matcher_names();
+    if names.contains(&name) {
+        Ok(name.to_owned())
+    } else {
+        Err(format!("expected one of: {}", names.join(", ")))
+    }
+}
+
+fn parse_metric_name(name: &str) -> Result<String, String> {
+    let names = registry::metric_names();
+    if names.contains(&name) {
+        Ok(name.to_owned())
+    } else {
+        Err(format!("expected one of: {}", names.join(", ")))
+    }
+}
+
+fn parse_block_position(position: &str) -> Result<(u32, u32), String> {
+    let (x, y) = position
+        .split_once(',')
+        .ok_or_else(|| "expected a position formatted as x,y".to_owned())?;
+
+    Ok((
+        x.trim()
+            .parse()
+            .map_err(|_| format!("invalid x offset '{}'", x))?,
+        y.trim()
+            .parse()
+            .map_err(|_| format!("invalid y offset '{}'", y))?,
+    ))
+}
+
+fn parse_reference_count(count: &str) -> Result<usize, String> {
+    match count.parse() {
+        Ok(0) => Err("at least one reference frame is required".to_owned()),
+        Ok(count) => Ok(count),
+        Err(_) => Err(format!("invalid reference count '{}'", count)),
+    }
+}
+
+fn parse_frame_size(size: &str) -> Result<(u32, u32), String> {
+    let (width, height) = size
+        .split_once('x')
+        .ok_or_else(|| "expected a size formatted as WIDTHxHEIGHT".to_owned())?;
+
+    Ok((
+        width
+            .parse()
+            .map_err(|_| format!("invalid width '{}'", width))?,
+        height
+            .parse()
+            .map_err(|_| format!("invalid height '{}'", height))?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_estimate(args: &[&str]) -> Result<EstimateArgs, clap::Error> {
+        let cli = Cli::try_parse_from(["motion_estimation", "estimate"].iter().chain(args))?;
+        match cli.command {
+            Command::Estimate(args) => args.validate().map(|_| args),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn refinement_ranges_match_the_levels() {
+        let args = parse_estimate(&["--levels", "3", "--refinement-ranges", "4,2"]).unwrap();
+        assert_eq!(args.refinement_ranges, vec![4, 2]);
+
+        let args = parse_estimate(&["--levels", "3"]).unwrap();
+        assert!(args.refinement_ranges.is_empty());
+    }
+
+    #[test]
+    fn refinement_ranges_of_the_wrong_length_are_rejected() {
+        let error = parse_estimate(&["--levels", "3", "--refinement-ranges", "2"])
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), ErrorKind::WrongNumberOfValues);
+
+        assert!(parse_estimate(&["--refinement-ranges", "2"]).is_err());
+    }
+
+    #[test]
+    fn quadtree_partitioning_conflicts_with_pyramids() {
+        assert!(parse_estimate(&["--min-block-size", "8"]).is_ok());
+        assert!(parse_estimate(&["--min-block-size", "8", "--levels", "2"]).is_err());
+    }
+
+    #[test]
+    fn quadtree_leaves_split_the_blocks_by_powers_of_two() {
+        assert!(parse_estimate(&["-b", "16", "--min-block-size", "4"]).is_ok());
+        assert!(parse_estimate(&["-b", "16", "--min-block-size", "16"]).is_ok());
+        assert!(parse_estimate(&["--min-block-size", "0"]).is_err());
+
+        for args in [
+            &["-b", "16", "--min-block-size", "32"],
+            &["-b", "16", "--min-block-size", "12"],
+            &["-b", "24", "--min-block-size", "8"],
+        ] {
+            let error = parse_estimate(args).err().unwrap();
+            assert_eq!(error.kind(), ErrorKind::ValueValidation, "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn search_ranges_cover_at_least_one_pixel() {
+        assert_eq!(parse_estimate(&["-s", "1"]).unwrap().frames.search_range, 1);
+        assert!(parse_estimate(&["-s", "0"]).is_err());
+    }
+
+    #[test]
+    fn pyramids_need_a_level() {
+        assert!(parse_estimate(&["--levels", "0"]).is_err());
+    }
+
+    #[test]
+    fn blocks_cover_at_least_one_pixel() {
+        assert_eq!(parse_estimate(&["-b", "8"]).unwrap().frames.block_size, 8);
+        assert!(parse_estimate(&["-b", "0"]).is_err());
+    }
+
+    #[test]
+    fn subpixel_compensation_needs_estimated_motion() {
+        let parse_compensate = |args: &[&str]| {
+            Cli::try_parse_from(["motion_estimation", "compensate"].iter().chain(args)).map(|cli| {
+                match cli.command {
+                    Command::Compensate(args) => args,
+                    _ => unreachable!(),
+                }
+            })
+        };
+
+        let args = parse_compensate(&["--subpel", "quarter"]).unwrap();
+        assert_eq!(args.subpel, Some(SubpixelPrecision::Quarter));
+        assert_eq!(
+            parse_compensate(&["--subpel", "half", "--motion", "motion.json"])
+                .err()
+                .unwrap()
+                .kind(),
+            ErrorKind::ArgumentConflict
+        );
+    }
+
+    #[test]
+    fn pyramids_conflict_with_flat_estimation_outputs() {
+        assert!(parse_estimate(&["--levels", "2", "--export", "json"]).is_ok());
+        for flag in [
+            &["--visualise"][..],
+            &["--subpel", "half"],
+            &["--block-position", "0,0"],
+        ] {
+            let args: Vec<&str> = ["--levels", "2"].iter().chain(flag).copied().collect();
+            let error = parse_estimate(&args).err().unwrap();
+            assert_eq!(error.kind(), ErrorKind::ArgumentConflict, "{:?}", flag);
+        }
+    }
+}
@@ -0,0 +1,309 @@
+use std::fs;
+
+use image::{DynamicImage, GenericImageView, GrayImage, ImageError, Luma};
+use itertools::Itertools;
+
+use crate::{
+    flow::FlowField,
+    motion_field::MotionField,
+    subpel::{FractionalMotionVector, SubpixelRefiner},
+    types::BlockMotion,
+    utils::crop_target_block,
+    ExtractedBlock,
+};
+
+const SSIM_WINDOW_SIZE: u32 = 8;
+const SSIM_WINDOW_STRIDE: u32 = 4;
+const SSIM_C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+const SSIM_C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+pub struct CompensationResult {
+    pub predicted_frame: GrayImage,
+    pub residual: Vec<i16>,
+    pub psnr: f64,
+    pub ssim: f64,
+}
+
+impl CompensationResult {
+    pub fn new(anchor_frame: &DynamicImage, predicted_frame: GrayImage) -> Self {
+        let anchor_frame = anchor_frame.to_luma8();
+
+        let residual = anchor_frame
+            .as_raw()
+            .iter()
+            .zip(predicted_frame.as_raw())
+            .map(|(anchor_pixel, predicted_pixel)| *anchor_pixel as i16 - *predicted_pixel as i16)
+            .collect();
+
+        Self {
+            psnr: calculate_psnr(&anchor_frame, &predicted_frame),
+            ssim: calculate_ssim(&anchor_frame, &predicted_frame),
+            predicted_frame,
+            residual,
+        }
+    }
+
+    // Residuals are centred on mid-grey and scaled by the gain to make them visible
+    pub fn amplified_residual(&self, gain: f64) -> GrayImage {
+        let (width, height) = self.predicted_frame.dimensions();
+        let pixels = self
+            .residual
+            .iter()
+            .map(|residual| f64::round(128.0 + gain * *residual as f64).clamp(0.0, 255.0) as u8)
+            .collect();
+
+        GrayImage::from_raw(width, height, pixels).unwrap()
+    }
+
+    pub fn export(
+        &self,
+        output_folder: &str,
+        file_name: &str,
+        gain: f64,
+    ) -> Result<(), ImageError> {
+        fs::create_dir_all(output_folder)?;
+
+        self.predicted_frame
+            .save(format!("{}/{}_prediction.png", output_folder, file_name))?;
+        self.amplified_residual(gain)
+            .save(format!("{}/{}_residual.png", output_folder, file_name))?;
+
+        Ok(())
+    }
+}
+
+pub fn compensate_frame(target_frame: &DynamicImage, motion_field: &MotionField) -> GrayImage {
+    let mut predicted_frame = GrayImage::new(target_frame.width(), target_frame.height());
+
+    for motion in &motion_field.blocks {
+        let predicted_block = crop_target_block(&empty_block(motion), &motion.vector, target_frame);
+        paste_block(
+            &mut predicted_frame,
+            motion,
+            &predicted_block.pixels.to_luma8(),
+        );
+    }
+
+    predicted_frame
+}
+
+// Blocks are interpolated by the refiner at their refined vectors, one per block of the field
+pub fn compensate_frame_with_subpixel(
+    target_frame: &DynamicImage,
+    motion_field: &MotionField,
+    refined_vectors: &[FractionalMotionVector],
+    refiner: &SubpixelRefiner,
+) -> GrayImage {
+    let mut predicted_frame = GrayImage::new(target_frame.width(), target_frame.height());
+
+    for (motion, refined_vector) in motion_field.blocks.iter().zip(refined_vectors) {
+        let predicted_block =
+            refiner.compensate(&empty_block(motion), target_frame, refined_vector);
+        paste_block(&mut predicted_frame, motion, &predicted_block);
+    }
+
+    predicted_frame
+}
+
+// Predictions only need the position and size of the anchor block
+fn empty_block(motion: &BlockMotion) -> ExtractedBlock {
+    ExtractedBlock {
+        x_offset: motion.x_offset,
+        y_offset: motion.y_offset,
+        pixels: DynamicImage::new_luma8(motion.width, motion.height),
+    }
+}
+
+fn paste_block(predicted_frame: &mut GrayImage, motion: &BlockMotion, predicted_block: &GrayImage) {
+    for (x, y, pixel) in predicted_block.enumerate_pixels() {
+        predicted_frame.put_pixel(motion.x_offset + x, motion.y_offset + y, *pixel);
+    }
+}
+
+// Dense vectors may be fractional, so the target frame is sampled bilinearly with
+// coordinates clamped to its borders
+pub fn compensate_frame_with_flow(target_frame: &DynamicImage, flow: &FlowField) -> GrayImage {
+    let target_frame = target_frame.to_luma8();
+    let (width, height) = target_frame.dimensions();
+    let pixel = |x: i64, y: i64| {
+        let Luma([value]) = *target_frame.get_pixel(
+            x.clamp(0, width as i64 - 1) as u32,
+            y.clamp(0, height as i64 - 1) as u32,
+        );
+        value as f64
+    };
+
+    GrayImage::from_fn(width, height, |x, y| {
+        let (u, v) = if x < flow.width && y < flow.height {
+            flow.get(x, y)
+        } else {
+            (0.0, 0.0)
+        };
+
+        let (source_x, source_y) = (x as f64 + u as f64, y as f64 + v as f64);
+        let (left, top) = (source_x.floor(), source_y.floor());
+        let (x_fraction, y_fraction) = (source_x - left, source_y - top);
+        let (left, top) = (left as i64, top as i64);
+
+        let value = (1.0 - y_fraction)
+            * ((1.0 - x_fraction) * pixel(left, top) + x_fraction * pixel(left + 1, top))
+            + y_fraction
+                * ((1.0 - x_fraction) * pixel(left, top + 1)
+                    + x_fraction * pixel(left + 1, top + 1));
+
+        Luma([f64::round(value).clamp(0.0, 255.0) as u8])
+    })
+}
+
+pub fn calculate_psnr(reference: &GrayImage, distorted: &GrayImage) -> f64 {
+    let squared_error: f64 = reference
+        .as_raw()
+        .iter()
+        .zip(distorted.as_raw())
+        .map(|(reference_pixel, distorted_pixel)| {
+            let difference = *reference_pixel as f64 - *distorted_pixel as f64;
+            difference * difference
+        })
+        .sum();
+
+    let mse = squared_error / reference.as_raw().len() as f64;
+    if mse == 0.0 {
+        return f64::INFINITY;
+    }
+
+    10.0 * f64::log10(255.0 * 255.0 / mse)
+}
+
+// Mean SSIM over overlapping square windows
+pub fn calculate_ssim(reference: &GrayImage, distorted: &GrayImage) -> f64 {
+    let (width, height) = reference.dimensions();
+    let window_size = SSIM_WINDOW_SIZE.min(width).min(height);
+
+    let window_positions: Vec<(u32, u32)> = (0..=height - window_size)
+        .step_by(SSIM_WINDOW_STRIDE as usize)
+        .cartesian_product((0..=width - window_size).step_by(SSIM_WINDOW_STRIDE as usize))
+        .collect();
+
+    let ssim_sum: f64 = window_positions
+        .iter()
+        .map(|(y_offset, x_offset)| {
+            let pixel_pairs: Vec<(f64, f64)> = (*y_offset..y_offset + window_size)
+                .cartesian_product(*x_offset..x_offset + window_size)
+                .map(|(y, x)| {
+                    let Luma([reference_pixel]) = *reference.get_pixel(x, y);
+                    let Luma([distorted_pixel]) = *distorted.get_pixel(x, y);
+                    (reference_pixel as f64, distorted_pixel as f64)
+                })
+                .collect();
+
+            let count = pixel_pairs.len() as f64;
+            let reference_mean = pixel_pairs.iter().map(|(r, _)| r).sum::<f64>() / count;
+            let distorted_mean = pixel_pairs.iter().map(|(_, d)| d).sum::<f64>() / count;
+
+            let (reference_variance, distorted_variance, covariance) = pixel_pairs.iter().fold(
+                (0.0, 0.0, 0.0),
+                |(reference_variance, distorted_variance, covariance), (r, d)| {
+                    let (r, d) = (r - reference_mean, d - distorted_mean);
+                    (
+                        reference_variance + r * r / count,
+                        distorted_variance + d * d / count,
+                        covariance + r * d / count,
+                    )
+                },
+            );
+
+            ((2.0 * reference_mean * distorted_mean + SSIM_C1) * (2.0 * covariance + SSIM_C2))
+                / ((reference_mean * reference_mean + distorted_mean * distorted_mean + SSIM_C1)
+                    * (reference_variance + distorted_variance + SSIM_C2))
+        })
+        .sum();
+
+    ssim_sum / window_positions.len() as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{bma::exhaustive::ExhaustiveBlockMatcher, test_utils::shifted_pair};
+
+    #[test]
+    fn psnr_follows_the_mean_squared_error() {
+        let reference = GrayImage::from_pixel(8, 8, Luma([100]));
+
+        assert_eq!(calculate_psnr(&reference, &reference), f64::INFINITY);
+        // A difference of one everywhere gives an MSE of one
+        let psnr = calculate_psnr(&reference, &GrayImage::from_pixel(8, 8, Luma([101])));
+        assert!((psnr - 20.0 * f64::log10(255.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn ssim_is_one_for_identical_frames_only() {
+        let (anchor_frame, target_frame) = shifted_pair(32, 32, (3, 0));
+        let (anchor_frame, target_frame) = (anchor_frame.to_luma8(), target_frame.to_luma8());
+
+        assert!((calculate_ssim(&anchor_frame, &anchor_frame) - 1.0).abs() < 1e-12);
+        assert!(calculate_ssim(&anchor_frame, &target_frame) < 0.9);
+    }
+
+    #[test]
+    fn residuals_are_signed_and_amplified_around_mid_grey() {
+        let anchor_frame =
+            DynamicImage::ImageLuma8(GrayImage::from_raw(2, 2, vec![10, 200, 130, 128]).unwrap());
+        let result = CompensationResult::new(
+            &anchor_frame,
+            GrayImage::from_raw(2, 2, vec![20, 100, 128, 128]).unwrap(),
+        );
+
+        assert_eq!(result.residual, vec![-10, 100, 2, 0]);
+        assert_eq!(
+            result.amplified_residual(4.0).into_raw(),
+            vec![88, 255, 136, 128]
+        );
+    }
+
+    #[test]
+    fn motion_fields_reconstruct_a_shifted_frame() {
+        let (anchor_frame, target_frame) = shifted_pair(64, 64, (3, -2));
+        let motion_field = MotionField::estimate(
+            &anchor_frame,
+            &target_frame,
+            16,
+            &ExhaustiveBlockMatcher::new(4),
+        );
+
+        let result = CompensationResult::new(
+            &anchor_frame,
+            compensate_frame(&target_frame, &motion_field),
+        );
+
+        // Only blocks whose match would leave the frame are predicted imperfectly
+        let anchor_frame = anchor_frame.to_luma8();
+        for (x, y, pixel) in result.predicted_frame.enumerate_pixels() {
+            if x < 48 && y >= 16 {
+                assert_eq!(pixel, anchor_frame.get_pixel(x, y));
+            }
+        }
+        // And the prediction still beats the uncompensated target frame
+        assert!(result.psnr > calculate_psnr(&anchor_frame, &target_frame.to_luma8()));
+    }
+
+    #[test]
+    fn integer_flow_reproduces_the_shift() {
+        let (anchor_frame, target_frame) = shifted_pair(32, 32, (2, 1));
+        let mut flow = FlowField::new(32, 32);
+        for (x, y) in (0..32).cartesian_product(0..32) {
+            flow.set(x, y, (2.0, 1.0));
+        }
+
+        let predicted_frame = compensate_frame_with_flow(&target_frame, &flow);
+
+        // Apart from the right and bottom borders, which replicate the last pixels
+        let anchor_frame = anchor_frame.to_luma8();
+        for (x, y) in (0..30).cartesian_product(0..31) {
+            assert_eq!(
+                predicted_frame.get_pixel(x, y),
+                anchor_frame.get_pixel(x, y)
+            );
+        }
+    }
+}
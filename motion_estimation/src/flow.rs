@@ -0,0 +1,123 @@
+use crate::{
+    subpel::FractionalMotionVector,
+    types::{BlockMotion, MotionVector},
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FlowField {
+    pub width: u32,
+    pub height: u32,
+    pub vectors: Vec<(f32, f32)>,
+}
+
+impl FlowField {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            vectors: vec![(0.0, 0.0); (width * height) as usize],
+        }
+    }
+
+    // Every pixel takes the vector of the block covering it
+    pub fn from_blocks<'a>(blocks: impl IntoIterator<Item = &'a BlockMotion>) -> Self {
+        Self::from_block_vectors(blocks.into_iter().map(|block| {
+            let vector = (block.vector.dx as f32, block.vector.dy as f32);
+            (block, vector)
+        }))
+    }
+
+    // Every pixel takes the subpixel vector refined for the block covering it
+    pub fn from_refined_blocks(
+        blocks: &[BlockMotion],
+        refined_vectors: &[FractionalMotionVector],
+    ) -> Self {
+        Self::from_block_vectors(blocks.iter().zip(refined_vectors).map(|(block, vector)| {
+            let (dx, dy) = vector.as_pixels();
+            (block, (dx as f32, dy as f32))
+        }))
+    }
+
+    fn from_block_vectors<'a>(
+        block_vectors: impl IntoIterator<Item = (&'a BlockMotion, (f32, f32))>,
+    ) -> Self {
+        let block_vectors: Vec<(&BlockMotion, (f32, f32))> = block_vectors.into_iter().collect();
+
+        let width = block_vectors
+            .iter()
+            .map(|(block, _)| block.x_offset + block.width)
+            .max()
+            .unwrap_or(0);
+        let height = block_vectors
+            .iter()
+            .map(|(block, _)| block.y_offset + block.height)
+            .max()
+            .unwrap_or(0);
+
+        let mut flow = Self::new(width, height);
+
+        for (block, vector) in block_vectors {
+            for y in block.y_offset..block.y_offset + block.height {
+                for x in block.x_offset..block.x_offset + block.width {
+                    flow.set(x, y, vector);
+                }
+            }
+        }
+
+        flow
+    }
+
+    // Averages the vectors over a grid of blocks, rounding them to whole pixels
+    pub fn to_blocks(&self, block_size: u32) -> Vec<BlockMotion> {
+        let mut blocks = Vec::new();
+
+        for y_offset in (0..self.height).step_by(block_size as usize) {
+            for x_offset in (0..self.width).step_by(block_size as usize) {
+                let width = block_size.min(self.width - x_offset);
+                let height = block_size.min(self.height - y_offset);
+
+                let (mut sum_u, mut sum_v) = (0.0, 0.0);
+                for y in y_offset..y_offset + height {
+                    for x in x_offset..x_offset + width {
+                        let (u, v) = self.get(x, y);
+                        sum_u += u;
+                        sum_v += v;
+                    }
+                }
+
+                let area = (width * height) as f32;
+                blocks.push(BlockMotion {
+                    x_offset,
+                    y_offset,
+                    width,
+                    height,
+                    vector: MotionVector {
+                        dx: (sum_u / area).round() as i32,
+                        dy: (sum_v / area).round() as i32,
+                        cost: 0.0,
+                        points_evaluated: 0,
+                        points_pruned: 0,
+                    },
+                });
+            }
+        }
+
+        blocks
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> (f32, f32) {
+        self.vectors[(y * self.width + x) as usize]
+    }
+
+    pub fn set(&mut self, x: u32, y: u32, vector: (f32, f32)) {
+        self.vectors[(y * self.width + x) as usize] = vector;
+    }
+
+    pub fn max_magnitude(&self) -> f32 {
+        self.vectors
+            .iter()
+            .map(|(u, v)| f32::hypot(*u, *v))
+            .filter(|magnitude| magnitude.is_finite())
+            .fold(0.0, f32::max)
+    }
+}
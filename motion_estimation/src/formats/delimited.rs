@@ -0,0 +1,216 @@
+use std::io::{self, Read, Write};
+
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+use super::{FrameMotionField, FrameReferenceMotionField};
+use crate::{
+    motion_field::MotionField,
+    multi_reference::{PredictionMode, ReferenceVector},
+    types::{BlockMotion, MotionVector},
+};
+
+// One row per block, rows of the same frame pair being contiguous and in raster order
+#[derive(Serialize, Deserialize)]
+struct BlockRecord {
+    anchor_frame_index: usize,
+    target_frame_index: usize,
+    x_offset: u32,
+    y_offset: u32,
+    width: u32,
+    height: u32,
+    dx: i32,
+    dy: i32,
+    cost: f64,
+    points_evaluated: u32,
+    #[serde(default)]
+    points_pruned: u32,
+}
+
+pub fn write_csv<W: Write>(writer: W, motion_fields: &[FrameMotionField]) -> io::Result<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+
+    for frame_motion_field in motion_fields {
+        for block in &frame_motion_field.motion_field.blocks {
+            writer.serialize(BlockRecord {
+                anchor_frame_index: frame_motion_field.anchor_frame_index,
+                target_frame_index: frame_motion_field.target_frame_index,
+                x_offset: block.x_offset,
+                y_offset: block.y_offset,
+                width: block.width,
+                height: block.height,
+                dx: block.vector.dx,
+                dy: block.vector.dy,
+                cost: block.vector.cost,
+                points_evaluated: block.vector.points_evaluated,
+                points_pruned: block.vector.points_pruned,
+            })?;
+        }
+    }
+
+    writer.flush()
+}
+
+// Vectors of the unused prediction direction are left empty
+#[derive(Serialize)]
+struct ReferenceBlockRecord {
+    anchor_frame_index: usize,
+    x_offset: u32,
+    y_offset: u32,
+    width: u32,
+    height: u32,
+    mode: PredictionMode,
+    backward_frame_index: Option<usize>,
+    backward_dx: Option<i32>,
+    backward_dy: Option<i32>,
+    forward_frame_index: Option<usize>,
+    forward_dx: Option<i32>,
+    forward_dy: Option<i32>,
+    cost: f64,
+}
+
+pub fn write_reference_csv<W: Write>(
+    writer: W,
+    motion_fields: &[FrameReferenceMotionField],
+) -> io::Result<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+
+    for frame_motion_field in motion_fields {
+        let frame_index = |frame_indices: &[usize], reference: Option<ReferenceVector>| {
+            reference.map(|reference| frame_indices[reference.reference])
+        };
+
+        for block in &frame_motion_field.motion_field.blocks {
+            writer.serialize(ReferenceBlockRecord {
+                anchor_frame_index: frame_motion_field.anchor_frame_index,
+                x_offset: block.x_offset,
+                y_offset: block.y_offset,
+                width: block.width,
+                height: block.height,
+                mode: block.mode,
+                backward_frame_index: frame_index(
+                    &frame_motion_field.past_frame_indices,
+                    block.backward,
+                ),
+                backward_dx: block.backward.map(|reference| reference.vector.dx),
+                backward_dy: block.backward.map(|reference| reference.vector.dy),
+                forward_frame_index: frame_index(
+                    &frame_motion_field.future_frame_indices,
+                    block.forward,
+                ),
+                forward_dx: block.forward.map(|reference| reference.vector.dx),
+                forward_dy: block.forward.map(|reference| reference.vector.dy),
+                cost: block.cost,
+            })?;
+        }
+    }
+
+    writer.flush()
+}
+
+pub fn read_csv<R: Read>(reader: R) -> io::Result<Vec<FrameMotionField>> {
+    let records: Vec<BlockRecord> = csv::Reader::from_reader(reader)
+        .deserialize()
+        .collect::<Result<_, _>>()?;
+
+    let motion_fields = records
+        .into_iter()
+        .group_by(|record| (record.anchor_frame_index, record.target_frame_index))
+        .into_iter()
+        .map(|((anchor_frame_index, target_frame_index), records)| {
+            let blocks = records
+                .map(|record| BlockMotion {
+                    x_offset: record.x_offset,
+                    y_offset: record.y_offset,
+                    width: record.width,
+                    height: record.height,
+                    vector: MotionVector {
+                        dx: record.dx,
+                        dy: record.dy,
+                        cost: record.cost,
+                        points_evaluated: record.points_evaluated,
+                        points_pruned: record.points_pruned,
+                    },
+                })
+                .collect();
+
+            FrameMotionField {
+                anchor_frame_index,
+                target_frame_index,
+                motion_field: MotionField::from_blocks(blocks),
+            }
+        })
+        .collect();
+
+    Ok(motion_fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::multi_reference::{ReferenceBlockMotion, ReferenceMotionField};
+
+    #[test]
+    fn rows_without_pruned_points_are_read() {
+        let csv = "anchor_frame_index,target_frame_index,x_offset,y_offset,width,height,dx,dy,\
+                   cost,points_evaluated\n\
+                   0,2,0,0,8,8,1,-1,0.5,9\n\
+                   0,2,8,0,4,8,0,0,1.25,9\n";
+
+        let motion_fields = read_csv(csv.as_bytes()).unwrap();
+
+        assert_eq!(motion_fields.len(), 1);
+        let motion_field = &motion_fields[0].motion_field;
+        assert_eq!(
+            (motion_field.mb_size, motion_field.cols, motion_field.rows),
+            (8, 2, 1)
+        );
+        assert_eq!(motion_field.blocks[1].width, 4);
+        assert_eq!(motion_field.blocks[0].vector.points_pruned, 0);
+    }
+
+    #[test]
+    fn reference_rows_resolve_frame_indices() {
+        let vector = |dx, dy| MotionVector {
+            dx,
+            dy,
+            cost: 0.0,
+            points_evaluated: 1,
+            points_pruned: 0,
+        };
+        let block = ReferenceBlockMotion {
+            x_offset: 8,
+            y_offset: 0,
+            width: 8,
+            height: 8,
+            mode: PredictionMode::Backward,
+            backward: Some(ReferenceVector {
+                reference: 1,
+                vector: vector(2, -3),
+            }),
+            forward: None,
+            cost: 0.5,
+        };
+        let motion_fields = [FrameReferenceMotionField {
+            anchor_frame_index: 5,
+            past_frame_indices: vec![4, 3],
+            future_frame_indices: vec![6],
+            motion_field: ReferenceMotionField {
+                mb_size: 8,
+                cols: 1,
+                rows: 1,
+                blocks: vec![block],
+            },
+        }];
+
+        let mut csv = Vec::new();
+        write_reference_csv(&mut csv, &motion_fields).unwrap();
+
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "anchor_frame_index,x_offset,y_offset,width,height,mode,backward_frame_index,\
+             backward_dx,backward_dy,forward_frame_index,forward_dx,forward_dy,cost\n\
+             5,8,0,8,8,backward,3,2,-3,,,,0.5\n"
+        );
+    }
+}
@@ -0,0 +1,134 @@
+use std::io::{self, Read, Write};
+
+use crate::flow::FlowField;
+
+// Sanity check value from the Middlebury flow format, "PIEH" in little endian bytes
+const FLO_TAG: f32 = 202021.25;
+
+pub fn write_flo<W: Write>(mut writer: W, flow: &FlowField) -> io::Result<()> {
+    writer.write_all(&FLO_TAG.to_le_bytes())?;
+    writer.write_all(&(flow.width as i32).to_le_bytes())?;
+    writer.write_all(&(flow.height as i32).to_le_bytes())?;
+
+    for (u, v) in &flow.vectors {
+        writer.write_all(&u.to_le_bytes())?;
+        writer.write_all(&v.to_le_bytes())?;
+    }
+
+    writer.flush()
+}
+
+pub fn read_flo<R: Read>(mut reader: R) -> io::Result<FlowField> {
+    if read_f32(&mut reader)? != FLO_TAG {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Not a Middlebury .flo file",
+        ));
+    }
+
+    let (width, height) = (read_i32(&mut reader)?, read_i32(&mut reader)?);
+    let vector_bytes = u32::try_from(width)
+        .ok()
+        .zip(u32::try_from(height).ok())
+        .and_then(|(width, height)| (width as usize).checked_mul(height as usize))
+        .and_then(|vector_count| vector_count.checked_mul(8))
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid .flo dimensions {}x{}", width, height),
+            )
+        })?;
+
+    // The header is untrusted, so the buffer only grows with the data actually present
+    let mut data = Vec::new();
+    reader
+        .by_ref()
+        .take(vector_bytes as u64)
+        .read_to_end(&mut data)?;
+    if data.len() < vector_bytes {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!(
+                "Truncated .flo file, {}x{} vectors announced",
+                width, height
+            ),
+        ));
+    }
+
+    Ok(FlowField {
+        width: width as u32,
+        height: height as u32,
+        vectors: data
+            .chunks_exact(8)
+            .map(|vector| {
+                (
+                    f32::from_le_bytes(vector[..4].try_into().unwrap()),
+                    f32::from_le_bytes(vector[4..].try_into().unwrap()),
+                )
+            })
+            .collect(),
+    })
+}
+
+fn read_f32<R: Read>(reader: &mut R) -> io::Result<f32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(f32::from_le_bytes(bytes))
+}
+
+fn read_i32<R: Read>(reader: &mut R) -> io::Result<i32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(i32::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(width: i32, height: i32) -> Vec<u8> {
+        [
+            FLO_TAG.to_le_bytes(),
+            width.to_le_bytes(),
+            height.to_le_bytes(),
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn flow_fields_round_trip() {
+        let mut flow = FlowField::new(3, 2);
+        flow.set(0, 0, (1.5, -2.25));
+        flow.set(2, 1, (-0.125, 1e9));
+        flow.set(1, 1, (f32::INFINITY, 0.0));
+
+        let mut bytes = Vec::new();
+        write_flo(&mut bytes, &flow).unwrap();
+        assert_eq!(bytes.len(), 12 + 3 * 2 * 8);
+
+        let read = read_flo(bytes.as_slice()).unwrap();
+        assert_eq!((read.width, read.height), (3, 2));
+        assert_eq!(read.vectors, flow.vectors);
+    }
+
+    #[test]
+    fn other_files_are_rejected() {
+        let error = read_flo(&b"PNG\0\0\0\0\0\0\0\0\0"[..]).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        let error = read_flo(header(-1, 4).as_slice()).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn announced_sizes_must_be_backed_by_data() {
+        // Would be 32 GiB of vectors if allocated from the header
+        let error = read_flo(header(i32::MAX, 2).as_slice()).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+
+        let mut bytes = header(2, 2);
+        bytes.extend_from_slice(&[0; 3 * 8 + 4]);
+        let error = read_flo(bytes.as_slice()).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
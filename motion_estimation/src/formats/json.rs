@@ -0,0 +1,12 @@
+use std::io::{self, Read, Write};
+
+use super::FrameMotionField;
+
+pub fn write_json<W: Write>(writer: W, motion_fields: &[FrameMotionField]) -> io::Result<()> {
+    serde_json::to_writer_pretty(writer, motion_fields)?;
+    Ok(())
+}
+
+pub fn read_json<R: Read>(reader: R) -> io::Result<Vec<FrameMotionField>> {
+    Ok(serde_json::from_reader(reader)?)
+}
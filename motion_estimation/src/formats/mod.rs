@@ -0,0 +1,338 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use image::GrayImage;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    compensation::{compensate_frame, compensate_frame_with_flow},
+    flow::FlowField,
+    motion_field::MotionField,
+    multi_reference::ReferenceMotionField,
+    pairing::FramePair,
+    subpel::FractionalMotionVector,
+};
+
+pub mod delimited;
+pub mod flo;
+pub mod json;
+
+use flo::{read_flo, write_flo};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    Json,
+    Csv,
+    Flo,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "json" => Ok(ExportFormat::Json),
+            "csv" => Ok(ExportFormat::Csv),
+            "flo" => Ok(ExportFormat::Flo),
+            _ => Err(format!(
+                "unknown export format '{}', expected one of: json, csv, flo",
+                name
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FrameMotionField {
+    pub anchor_frame_index: usize,
+    pub target_frame_index: usize,
+    pub motion_field: MotionField,
+}
+
+// Reference positions of the blocks are resolved to frame indices through the lists
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FrameReferenceMotionField {
+    pub anchor_frame_index: usize,
+    pub past_frame_indices: Vec<usize>,
+    pub future_frame_indices: Vec<usize>,
+    pub motion_field: ReferenceMotionField,
+}
+
+// The format is picked from the file extension, either json or csv
+pub fn write_motion_fields(path: &Path, motion_fields: &[FrameMotionField]) -> io::Result<()> {
+    let writer = BufWriter::new(File::create(path)?);
+
+    match extension(path)?.as_str() {
+        "json" => json::write_json(writer, motion_fields),
+        "csv" => delimited::write_csv(writer, motion_fields),
+        extension => Err(unsupported_extension(extension)),
+    }
+}
+
+pub fn read_motion_fields(path: &Path) -> io::Result<Vec<FrameMotionField>> {
+    let reader = BufReader::new(File::open(path)?);
+
+    match extension(path)?.as_str() {
+        "json" => json::read_json(reader),
+        "csv" => delimited::read_csv(reader),
+        extension => Err(unsupported_extension(extension)),
+    }
+}
+
+// Writes the motion of a sequence of frame pairs to a folder. Dense .flo files are written
+// per pair as they are added, while JSON and CSV gather the whole sequence until finished.
+pub struct MotionExport {
+    output_folder: String,
+    formats: Vec<ExportFormat>,
+    motion_fields: Vec<FrameMotionField>,
+}
+
+impl MotionExport {
+    pub fn new(output_folder: &str, formats: &[ExportFormat]) -> io::Result<Self> {
+        fs::create_dir_all(output_folder)?;
+
+        Ok(Self {
+            output_folder: output_folder.to_owned(),
+            formats: formats.to_vec(),
+            motion_fields: Vec::new(),
+        })
+    }
+
+    pub fn add(&mut self, pair: &FramePair, motion_field: MotionField) -> io::Result<()> {
+        self.add_with_flow(pair, motion_field, |motion_field| {
+            FlowField::from_blocks(&motion_field.blocks)
+        })
+    }
+
+    // Only .flo files hold fractional vectors, so JSON and CSV keep the whole pixel ones
+    pub fn add_refined(
+        &mut self,
+        pair: &FramePair,
+        motion_field: MotionField,
+        refined_vectors: &[FractionalMotionVector],
+    ) -> io::Result<()> {
+        self.add_with_flow(pair, motion_field, |motion_field| {
+            FlowField::from_refined_blocks(&motion_field.blocks, refined_vectors)
+        })
+    }
+
+    fn add_with_flow(
+        &mut self,
+        pair: &FramePair,
+        motion_field: MotionField,
+        flow: impl FnOnce(&MotionField) -> FlowField,
+    ) -> io::Result<()> {
+        if self.formats.contains(&ExportFormat::Flo) {
+            self.add_flow(pair, &flow(&motion_field))?;
+        }
+
+        if self.formats.contains(&ExportFormat::Json) || self.formats.contains(&ExportFormat::Csv) {
+            self.motion_fields.push(FrameMotionField {
+                anchor_frame_index: pair.anchor_index,
+                target_frame_index: pair.target_index,
+                motion_field,
+            });
+        }
+
+        Ok(())
+    }
+
+    pub fn add_flow(&mut self, pair: &FramePair, flow: &FlowField) -> io::Result<()> {
+        let file = File::create(flo_path(Path::new(&self.output_folder), pair))?;
+        write_flo(BufWriter::new(file), flow)
+    }
+
+    // The gathered fields are saved as motion.json and motion.csv
+    pub fn finish(self) -> io::Result<()> {
+        for (format, extension) in [(ExportFormat::Json, "json"), (ExportFormat::Csv, "csv")] {
+            if self.formats.contains(&format) {
+                let path = format!("{}/motion.{}", self.output_folder, extension);
+                write_motion_fields(Path::new(&path), &self.motion_fields)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub enum LoadedMotion {
+    Fields(HashMap<(usize, usize), MotionField>),
+    Flow(PathBuf),
+}
+
+impl LoadedMotion {
+    // Predicts the anchor frame of the pair from its target frame
+    pub fn predict(&self, pair: &FramePair) -> io::Result<GrayImage> {
+        let (_, target_frame) = pair.target.as_ref();
+
+        match self {
+            LoadedMotion::Fields(motion_fields) => motion_fields
+                .get(&(pair.anchor_index, pair.target_index))
+                .map(|motion_field| compensate_frame(target_frame, motion_field))
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!(
+                            "No motion field between frames {} and {}",
+                            pair.anchor_index, pair.target_index
+                        ),
+                    )
+                }),
+            LoadedMotion::Flow(folder) => {
+                let file = File::open(flo_path(folder, pair))?;
+                let flow = read_flo(BufReader::new(file))?;
+
+                Ok(compensate_frame_with_flow(target_frame, &flow))
+            }
+        }
+    }
+}
+
+// Reloads exported JSON or CSV motion fields, or a folder holding one .flo file per frame
+// pair. Folders are read lazily, as pairs are predicted.
+pub fn load_motion(path: &Path) -> io::Result<LoadedMotion> {
+    if path.is_dir() {
+        return Ok(LoadedMotion::Flow(path.to_owned()));
+    }
+
+    Ok(LoadedMotion::Fields(
+        read_motion_fields(path)?
+            .into_iter()
+            .map(|frame_motion_field| {
+                (
+                    (
+                        frame_motion_field.anchor_frame_index,
+                        frame_motion_field.target_frame_index,
+                    ),
+                    frame_motion_field.motion_field,
+                )
+            })
+            .collect(),
+    ))
+}
+
+fn flo_path(folder: &Path, pair: &FramePair) -> PathBuf {
+    folder.join(format!("{}.flo", pair.file_stem()))
+}
+
+fn extension(path: &Path) -> io::Result<String> {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_lowercase)
+        .ok_or_else(|| unsupported_extension(""))
+}
+
+fn unsupported_extension(extension: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!(
+            "Unsupported motion field format '{}', expected json or csv",
+            extension
+        ),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        bma::exhaustive::ExhaustiveBlockMatcher,
+        test_utils::{shifted_pair, temp_folder},
+    };
+
+    // Odd frame sizes give cropped border blocks
+    fn frame_pair(anchor_index: usize, vector: (i32, i32)) -> (FramePair, MotionField) {
+        let (anchor_frame, target_frame) = shifted_pair(40, 37, vector);
+        let motion_field = MotionField::estimate(
+            &anchor_frame,
+            &target_frame,
+            16,
+            &ExhaustiveBlockMatcher::new(3),
+        );
+        let pair = FramePair {
+            anchor_index,
+            target_index: anchor_index + 1,
+            anchor: Arc::new((format!("{}.png", anchor_index), anchor_frame)),
+            target: Arc::new((format!("{}.png", anchor_index + 1), target_frame)),
+        };
+
+        (pair, motion_field)
+    }
+
+    #[test]
+    fn json_and_csv_round_trip() {
+        let motion_fields: Vec<FrameMotionField> = [(0, (2, 1)), (1, (-1, 2))]
+            .into_iter()
+            .map(|(anchor_frame_index, vector)| FrameMotionField {
+                anchor_frame_index,
+                target_frame_index: anchor_frame_index + 1,
+                motion_field: frame_pair(anchor_frame_index, vector).1,
+            })
+            .collect();
+        let folder = temp_folder("formats_round_trip");
+
+        for extension in ["json", "csv", "JSON"] {
+            let path = folder.join("motion").with_extension(extension);
+            write_motion_fields(&path, &motion_fields).unwrap();
+
+            assert_eq!(
+                read_motion_fields(&path).unwrap(),
+                motion_fields,
+                "{}",
+                extension
+            );
+        }
+
+        let error = write_motion_fields(&folder.join("motion.xml"), &motion_fields).err();
+        assert_eq!(error.unwrap().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn exports_are_reloaded_for_compensation() {
+        let (pair, motion_field) = frame_pair(3, (2, -1));
+        let expected_frame = compensate_frame(&pair.target.1, &motion_field);
+        let folder = temp_folder("formats_export");
+
+        let mut export = MotionExport::new(
+            folder.to_str().unwrap(),
+            &[ExportFormat::Json, ExportFormat::Csv, ExportFormat::Flo],
+        )
+        .unwrap();
+        export.add(&pair, motion_field).unwrap();
+        export.finish().unwrap();
+
+        assert!(folder.join("3_4.flo").is_file());
+        // Whole pixel vectors give the same prediction when sampled as dense flow
+        for path in [
+            folder.join("motion.json"),
+            folder.join("motion.csv"),
+            folder.clone(),
+        ] {
+            let loaded_motion = load_motion(&path).unwrap();
+            assert_eq!(
+                loaded_motion.predict(&pair).unwrap(),
+                expected_frame,
+                "{:?}",
+                path
+            );
+        }
+
+        let (other_pair, _) = frame_pair(5, (0, 0));
+        let loaded_motion = load_motion(&folder.join("motion.json")).unwrap();
+        let error = loaded_motion.predict(&other_pair).err();
+        assert_eq!(error.unwrap().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn export_formats_are_parsed_by_name() {
+        assert_eq!("flo".parse::<ExportFormat>(), Ok(ExportFormat::Flo));
+        assert!("png".parse::<ExportFormat>().is_err());
+    }
+}
@@ -0,0 +1,212 @@
+use image::{DynamicImage, GenericImageView};
+use itertools::Itertools;
+use log::debug;
+use rayon::prelude::*;
+
+use crate::{
+    bma::BlockMatcher,
+    metrics::{sad::MadMetric, DistortionMetric},
+    motion_field::MotionField,
+    plane::{luma_frame, LumaPlane},
+    pyramid::build_gaussian_pyramid,
+    types::{BlockMotion, MotionVector},
+    utils::{calculate_candidate_error, tile_frame},
+    ExtractedBlock,
+};
+
+const DEFAULT_REFINEMENT_RANGE: u16 = 2;
+
+pub struct HierarchicalMotionEstimator {
+    coarse_matcher: Box<dyn BlockMatcher>,
+    levels: usize,
+    refinement_ranges: Vec<u16>,
+    metric: Box<dyn DistortionMetric>,
+}
+
+impl HierarchicalMotionEstimator {
+    pub fn new(coarse_matcher: Box<dyn BlockMatcher>, levels: usize) -> Self {
+        assert!(levels >= 1, "A pyramid needs at least one level");
+
+        Self {
+            coarse_matcher,
+            levels,
+            refinement_ranges: vec![DEFAULT_REFINEMENT_RANGE; levels - 1],
+            metric: Box::new(MadMetric::new()),
+        }
+    }
+
+    // Ranges are given from the finest level up to the one below the coarsest
+    pub fn with_refinement_ranges(self, refinement_ranges: Vec<u16>) -> Self {
+        assert_eq!(
+            refinement_ranges.len(),
+            self.levels - 1,
+            "One refinement range is needed for each level below the coarsest"
+        );
+
+        Self {
+            refinement_ranges,
+            ..self
+        }
+    }
+
+    pub fn with_metric(self, metric: Box<dyn DistortionMetric>) -> Self {
+        Self { metric, ..self }
+    }
+
+    pub fn estimate(
+        &self,
+        anchor_frame: &DynamicImage,
+        target_frame: &DynamicImage,
+        mb_size: u32,
+    ) -> MotionField {
+        let coarsest_level = self.levels - 1;
+        assert!(
+            mb_size.is_multiple_of(1 << coarsest_level),
+            "Block size must be divisible by 2^(levels - 1)"
+        );
+
+        let anchor_pyramid = build_gaussian_pyramid(anchor_frame, self.levels);
+        let target_pyramid = build_gaussian_pyramid(target_frame, self.levels);
+
+        debug!("Estimating coarse motion at level {}", coarsest_level);
+        let mut motion_field = MotionField::estimate(
+            &anchor_pyramid[coarsest_level],
+            &target_pyramid[coarsest_level],
+            mb_size >> coarsest_level,
+            self.coarse_matcher.as_ref(),
+        );
+
+        for level in (0..coarsest_level).rev() {
+            debug!("Refining motion at level {}", level);
+
+            let level_mb_size = mb_size >> level;
+            let level_cols = anchor_pyramid[level].width().div_ceil(level_mb_size);
+            let level_rows = anchor_pyramid[level].height().div_ceil(level_mb_size);
+            let refinement_range = self.refinement_ranges[level] as i32;
+            let target_frame = luma_frame(&target_pyramid[level]);
+            let target_plane = LumaPlane::from_image(&target_frame);
+            let coarser_field = &motion_field;
+
+            let blocks = tile_frame(&anchor_pyramid[level], level_mb_size)
+                .par_iter()
+                .enumerate()
+                .map(|(index, anchor_block)| {
+                    let (col, row) = (index as u32 % level_cols, index as u32 / level_cols);
+                    let parent = coarser_field
+                        .block(
+                            col.min(coarser_field.cols - 1),
+                            row.min(coarser_field.rows - 1),
+                        )
+                        .vector;
+
+                    let vector = self.refine_block(
+                        anchor_block,
+                        &target_plane,
+                        (2 * parent.dx, 2 * parent.dy),
+                        refinement_range,
+                        parent.points_evaluated,
+                    );
+
+                    BlockMotion {
+                        x_offset: anchor_block.x_offset,
+                        y_offset: anchor_block.y_offset,
+                        width: anchor_block.pixels.width(),
+                        height: anchor_block.pixels.height(),
+                        vector,
+                    }
+                })
+                .collect();
+
+            motion_field = MotionField {
+                mb_size: level_mb_size,
+                cols: level_cols,
+                rows: level_rows,
+                blocks,
+            };
+        }
+
+        motion_field
+    }
+
+    fn refine_block(
+        &self,
+        block: &ExtractedBlock,
+        frame: &LumaPlane,
+        predicted_vector: (i32, i32),
+        refinement_range: i32,
+        points_evaluated: u32,
+    ) -> MotionVector {
+        let mut points_evaluated = points_evaluated;
+
+        // The zero vector is kept as a fallback when the whole window leaves the frame
+        let (dx, dy, cost) = (-refinement_range..=refinement_range)
+            .cartesian_product(-refinement_range..=refinement_range)
+            .map(|(x_offset, y_offset)| {
+                (predicted_vector.0 + x_offset, predicted_vector.1 + y_offset)
+            })
+            .chain(std::iter::once((0, 0)))
+            .filter_map(|vector| {
+                calculate_candidate_error(block, frame, vector, self.metric.as_ref())
+                    .map(|error| (vector.0, vector.1, error))
+            })
+            .inspect(|_| points_evaluated += 1)
+            .min_by(|(_, _, first_error), (_, _, second_error)| {
+                first_error
+                    .partial_cmp(second_error)
+                    .expect("Comparing NaN errors")
+            })
+            .unwrap();
+
+        MotionVector {
+            dx,
+            dy,
+            cost,
+            points_evaluated,
+            points_pruned: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{bma::exhaustive::ExhaustiveBlockMatcher, test_utils::shifted_pair};
+
+    #[test]
+    fn tracks_shifts_beyond_the_coarse_search_range() {
+        let (anchor_frame, target_frame) = shifted_pair(128, 128, (14, -10));
+        let estimator =
+            HierarchicalMotionEstimator::new(Box::new(ExhaustiveBlockMatcher::new(6)), 3);
+
+        let motion_field = estimator.estimate(&anchor_frame, &target_frame, 32);
+
+        assert_eq!(
+            (motion_field.mb_size, motion_field.cols, motion_field.rows),
+            (32, 4, 4)
+        );
+        let vector = motion_field.block(1, 2).vector;
+        assert_eq!((vector.dx, vector.dy, vector.cost), (14, -10, 0.0));
+    }
+
+    #[test]
+    fn refinement_ranges_bound_the_correction_of_each_level() {
+        let (anchor_frame, target_frame) = shifted_pair(128, 128, (14, -10));
+        let estimator =
+            HierarchicalMotionEstimator::new(Box::new(ExhaustiveBlockMatcher::new(6)), 3)
+                .with_refinement_ranges(vec![0, 0]);
+
+        let motion_field = estimator.estimate(&anchor_frame, &target_frame, 32);
+
+        // Without refinement the coarse vectors are only scaled up to the finest level
+        let vector = motion_field.block(1, 2).vector;
+        assert_eq!((vector.dx % 4, vector.dy % 4), (0, 0));
+        assert_ne!((vector.dx, vector.dy), (14, -10));
+    }
+
+    #[test]
+    #[should_panic(expected = "One refinement range is needed")]
+    fn refinement_ranges_match_the_levels() {
+        HierarchicalMotionEstimator::new(Box::new(ExhaustiveBlockMatcher::new(6)), 3)
+            .with_refinement_ranges(vec![2]);
+    }
+}
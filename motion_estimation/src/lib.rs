@@ -0,0 +1,29 @@
+pub mod bma;
+pub mod compensation;
+pub mod flow;
+pub mod formats;
+pub mod hierarchical;
+pub mod metrics;
+pub mod motion_field;
+pub mod multi_reference;
+pub mod optical_flow;
+pub mod pairing;
+pub mod pipeline;
+pub mod plane;
+pub mod pyramid;
+pub mod quadtree;
+pub mod rate;
+pub mod registry;
+pub mod source;
+pub mod subpel;
+pub mod types;
+pub mod utils;
+pub mod visualisation;
+
+#[cfg(test)]
+mod test_utils;
+
+pub use bma::BlockMatcher;
+pub use metrics::DistortionMetric;
+pub use motion_field::MotionField;
+pub use types::{BlockMotion, ExtractedBlock, MotionVector, SearchContext};
@@ -1,146 +1,305 @@
-mod bma;
-mod utils;
-mod types;
+mod cli;
 
-use std::{fs, ops::Div, time::Instant};
+use std::io;
 
-use bma::exhaustive::ExhaustiveBlockMatcher;
-use image::{DynamicImage};
-use itertools::Itertools;
-use log::{debug, info};
-use types::{ExtractedBlock, PredictionResult};
-use utils::extract_blocks;
-
-use crate::{bma::{BlockMatcher, naive::NaiveBlockMatcher, three_step::ThreeStepBlockMatcher}, utils::{calculate_block_prediction_error, export_block}};
+use clap::Parser;
+use cli::{
+    Cli, Command, CompareArgs, CompensateArgs, EstimateArgs, FlowArgs, FlowMethod, FrameArgs,
+    ReferencesArgs,
+};
+use log::info;
+use motion_estimation::{
+    bma::BlockMatcher,
+    formats::{load_motion, ExportFormat, MotionExport},
+    hierarchical::HierarchicalMotionEstimator,
+    multi_reference::MultiReferenceMotionEstimator,
+    optical_flow::{
+        horn_schunck::HornSchunckFlowEstimator, lucas_kanade::LucasKanadeFlowEstimator,
+        FlowEstimator,
+    },
+    pairing::FramePair,
+    pipeline::{
+        compensate::{compare_motion, compensate_pairs, MotionSource},
+        estimate::{
+            estimate_hierarchical_motion_fields, estimate_motion_fields,
+            estimate_quadtree_motion_fields, BlockPrediction,
+        },
+        flow::estimate_flow_fields,
+        open_frame_pairs, open_reference_windows,
+        references::estimate_reference_motion_fields,
+    },
+    quadtree::QuadtreeMotionEstimator,
+    registry::{create_matcher, create_metric, matcher_names},
+    subpel::SubpixelRefiner,
+    visualisation::{VectorOverlay, Visualisation},
+};
 
 fn main() {
     env_logger::init();
 
-    let mb_size = 16;
-
-    let mut frames: Vec<(String, DynamicImage)> = fs::read_dir("assets/meatthezoo_frames")
-        .unwrap()
-        .map(|file| {
-            let path = file.unwrap().path();
-            info!("Loading frame {:?}...", path);
-
-            let file_name = path
-                .clone()
-                .file_name()
-                .unwrap()
-                .to_os_string()
-                .clone()
-                .to_str()
-                .unwrap()
-                .to_owned();
-
-            let img = image::io::Reader::open(path.clone())
-                .unwrap()
-                .decode()
-                .unwrap()
-                .to_luma8();
-
-            let img = DynamicImage::ImageLuma8(img);
-
-            (file_name, img)
-        })
-        .collect();
-
-    frames.sort_unstable_by_key(|(file_name, _)| {
-        let (id, _) = file_name.split_once(".").unwrap();
-        id.parse::<i32>().unwrap()
-    });
+    let cli = Cli::parse();
+    if let Some(threads) = cli.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .expect("Failed to configure the thread pool");
+    }
 
-    // let anchor_blocks = extract_blocks("topleft/original", &frames, 0, 0, mb_size);
-    let anchor_blocks = extract_blocks("central/original", &frames, 190, 80, mb_size);
+    match cli.command {
+        Command::Estimate(args) => {
+            args.validate().unwrap_or_else(|error| error.exit());
+            run_estimate(&args)
+        }
+        Command::Compensate(args) => run_compensate(&args),
+        Command::Compare(args) => run_compare(&args),
+        Command::Flow(args) => run_flow(&args),
+        Command::References(args) => run_references(&args),
+    }
+}
 
-    info!(" --- Naive predictor");
-    let start_time = Instant::now();
-    predict_with_matcher(
-        &anchor_blocks,
-        &frames,
-        "central/naive",
-        Box::new(NaiveBlockMatcher::new())
-    );
-    info!(" Execution time: {}s", start_time.elapsed().as_secs_f64());
-
-    info!(" --- Exhaustive predictor");
-    let start_time = Instant::now();
-    predict_with_matcher(
-        &anchor_blocks,
-        &frames,
-        "central/exhaustive",
-        Box::new(ExhaustiveBlockMatcher::new(25))
-    );
-    info!(" Execution time: {}s", start_time.elapsed().as_secs_f64());
-
-    info!(" --- Three-step predictor");
-    let start_time = Instant::now();
-    predict_with_matcher(
-        &anchor_blocks,
-        &frames,
-        "central/three_step",
-        Box::new(ThreeStepBlockMatcher::new(25))
-    );
-    info!(" Execution time: {}s", start_time.elapsed().as_secs_f64());
+fn frame_pairs(args: &FrameArgs) -> impl Iterator<Item = io::Result<FramePair>> {
+    open_frame_pairs(&args.input, args.raw_format(), args.pairing)
+        .unwrap_or_else(|error| panic!("Failed to open {:?}: {}", args.input, error))
+}
+
+fn build_matcher(
+    name: &str,
+    frames: &FrameArgs,
+    metric: &str,
+    lambda: f64,
+) -> Box<dyn BlockMatcher> {
+    create_matcher(
+        name,
+        frames.search_range,
+        create_metric(metric).expect("Unknown metric"),
+        lambda,
+        frames.zero_motion_threshold,
+    )
+    .expect("Unknown matcher")
 }
 
-fn predict_with_matcher(
-    anchor_blocks: &Vec<ExtractedBlock>,
-    frames: &Vec<(String, DynamicImage)>,
-    parent_folder: &str,
-    matcher: Box<dyn BlockMatcher>,
-) {
-    let anchor_frame_indices = 0..frames.len() - 1;
-    // let target_frame_indices = 1..frames.len();
-
-    let prediction_indices_pairs = anchor_frame_indices.flat_map(|anchor_frame_index| {
-        (anchor_frame_index..anchor_frame_index+1).cartesian_product(anchor_frame_index+1..frames.len())
+fn run_estimate(args: &EstimateArgs) {
+    let output = args.frames.output.to_str().unwrap();
+    let (mb_size, search_range) = (args.frames.block_size, args.frames.search_range);
+    // Quadtree partitioning ranks its leaves with the same lambda as the matcher
+    let lambda = args
+        .lambda
+        .unwrap_or(if args.min_block_size.is_some() { 4.0 } else { 0.0 });
+    let matcher = build_matcher(&args.matcher, &args.frames, &args.metric, lambda);
+    let pairs = frame_pairs(&args.frames);
+
+    let visualisation = args.visualise.then(|| {
+        Visualisation::new(
+            &format!("{}/visualisation/{}", output, args.matcher),
+            VectorOverlay::new(),
+            search_range as f32,
+            args.animate.then_some(args.frame_delay),
+        )
+        .expect("Failed to create visualisation output")
+    });
+
+    let export = (!args.export.is_empty()).then(|| {
+        MotionExport::new(&format!("{}/export/{}", output, args.matcher), &args.export)
+            .expect("Failed to create export output")
+    });
+
+    if let Some(min_block_size) = args.min_block_size {
+        info!(
+            " --- Quadtree {} predictor, {}x{} to {}x{} blocks, lambda {}",
+            args.matcher, mb_size, mb_size, min_block_size, min_block_size, lambda
+        );
+        let estimator = QuadtreeMotionEstimator::new(matcher, mb_size, min_block_size)
+            .with_lambda(lambda)
+            .with_metric(create_metric(&args.metric).expect("Unknown metric"));
+        estimate_quadtree_motion_fields(pairs, &estimator, visualisation)
+            .expect("Failed to estimate motion fields");
+        return;
+    }
+
+    let levels = args.pyramid_levels();
+    if levels > 1 {
+        info!(" --- Hierarchical {} predictor, {} levels", args.matcher, levels);
+        let mut estimator = HierarchicalMotionEstimator::new(matcher, levels)
+            .with_metric(create_metric(&args.metric).expect("Unknown metric"));
+        if !args.refinement_ranges.is_empty() {
+            info!(" --- Refinement ranges {:?}", args.refinement_ranges);
+            estimator = estimator.with_refinement_ranges(args.refinement_ranges.clone());
+        }
+        estimate_hierarchical_motion_fields(pairs, mb_size, &estimator, export)
+            .expect("Failed to estimate motion fields");
+        return;
+    }
+
+    info!(" --- {} predictor", args.matcher);
+    let refiner = args.subpel.map(|precision| {
+        info!(" --- {:?} pel {:?} refinement", precision, args.filter);
+        SubpixelRefiner::new(precision, args.filter)
+            .with_metric(create_metric(&args.metric).expect("Unknown metric"))
+    });
+    let block_prediction = args.block_position.map(|position| BlockPrediction {
+        position,
+        output_folder: format!("{}/{}_{}", output, position.0, position.1),
+        matcher_name: args.matcher.clone(),
     });
 
-    let prediction_results: Vec<PredictionResult> = prediction_indices_pairs
-        .map(|(anchor_frame_index, target_frame_index)| {
-            let anchor_block = &anchor_blocks[anchor_frame_index];
-            let anchor_frame_id = anchor_frame_index + 1;
-            let (target_frame_id, target_frame) = &frames[target_frame_index];
-
-            debug!(
-                "Matching block from anchor {} to target frame {}",
-                anchor_frame_id, target_frame_id
-            );
-
-            let target_block = matcher.match_block(anchor_block, target_frame);
-
-            export_block(
-                parent_folder,
-                target_block.pixels.as_luma8().unwrap(),
-                &format!("{}_{}", anchor_frame_id, target_frame_id),
-            )
-            .unwrap();
-
-            PredictionResult {
-                anchor_frame_index,
-                target_frame_index,
-                anchor_block,
-                target_block,
+    estimate_motion_fields(
+        pairs,
+        mb_size,
+        matcher.as_ref(),
+        refiner.as_ref(),
+        block_prediction.as_ref(),
+        visualisation,
+        export,
+    )
+    .expect("Failed to estimate motion fields");
+}
+
+fn run_compensate(args: &CompensateArgs) {
+    let matcher = build_matcher(&args.matcher, &args.frames, &args.metric, args.lambda);
+    let output_folder = format!(
+        "{}/compensation/{}",
+        args.frames.output.to_str().unwrap(),
+        args.matcher
+    );
+
+    let loaded_motion = args.motion.as_ref().map(|path| {
+        info!(" --- Motion loaded from {:?}", path);
+        load_motion(path).expect("Failed to load motion fields")
+    });
+    let refiner = args.subpel.map(|precision| {
+        info!(" --- {:?} pel {:?} compensation", precision, args.filter);
+        SubpixelRefiner::new(precision, args.filter)
+            .with_metric(create_metric(&args.metric).expect("Unknown metric"))
+    });
+    let motion_source = match &loaded_motion {
+        Some(loaded_motion) => MotionSource::Loaded(loaded_motion),
+        None => {
+            info!(" --- {} predictor", args.matcher);
+            MotionSource::Estimated {
+                matcher: matcher.as_ref(),
+                mb_size: args.frames.block_size,
+                refiner: refiner.as_ref(),
             }
-        })
-        .collect();
+        }
+    };
 
-    let prediction_errors = prediction_results.iter().map(|prediction| {
-        let anchor_block = prediction.anchor_block;
-        let target_block = &prediction.target_block;
-        let error = calculate_block_prediction_error(&anchor_block.pixels, &target_block.pixels);
+    compensate_pairs(
+        frame_pairs(&args.frames),
+        motion_source,
+        &output_folder,
+        args.gain,
+    )
+    .expect("Failed to compensate frames");
+}
 
-        debug!(
-            "Error between anchor {} and target {}: {}",
-            prediction.anchor_frame_index, prediction.target_frame_index, error
-        );
+fn run_compare(args: &CompareArgs) {
+    let matchers = if args.matchers.is_empty() && args.motion.is_empty() {
+        matcher_names().into_iter().map(str::to_owned).collect()
+    } else {
+        args.matchers.clone()
+    };
+
+    for motion_path in &args.motion {
+        info!(" --- Motion loaded from {:?}", motion_path);
+        let loaded_motion = load_motion(motion_path).expect("Failed to load motion fields");
+
+        compare_motion(
+            frame_pairs(&args.frames),
+            MotionSource::Loaded(&loaded_motion),
+        )
+        .expect("Failed to compensate frames");
+    }
+
+    for matcher_name in &matchers {
+        for metric_name in &args.metrics {
+            info!(" --- {} predictor, {} metric", matcher_name, metric_name);
+            let matcher = build_matcher(matcher_name, &args.frames, metric_name, args.lambda);
+            let motion_source = MotionSource::Estimated {
+                matcher: matcher.as_ref(),
+                mb_size: args.frames.block_size,
+                refiner: None,
+            };
+
+            compare_motion(frame_pairs(&args.frames), motion_source)
+                .expect("Failed to compensate frames");
+        }
+    }
+}
 
-        error
+fn run_flow(args: &FlowArgs) {
+    let output = args.frames.output.to_str().unwrap();
+    let (method_name, estimator): (&str, Box<dyn FlowEstimator>) = match args.method {
+        FlowMethod::LucasKanade => (
+            "lucas_kanade",
+            Box::new(
+                LucasKanadeFlowEstimator::new(args.window_size, args.levels)
+                    .with_iterations(args.iterations.unwrap_or(5)),
+            ),
+        ),
+        FlowMethod::HornSchunck => (
+            "horn_schunck",
+            Box::new(
+                HornSchunckFlowEstimator::new(args.alpha, args.iterations.unwrap_or(100))
+                    .with_levels(args.levels),
+            ),
+        ),
+    };
+
+    let visualisation = args.visualise.then(|| {
+        Visualisation::new(
+            &format!("{}/visualisation/{}", output, method_name),
+            VectorOverlay::new().with_cost_colouring(false),
+            args.frames.search_range as f32,
+            args.animate.then_some(args.frame_delay),
+        )
+        .expect("Failed to create visualisation output")
+    });
+    let export = args.export_flo.then(|| {
+        MotionExport::new(&format!("{}/export/{}", output, method_name), &[ExportFormat::Flo])
+            .expect("Failed to create export output")
     });
 
-    info!(" Average error: {}", prediction_errors
-        .sum::<f64>()
-        .div(prediction_results.len() as f64));
+    info!(" --- {} dense flow", method_name);
+    estimate_flow_fields(
+        frame_pairs(&args.frames),
+        estimator.as_ref(),
+        args.frames.block_size,
+        visualisation,
+        export,
+    )
+    .expect("Failed to estimate flow fields");
+}
+
+fn run_references(args: &ReferencesArgs) {
+    let matcher = build_matcher(&args.matcher, &args.frames, &args.metric, args.lambda);
+    let estimator = MultiReferenceMotionEstimator::new(matcher)
+        .with_metric(create_metric(&args.metric).expect("Unknown metric"))
+        .with_lambda(args.lambda);
+    let future_references = args.bidirectional as usize;
+
+    info!(
+        " --- {} predictor, {} past and {} future references",
+        args.matcher, args.references, future_references
+    );
+
+    let windows = open_reference_windows(
+        &args.frames.input,
+        args.frames.raw_format(),
+        args.references,
+        future_references,
+    )
+    .unwrap_or_else(|error| panic!("Failed to open {:?}: {}", args.frames.input, error));
+    let output_folder = format!(
+        "{}/references/{}",
+        args.frames.output.to_str().unwrap(),
+        args.matcher
+    );
+
+    estimate_reference_motion_fields(
+        windows,
+        &estimator,
+        args.frames.block_size,
+        &output_folder,
+    )
+    .expect("Failed to estimate reference motion fields");
 }
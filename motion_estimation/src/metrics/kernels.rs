@@ -0,0 +1,392 @@
+use crate::plane::LumaPlane;
+
+// Integer SAD and SSD over borrowed planes. On x86_64 the rows are processed with SSE2, which
+// is always available there, or with AVX2 when the CPU supports it. Both paths accumulate
+// exact integers, so they agree with the scalar kernels bit for bit.
+
+pub fn sad(anchor_block: &LumaPlane, target_block: &LumaPlane) -> u64 {
+    bounded_sad(anchor_block, target_block, u64::MAX)
+}
+
+// Partial distortion elimination: the sum is checked after every row and the candidate is
+// abandoned as soon as it exceeds the limit
+pub fn sad_within(anchor_block: &LumaPlane, target_block: &LumaPlane, limit: u64) -> Option<u64> {
+    Some(bounded_sad(anchor_block, target_block, limit)).filter(|sad| *sad <= limit)
+}
+
+fn bounded_sad(anchor_block: &LumaPlane, target_block: &LumaPlane, limit: u64) -> u64 {
+    assert_eq!(anchor_block.dimensions(), target_block.dimensions());
+
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx2") {
+            return unsafe { x86::sad_avx2(anchor_block, target_block, limit) };
+        }
+        unsafe { x86::sad_sse2(anchor_block, target_block, limit) }
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    sad_rows(anchor_block, target_block, limit)
+}
+
+pub fn ssd(anchor_block: &LumaPlane, target_block: &LumaPlane) -> u64 {
+    assert_eq!(anchor_block.dimensions(), target_block.dimensions());
+
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx2") {
+            return unsafe { x86::ssd_avx2(anchor_block, target_block) };
+        }
+        unsafe { x86::ssd_sse2(anchor_block, target_block) }
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    ssd_scalar(anchor_block, target_block)
+}
+
+pub fn sad_scalar(anchor_block: &LumaPlane, target_block: &LumaPlane) -> u64 {
+    sad_rows(anchor_block, target_block, u64::MAX)
+}
+
+fn sad_rows(anchor_block: &LumaPlane, target_block: &LumaPlane, limit: u64) -> u64 {
+    let mut total = 0;
+
+    for (anchor_row, target_row) in anchor_block.rows().zip(target_block.rows()) {
+        total += sad_row(anchor_row, target_row);
+        if total > limit {
+            break;
+        }
+    }
+
+    total
+}
+
+pub fn ssd_scalar(anchor_block: &LumaPlane, target_block: &LumaPlane) -> u64 {
+    anchor_block
+        .rows()
+        .zip(target_block.rows())
+        .map(|(anchor_row, target_row)| ssd_row(anchor_row, target_row))
+        .sum()
+}
+
+fn sad_row(anchor_row: &[u8], target_row: &[u8]) -> u64 {
+    anchor_row
+        .iter()
+        .zip(target_row)
+        .map(|(anchor_pixel, target_pixel)| anchor_pixel.abs_diff(*target_pixel) as u64)
+        .sum()
+}
+
+fn ssd_row(anchor_row: &[u8], target_row: &[u8]) -> u64 {
+    anchor_row
+        .iter()
+        .zip(target_row)
+        .map(|(anchor_pixel, target_pixel)| {
+            let difference = anchor_pixel.abs_diff(*target_pixel) as u64;
+            difference * difference
+        })
+        .sum()
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use std::arch::x86_64::*;
+
+    use super::{sad_row, ssd_row};
+    use crate::plane::LumaPlane;
+
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn sad_sse2(anchor_block: &LumaPlane, target_block: &LumaPlane, limit: u64) -> u64 {
+        let chunks = anchor_block.width() as usize / 16;
+        let mut total = 0;
+
+        for (anchor_row, target_row) in anchor_block.rows().zip(target_block.rows()) {
+            let mut sums = _mm_setzero_si128();
+            for chunk in 0..chunks {
+                let anchor = _mm_loadu_si128(anchor_row.as_ptr().add(16 * chunk) as *const __m128i);
+                let target = _mm_loadu_si128(target_row.as_ptr().add(16 * chunk) as *const __m128i);
+                sums = _mm_add_epi64(sums, _mm_sad_epu8(anchor, target));
+            }
+
+            total += horizontal_sum_epi64(sums)
+                + sad_row(&anchor_row[16 * chunks..], &target_row[16 * chunks..]);
+            if total > limit {
+                break;
+            }
+        }
+
+        total
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn sad_avx2(anchor_block: &LumaPlane, target_block: &LumaPlane, limit: u64) -> u64 {
+        let chunks = anchor_block.width() as usize / 32;
+        let mut total = 0;
+
+        for (anchor_row, target_row) in anchor_block.rows().zip(target_block.rows()) {
+            let mut sums = _mm256_setzero_si256();
+            for chunk in 0..chunks {
+                let anchor =
+                    _mm256_loadu_si256(anchor_row.as_ptr().add(32 * chunk) as *const __m256i);
+                let target =
+                    _mm256_loadu_si256(target_row.as_ptr().add(32 * chunk) as *const __m256i);
+                sums = _mm256_add_epi64(sums, _mm256_sad_epu8(anchor, target));
+            }
+
+            // A 16 pixels remainder, e.g. the whole row of a 16x16 block, still uses SSE2
+            let mut remainder = 32 * chunks;
+            let mut half_sums = _mm_add_epi64(
+                _mm256_castsi256_si128(sums),
+                _mm256_extracti128_si256::<1>(sums),
+            );
+            if anchor_row.len() - remainder >= 16 {
+                let anchor = _mm_loadu_si128(anchor_row.as_ptr().add(remainder) as *const __m128i);
+                let target = _mm_loadu_si128(target_row.as_ptr().add(remainder) as *const __m128i);
+                half_sums = _mm_add_epi64(half_sums, _mm_sad_epu8(anchor, target));
+                remainder += 16;
+            }
+
+            total += horizontal_sum_epi64(half_sums)
+                + sad_row(&anchor_row[remainder..], &target_row[remainder..]);
+            if total > limit {
+                break;
+            }
+        }
+
+        total
+    }
+
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn ssd_sse2(anchor_block: &LumaPlane, target_block: &LumaPlane) -> u64 {
+        let chunks = anchor_block.width() as usize / 16;
+        let zero = _mm_setzero_si128();
+        let mut total = 0;
+
+        for (anchor_row, target_row) in anchor_block.rows().zip(target_block.rows()) {
+            // Each 32-bit lane gains at most 4 * 255^2 per chunk, so rows shorter than 130k
+            // pixels cannot overflow it
+            let mut sums = _mm_setzero_si128();
+            for chunk in 0..chunks {
+                let anchor = _mm_loadu_si128(anchor_row.as_ptr().add(16 * chunk) as *const __m128i);
+                let target = _mm_loadu_si128(target_row.as_ptr().add(16 * chunk) as *const __m128i);
+
+                let low = _mm_sub_epi16(
+                    _mm_unpacklo_epi8(anchor, zero),
+                    _mm_unpacklo_epi8(target, zero),
+                );
+                let high = _mm_sub_epi16(
+                    _mm_unpackhi_epi8(anchor, zero),
+                    _mm_unpackhi_epi8(target, zero),
+                );
+                sums = _mm_add_epi32(sums, _mm_madd_epi16(low, low));
+                sums = _mm_add_epi32(sums, _mm_madd_epi16(high, high));
+            }
+
+            total += horizontal_sum_epi32(sums)
+                + ssd_row(&anchor_row[16 * chunks..], &target_row[16 * chunks..]);
+        }
+
+        total
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn ssd_avx2(anchor_block: &LumaPlane, target_block: &LumaPlane) -> u64 {
+        let chunks = anchor_block.width() as usize / 16;
+        let mut total = 0;
+
+        for (anchor_row, target_row) in anchor_block.rows().zip(target_block.rows()) {
+            let mut sums = _mm256_setzero_si256();
+            for chunk in 0..chunks {
+                let anchor = _mm256_cvtepu8_epi16(_mm_loadu_si128(
+                    anchor_row.as_ptr().add(16 * chunk) as *const __m128i,
+                ));
+                let target = _mm256_cvtepu8_epi16(_mm_loadu_si128(
+                    target_row.as_ptr().add(16 * chunk) as *const __m128i,
+                ));
+
+                let differences = _mm256_sub_epi16(anchor, target);
+                sums = _mm256_add_epi32(sums, _mm256_madd_epi16(differences, differences));
+            }
+
+            let half_sums = _mm_add_epi32(
+                _mm256_castsi256_si128(sums),
+                _mm256_extracti128_si256::<1>(sums),
+            );
+            total += horizontal_sum_epi32(half_sums)
+                + ssd_row(&anchor_row[16 * chunks..], &target_row[16 * chunks..]);
+        }
+
+        total
+    }
+
+    #[target_feature(enable = "sse2")]
+    unsafe fn horizontal_sum_epi64(sums: __m128i) -> u64 {
+        let mut lanes = [0u64; 2];
+        _mm_storeu_si128(lanes.as_mut_ptr() as *mut __m128i, sums);
+        lanes[0] + lanes[1]
+    }
+
+    #[target_feature(enable = "sse2")]
+    unsafe fn horizontal_sum_epi32(sums: __m128i) -> u64 {
+        let mut lanes = [0u32; 4];
+        _mm_storeu_si128(lanes.as_mut_ptr() as *mut __m128i, sums);
+        lanes.iter().map(|lane| *lane as u64).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::GrayImage;
+
+    use super::*;
+
+    // Widths around the 16 and 32 pixel vector lengths
+    const WIDTHS: [u32; 7] = [1, 15, 16, 17, 31, 32, 33];
+
+    type Kernel = Box<dyn Fn(&LumaPlane, &LumaPlane) -> u64>;
+
+    // The scalar kernels and every vector path this CPU supports, the others being skipped
+    fn kernels() -> Vec<(&'static str, Kernel, Kernel)> {
+        #[allow(unused_mut)]
+        let mut kernels: Vec<(&'static str, Kernel, Kernel)> = vec![(
+            "scalar",
+            Box::new(|anchor: &LumaPlane, target: &LumaPlane| sad_scalar(anchor, target)),
+            Box::new(|anchor: &LumaPlane, target: &LumaPlane| ssd_scalar(anchor, target)),
+        )];
+
+        #[cfg(target_arch = "x86_64")]
+        {
+            kernels.push((
+                "sse2",
+                Box::new(|anchor: &LumaPlane, target: &LumaPlane| unsafe {
+                    x86::sad_sse2(anchor, target, u64::MAX)
+                }),
+                Box::new(|anchor: &LumaPlane, target: &LumaPlane| unsafe {
+                    x86::ssd_sse2(anchor, target)
+                }),
+            ));
+
+            if is_x86_feature_detected!("avx2") {
+                kernels.push((
+                    "avx2",
+                    Box::new(|anchor: &LumaPlane, target: &LumaPlane| unsafe {
+                        x86::sad_avx2(anchor, target, u64::MAX)
+                    }),
+                    Box::new(|anchor: &LumaPlane, target: &LumaPlane| unsafe {
+                        x86::ssd_avx2(anchor, target)
+                    }),
+                ));
+            } else {
+                eprintln!("AVX2 is not supported by this CPU, skipping its kernels");
+            }
+        }
+
+        kernels
+    }
+
+    fn noise(width: u32, height: u32, seed: u32) -> GrayImage {
+        let mut state = seed;
+        GrayImage::from_fn(width, height, |_, _| {
+            state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            image::Luma([(state >> 24) as u8])
+        })
+    }
+
+    fn assert_kernels_agree(anchor_block: &LumaPlane, target_block: &LumaPlane) {
+        let expected = (
+            sad_scalar(anchor_block, target_block),
+            ssd_scalar(anchor_block, target_block),
+        );
+
+        for (name, sad_kernel, ssd_kernel) in kernels() {
+            assert_eq!(
+                (
+                    sad_kernel(anchor_block, target_block),
+                    ssd_kernel(anchor_block, target_block)
+                ),
+                expected,
+                "{} kernels on {:?} blocks",
+                name,
+                anchor_block.dimensions()
+            );
+        }
+        assert_eq!(
+            (
+                sad(anchor_block, target_block),
+                ssd(anchor_block, target_block)
+            ),
+            expected
+        );
+    }
+
+    #[test]
+    fn vector_kernels_match_the_scalar_ones() {
+        for width in WIDTHS {
+            let (anchor_frame, target_frame) =
+                (noise(width, 3, width), noise(width, 3, 99 * width));
+
+            assert_kernels_agree(
+                &LumaPlane::from_image(&anchor_frame),
+                &LumaPlane::from_image(&target_frame),
+            );
+        }
+    }
+
+    #[test]
+    fn strided_views_only_read_their_region() {
+        let (anchor_frame, target_frame) = (noise(80, 12, 1), noise(80, 12, 2));
+        let (anchor_plane, target_plane) = (
+            LumaPlane::from_image(&anchor_frame),
+            LumaPlane::from_image(&target_frame),
+        );
+
+        for width in WIDTHS {
+            // Views reaching the right and bottom edges of their frames
+            let anchor_block = anchor_plane.view(3, 2, width, 5).unwrap();
+            let target_block = target_plane.view(80 - width as i32, 7, width, 5).unwrap();
+
+            assert_eq!(anchor_block.stride(), 80);
+            assert_kernels_agree(&anchor_block, &target_block);
+        }
+    }
+
+    #[test]
+    fn extreme_differences_do_not_saturate() {
+        for width in WIDTHS {
+            let black = GrayImage::new(width, 4);
+            let white = GrayImage::from_pixel(width, 4, image::Luma([255]));
+            let (black, white) = (LumaPlane::from_image(&black), LumaPlane::from_image(&white));
+            let pixels = 4 * width as u64;
+
+            for (name, sad_kernel, ssd_kernel) in kernels() {
+                for (anchor_block, target_block) in [(&black, &white), (&white, &black)] {
+                    assert_eq!(
+                        sad_kernel(anchor_block, target_block),
+                        255 * pixels,
+                        "{}",
+                        name
+                    );
+                    assert_eq!(
+                        ssd_kernel(anchor_block, target_block),
+                        255 * 255 * pixels,
+                        "{}",
+                        name
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn partial_sums_stop_beyond_the_limit() {
+        let (anchor_frame, target_frame) = (noise(33, 6, 3), noise(33, 6, 4));
+        let (anchor_block, target_block) = (
+            LumaPlane::from_image(&anchor_frame),
+            LumaPlane::from_image(&target_frame),
+        );
+        let total = sad_scalar(&anchor_block, &target_block);
+
+        assert_eq!(sad_within(&anchor_block, &target_block, total), Some(total));
+        assert_eq!(sad_within(&anchor_block, &target_block, total - 1), None);
+    }
+}
@@ -0,0 +1,50 @@
+use image::DynamicImage;
+
+use crate::plane::{luma_frame, LumaPlane};
+
+pub mod kernels;
+pub mod ncc;
+pub mod sad;
+pub mod satd;
+pub mod ssd;
+
+pub trait DistortionMetric: Send + Sync {
+    fn calculate_error(&self, anchor_block: &DynamicImage, target_block: &DynamicImage) -> f64;
+
+    // Metrics without an integer kernel copy the views into images
+    fn calculate_plane_error(&self, anchor_block: &LumaPlane, target_block: &LumaPlane) -> f64 {
+        self.calculate_error(
+            &DynamicImage::ImageLuma8(anchor_block.to_image()),
+            &DynamicImage::ImageLuma8(target_block.to_image()),
+        )
+    }
+
+    // Metrics that grow with the SAD of the blocks can be searched with SAD based pruning
+    fn cost_from_sad(&self, _sad: u64, _area: u32) -> Option<f64> {
+        None
+    }
+}
+
+fn plane_error(
+    metric: &dyn DistortionMetric,
+    anchor_block: &DynamicImage,
+    target_block: &DynamicImage,
+) -> f64 {
+    let (anchor_block, target_block) = (luma_frame(anchor_block), luma_frame(target_block));
+
+    metric.calculate_plane_error(
+        &LumaPlane::from_image(&anchor_block),
+        &LumaPlane::from_image(&target_block),
+    )
+}
+
+fn pixel_differences(anchor_block: &DynamicImage, target_block: &DynamicImage) -> Vec<i32> {
+    let anchor_pixels = anchor_block.to_luma8().into_raw();
+    let target_pixels = target_block.to_luma8().into_raw();
+
+    anchor_pixels
+        .into_iter()
+        .zip(target_pixels)
+        .map(|(anchor_pixel, target_pixel)| anchor_pixel as i32 - target_pixel as i32)
+        .collect()
+}
@@ -0,0 +1,89 @@
+use image::DynamicImage;
+
+use super::DistortionMetric;
+
+#[derive(Default)]
+pub struct NccMetric {}
+
+impl NccMetric {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl DistortionMetric for NccMetric {
+    fn calculate_error(&self, anchor_block: &DynamicImage, target_block: &DynamicImage) -> f64 {
+        let anchor_pixels = anchor_block.to_luma8().into_raw();
+        let target_pixels = target_block.to_luma8().into_raw();
+        let pixels_count = anchor_pixels.len() as f64;
+
+        let anchor_mean = anchor_pixels.iter().map(|p| *p as f64).sum::<f64>() / pixels_count;
+        let target_mean = target_pixels.iter().map(|p| *p as f64).sum::<f64>() / pixels_count;
+
+        let (covariance, anchor_variance, target_variance) = anchor_pixels
+            .iter()
+            .zip(target_pixels.iter())
+            .map(|(anchor_pixel, target_pixel)| {
+                (
+                    *anchor_pixel as f64 - anchor_mean,
+                    *target_pixel as f64 - target_mean,
+                )
+            })
+            .fold(
+                (0.0, 0.0, 0.0),
+                |(covariance, anchor_variance, target_variance), (a, t)| {
+                    (
+                        covariance + a * t,
+                        anchor_variance + a * a,
+                        target_variance + t * t,
+                    )
+                },
+            );
+
+        // Flat blocks carry no structure: they only match other flat blocks
+        let correlation = if anchor_variance == 0.0 || target_variance == 0.0 {
+            if anchor_variance == target_variance {
+                1.0
+            } else {
+                0.0
+            }
+        } else {
+            covariance / f64::sqrt(anchor_variance * target_variance)
+        };
+
+        1.0 - correlation
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::block;
+
+    const PIXELS: [u8; 4] = [10, 40, 20, 90];
+
+    fn error(anchor_pixels: Vec<u8>, target_pixels: Vec<u8>) -> f64 {
+        NccMetric::new().calculate_error(&block(2, 2, anchor_pixels), &block(2, 2, target_pixels))
+    }
+
+    #[test]
+    fn ignores_gain_and_offset() {
+        let brightened = PIXELS.iter().map(|pixel| 2 * pixel + 30).collect();
+
+        assert!(error(PIXELS.to_vec(), PIXELS.to_vec()).abs() < 1e-12);
+        assert!(error(PIXELS.to_vec(), brightened).abs() < 1e-12);
+    }
+
+    #[test]
+    fn inverted_blocks_have_the_largest_error() {
+        let inverted = PIXELS.iter().map(|pixel| 255 - pixel).collect();
+
+        assert!((error(PIXELS.to_vec(), inverted) - 2.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn flat_blocks_only_match_flat_blocks() {
+        assert_eq!(error(vec![7; 4], vec![200; 4]), 0.0);
+        assert_eq!(error(vec![7; 4], PIXELS.to_vec()), 1.0);
+    }
+}
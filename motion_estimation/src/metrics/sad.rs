@@ -0,0 +1,78 @@
+use image::DynamicImage;
+
+use super::{kernels::sad, plane_error, DistortionMetric};
+use crate::plane::LumaPlane;
+
+#[derive(Default)]
+pub struct SadMetric {}
+
+impl SadMetric {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl DistortionMetric for SadMetric {
+    fn calculate_error(&self, anchor_block: &DynamicImage, target_block: &DynamicImage) -> f64 {
+        plane_error(self, anchor_block, target_block)
+    }
+
+    fn calculate_plane_error(&self, anchor_block: &LumaPlane, target_block: &LumaPlane) -> f64 {
+        sad(anchor_block, target_block) as f64
+    }
+
+    fn cost_from_sad(&self, sad: u64, _area: u32) -> Option<f64> {
+        Some(sad as f64)
+    }
+}
+
+#[derive(Default)]
+pub struct MadMetric {}
+
+impl MadMetric {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl DistortionMetric for MadMetric {
+    fn calculate_error(&self, anchor_block: &DynamicImage, target_block: &DynamicImage) -> f64 {
+        plane_error(self, anchor_block, target_block)
+    }
+
+    fn calculate_plane_error(&self, anchor_block: &LumaPlane, target_block: &LumaPlane) -> f64 {
+        let (width, height) = anchor_block.dimensions();
+        sad(anchor_block, target_block) as f64 / (width * height) as f64
+    }
+
+    fn cost_from_sad(&self, sad: u64, area: u32) -> Option<f64> {
+        Some(sad as f64 / area as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::block;
+
+    #[test]
+    fn sums_and_averages_absolute_differences() {
+        let anchor_block = block(2, 2, vec![10, 20, 30, 40]);
+        let target_block = block(2, 2, vec![12, 15, 30, 50]);
+
+        assert_eq!(
+            SadMetric::new().calculate_error(&anchor_block, &target_block),
+            17.0
+        );
+        assert_eq!(
+            MadMetric::new().calculate_error(&anchor_block, &target_block),
+            4.25
+        );
+    }
+
+    #[test]
+    fn costs_follow_from_the_sad() {
+        assert_eq!(SadMetric::new().cost_from_sad(34, 4), Some(34.0));
+        assert_eq!(MadMetric::new().cost_from_sad(34, 4), Some(8.5));
+    }
+}
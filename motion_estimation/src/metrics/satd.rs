@@ -0,0 +1,139 @@
+use image::{DynamicImage, GenericImageView};
+use itertools::Itertools;
+
+use super::{pixel_differences, DistortionMetric};
+
+pub struct SatdMetric {
+    transform_size: usize,
+}
+
+impl SatdMetric {
+    pub fn new(transform_size: usize) -> Self {
+        assert!(
+            transform_size == 4 || transform_size == 8,
+            "SATD transform size must be 4 or 8"
+        );
+
+        Self { transform_size }
+    }
+}
+
+impl DistortionMetric for SatdMetric {
+    fn calculate_error(&self, anchor_block: &DynamicImage, target_block: &DynamicImage) -> f64 {
+        let (width, height) = anchor_block.dimensions();
+        let (width, height) = (width as usize, height as usize);
+        let size = self.transform_size;
+        let differences = pixel_differences(anchor_block, target_block);
+
+        // Border pixels not covered by a whole transform fall back to SAD
+        let (covered_width, covered_height) = (width - width % size, height - height % size);
+        let uncovered_error: i64 = (0..height)
+            .cartesian_product(0..width)
+            .filter(|(row, col)| *row >= covered_height || *col >= covered_width)
+            .map(|(row, col)| differences[row * width + col].abs() as i64)
+            .sum();
+
+        let transformed_error: i64 = (0..covered_height)
+            .step_by(size)
+            .cartesian_product((0..covered_width).step_by(size))
+            .map(|(block_row, block_col)| {
+                let mut coefficients: Vec<i64> = (0..size)
+                    .cartesian_product(0..size)
+                    .map(|(row, col)| {
+                        differences[(block_row + row) * width + block_col + col] as i64
+                    })
+                    .collect();
+
+                hadamard_2d(&mut coefficients, size);
+
+                coefficients
+                    .iter()
+                    .map(|coefficient| coefficient.abs())
+                    .sum::<i64>()
+            })
+            .sum();
+
+        // Same scaling as the H.264 reference encoder: 1/2 for 4x4, 1/4 for 8x8
+        transformed_error as f64 / (size / 2) as f64 + uncovered_error as f64
+    }
+}
+
+fn hadamard_2d(coefficients: &mut [i64], size: usize) {
+    for row in 0..size {
+        hadamard_1d(coefficients, row * size, 1, size);
+    }
+
+    for col in 0..size {
+        hadamard_1d(coefficients, col, size, size);
+    }
+}
+
+fn hadamard_1d(coefficients: &mut [i64], start: usize, stride: usize, size: usize) {
+    let mut half = 1;
+
+    while half < size {
+        for group in (0..size).step_by(half * 2) {
+            for i in group..group + half {
+                let first = start + i * stride;
+                let second = start + (i + half) * stride;
+
+                let (a, b) = (coefficients[first], coefficients[second]);
+                coefficients[first] = a + b;
+                coefficients[second] = a - b;
+            }
+        }
+
+        half *= 2;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::block;
+
+    #[test]
+    fn constant_differences_only_hit_the_dc_coefficient() {
+        let anchor_block = block(8, 8, vec![13; 64]);
+        let target_block = block(8, 8, vec![10; 64]);
+
+        // Four 4x4 transforms with a DC of 16 * 3, halved
+        assert_eq!(
+            SatdMetric::new(4).calculate_error(&anchor_block, &target_block),
+            96.0
+        );
+        // One 8x8 transform with a DC of 64 * 3, quartered
+        assert_eq!(
+            SatdMetric::new(8).calculate_error(&anchor_block, &target_block),
+            48.0
+        );
+    }
+
+    #[test]
+    fn impulses_spread_over_all_coefficients() {
+        let mut pixels = vec![0; 16];
+        pixels[5] = 2;
+
+        let satd =
+            SatdMetric::new(4).calculate_error(&block(4, 4, pixels), &block(4, 4, vec![0; 16]));
+        assert_eq!(satd, 16.0);
+    }
+
+    #[test]
+    fn uncovered_border_pixels_use_sad() {
+        let anchor_block = block(5, 4, vec![1; 20]);
+        let target_block = block(5, 4, vec![0; 20]);
+
+        // 8 for the 4x4 transform and 4 for the last column
+        assert_eq!(
+            SatdMetric::new(4).calculate_error(&anchor_block, &target_block),
+            12.0
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "must be 4 or 8")]
+    fn rejects_other_transform_sizes() {
+        SatdMetric::new(16);
+    }
+}
@@ -0,0 +1,69 @@
+use image::DynamicImage;
+
+use super::{kernels::ssd, plane_error, DistortionMetric};
+use crate::plane::LumaPlane;
+
+#[derive(Default)]
+pub struct SsdMetric {}
+
+impl SsdMetric {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl DistortionMetric for SsdMetric {
+    fn calculate_error(&self, anchor_block: &DynamicImage, target_block: &DynamicImage) -> f64 {
+        plane_error(self, anchor_block, target_block)
+    }
+
+    fn calculate_plane_error(&self, anchor_block: &LumaPlane, target_block: &LumaPlane) -> f64 {
+        ssd(anchor_block, target_block) as f64
+    }
+}
+
+#[derive(Default)]
+pub struct MseMetric {}
+
+impl MseMetric {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl DistortionMetric for MseMetric {
+    fn calculate_error(&self, anchor_block: &DynamicImage, target_block: &DynamicImage) -> f64 {
+        plane_error(self, anchor_block, target_block)
+    }
+
+    fn calculate_plane_error(&self, anchor_block: &LumaPlane, target_block: &LumaPlane) -> f64 {
+        let (width, height) = anchor_block.dimensions();
+        ssd(anchor_block, target_block) as f64 / (width * height) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::block;
+
+    #[test]
+    fn sums_and_averages_squared_differences() {
+        let anchor_block = block(2, 2, vec![10, 20, 30, 40]);
+        let target_block = block(2, 2, vec![12, 15, 30, 50]);
+
+        assert_eq!(
+            SsdMetric::new().calculate_error(&anchor_block, &target_block),
+            129.0
+        );
+        assert_eq!(
+            MseMetric::new().calculate_error(&anchor_block, &target_block),
+            32.25
+        );
+    }
+
+    #[test]
+    fn ssd_has_no_sad_cost() {
+        assert_eq!(SsdMetric::new().cost_from_sad(10, 4), None);
+    }
+}
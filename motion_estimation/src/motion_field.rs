@@ -0,0 +1,319 @@
+use image::{DynamicImage, GenericImageView};
+use log::debug;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bma::BlockMatcher,
+    plane::{luma_frame, LumaPlane},
+    rate::vector_bits,
+    subpel::{FractionalMotionVector, SubpixelRefiner},
+    types::{BlockMotion, MotionVector, SearchContext},
+    utils::tile_frame,
+};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MotionField {
+    pub mb_size: u32,
+    pub cols: u32,
+    pub rows: u32,
+    pub blocks: Vec<BlockMotion>,
+}
+
+impl MotionField {
+    pub fn estimate(
+        anchor_frame: &DynamicImage,
+        target_frame: &DynamicImage,
+        mb_size: u32,
+        matcher: &dyn BlockMatcher,
+    ) -> Self {
+        let cols = anchor_frame.width().div_ceil(mb_size);
+        let rows = anchor_frame.height().div_ceil(mb_size);
+
+        let anchor_blocks = tile_frame(anchor_frame, mb_size);
+        let target_frame = luma_frame(target_frame);
+        let target_plane = LumaPlane::from_image(&target_frame);
+        let mut blocks: Vec<Option<BlockMotion>> = vec![None; anchor_blocks.len()];
+
+        // A block depends on its left, top and top right neighbours, so blocks on the same
+        // wavefront col + 2 * row are independent and matched in parallel. Results do not
+        // depend on the number of threads.
+        for wavefront in 0..(cols + 2 * rows).saturating_sub(2) {
+            let wavefront_blocks: Vec<(u32, u32)> = (0..rows)
+                .filter_map(|row| {
+                    wavefront
+                        .checked_sub(2 * row)
+                        .filter(|col| *col < cols)
+                        .map(|col| (col, row))
+                })
+                .collect();
+
+            let motions: Vec<(usize, BlockMotion)> = wavefront_blocks
+                .into_par_iter()
+                .map(|(col, row)| {
+                    let index = (row * cols + col) as usize;
+                    let anchor_block = &anchor_blocks[index];
+                    let context = causal_context(col, row, cols, |col, row| {
+                        blocks[(row * cols + col) as usize]
+                            .as_ref()
+                            .map(|b| b.vector)
+                    });
+
+                    let vector = matcher.match_block(anchor_block, &target_plane, &context);
+
+                    let motion = BlockMotion {
+                        x_offset: anchor_block.x_offset,
+                        y_offset: anchor_block.y_offset,
+                        width: anchor_block.pixels.width(),
+                        height: anchor_block.pixels.height(),
+                        vector,
+                    };
+
+                    debug!(
+                        "Block ({}, {}): vector ({}, {}), cost {}",
+                        motion.x_offset, motion.y_offset, vector.dx, vector.dy, vector.cost
+                    );
+
+                    (index, motion)
+                })
+                .collect();
+
+            for (index, motion) in motions {
+                blocks[index] = Some(motion);
+            }
+        }
+
+        let blocks = blocks.into_iter().map(Option::unwrap).collect();
+
+        Self {
+            mb_size,
+            cols,
+            rows,
+            blocks,
+        }
+    }
+
+    // Rebuilds a field from raster ordered blocks, e.g. when reloading exported vectors
+    pub fn from_blocks(blocks: Vec<BlockMotion>) -> Self {
+        let mb_size = blocks.iter().map(|block| block.width).max().unwrap_or(0);
+        let cols = blocks
+            .iter()
+            .take_while(|block| block.y_offset == blocks[0].y_offset)
+            .count() as u32;
+        let rows = (blocks.len() as u32).checked_div(cols).unwrap_or(0);
+
+        Self {
+            mb_size,
+            cols,
+            rows,
+            blocks,
+        }
+    }
+
+    pub fn refine_subpixel(
+        &self,
+        anchor_frame: &DynamicImage,
+        target_frame: &DynamicImage,
+        refiner: &SubpixelRefiner,
+    ) -> Vec<FractionalMotionVector> {
+        tile_frame(anchor_frame, self.mb_size)
+            .par_iter()
+            .zip(self.blocks.par_iter())
+            .map(|(anchor_block, motion)| {
+                refiner.refine(anchor_block, target_frame, &motion.vector)
+            })
+            .collect()
+    }
+
+    pub fn block(&self, col: u32, row: u32) -> &BlockMotion {
+        &self.blocks[(row * self.cols + col) as usize]
+    }
+
+    // Bits of the vector differences to their median predictions, as coded in raster order
+    pub fn average_vector_bits(&self) -> f64 {
+        let total_bits: u32 = self
+            .blocks
+            .iter()
+            .enumerate()
+            .map(|(index, block)| {
+                let (col, row) = (index as u32 % self.cols, index as u32 / self.cols);
+                let context = causal_context(col, row, self.cols, |col, row| {
+                    Some(self.block(col, row).vector)
+                });
+
+                vector_bits(
+                    (block.vector.dx, block.vector.dy),
+                    context.median_predictor().unwrap_or((0, 0)),
+                )
+            })
+            .sum();
+
+        total_bits as f64 / self.blocks.len() as f64
+    }
+
+    pub fn average_cost(&self) -> f64 {
+        // Partial border blocks are weighted by their area
+        let (weighted_cost, area) = self.blocks.iter().fold((0.0, 0.0), |(cost, area), block| {
+            let block_area = (block.width * block.height) as f64;
+            (cost + block.vector.cost * block_area, area + block_area)
+        });
+
+        weighted_cost / area
+    }
+}
+
+fn causal_context(
+    col: u32,
+    row: u32,
+    cols: u32,
+    neighbour: impl Fn(u32, u32) -> Option<MotionVector>,
+) -> SearchContext {
+    SearchContext {
+        left: col
+            .checked_sub(1)
+            .and_then(|left_col| neighbour(left_col, row)),
+        top: row
+            .checked_sub(1)
+            .and_then(|top_row| neighbour(col, top_row)),
+        top_right: row
+            .checked_sub(1)
+            .filter(|_| col + 1 < cols)
+            .and_then(|top_row| neighbour(col + 1, top_row)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bma::{
+            arps::{AdaptiveRoodPatternBlockMatcher, PredictorMode},
+            exhaustive::ExhaustiveBlockMatcher,
+        },
+        test_utils::{shifted_pair, textured_frame},
+    };
+
+    #[test]
+    fn blocks_follow_a_global_shift() {
+        let (anchor_frame, target_frame) = shifted_pair(64, 48, (3, -2));
+        let motion_field = MotionField::estimate(
+            &anchor_frame,
+            &target_frame,
+            16,
+            &ExhaustiveBlockMatcher::new(7),
+        );
+
+        assert_eq!((motion_field.cols, motion_field.rows), (4, 3));
+        // Blocks whose match would leave the frame cannot follow the shift
+        for block in motion_field
+            .blocks
+            .iter()
+            .filter(|block| block.x_offset + 3 + block.width <= 64 && block.y_offset >= 2)
+        {
+            assert_eq!((block.vector.dx, block.vector.dy), (3, -2));
+            assert_eq!(block.vector.cost, 0.0);
+        }
+    }
+
+    #[test]
+    fn border_blocks_are_cropped_to_the_frame() {
+        let (anchor_frame, target_frame) = shifted_pair(40, 36, (0, 0));
+        let motion_field = MotionField::estimate(
+            &anchor_frame,
+            &target_frame,
+            16,
+            &ExhaustiveBlockMatcher::new(2),
+        );
+
+        assert_eq!((motion_field.cols, motion_field.rows), (3, 3));
+        let last_block = motion_field.block(2, 2);
+        assert_eq!(
+            (
+                last_block.x_offset,
+                last_block.y_offset,
+                last_block.width,
+                last_block.height
+            ),
+            (32, 32, 8, 4)
+        );
+        assert!(motion_field
+            .blocks
+            .iter()
+            .all(|block| block.vector.cost == 0.0));
+    }
+
+    #[test]
+    fn fields_are_rebuilt_from_their_blocks() {
+        let (anchor_frame, target_frame) = shifted_pair(40, 36, (1, 1));
+        let motion_field = MotionField::estimate(
+            &anchor_frame,
+            &target_frame,
+            16,
+            &ExhaustiveBlockMatcher::new(2),
+        );
+
+        assert_eq!(
+            MotionField::from_blocks(motion_field.blocks.clone()),
+            motion_field
+        );
+    }
+
+    #[test]
+    fn average_cost_is_weighted_by_block_area() {
+        let block = |width, cost| BlockMotion {
+            x_offset: 0,
+            y_offset: 0,
+            width,
+            height: 4,
+            vector: MotionVector {
+                dx: 0,
+                dy: 0,
+                cost,
+                points_evaluated: 1,
+                points_pruned: 0,
+            },
+        };
+        let motion_field = MotionField {
+            mb_size: 12,
+            cols: 2,
+            rows: 1,
+            blocks: vec![block(12, 1.0), block(4, 5.0)],
+        };
+
+        assert_eq!(motion_field.average_cost(), 2.0);
+    }
+
+    #[test]
+    fn fields_do_not_depend_on_the_thread_count() {
+        let anchor_frame = textured_frame(96, 80, (0.0, 0.0));
+        let target_frame = textured_frame(96, 80, (3.5, -2.0));
+        // Median predictors make every block depend on its causal neighbours
+        let matcher = AdaptiveRoodPatternBlockMatcher::new(8)
+            .with_predictor_mode(PredictorMode::Median)
+            .with_lambda(2.0);
+
+        let fields: Vec<MotionField> = [1, 4]
+            .iter()
+            .map(|threads| {
+                rayon::ThreadPoolBuilder::new()
+                    .num_threads(*threads)
+                    .build()
+                    .unwrap()
+                    .install(|| MotionField::estimate(&anchor_frame, &target_frame, 16, &matcher))
+            })
+            .collect();
+
+        assert_eq!(fields[0], fields[1]);
+    }
+
+    #[test]
+    fn empty_frames_have_no_blocks() {
+        let frame = DynamicImage::new_luma8(32, 0);
+
+        let motion_field =
+            MotionField::estimate(&frame, &frame, 16, &ExhaustiveBlockMatcher::new(2));
+
+        assert_eq!((motion_field.cols, motion_field.rows), (2, 0));
+        assert!(motion_field.blocks.is_empty());
+    }
+}
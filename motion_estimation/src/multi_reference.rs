@@ -0,0 +1,500 @@
+use std::borrow::Cow;
+
+use image::{DynamicImage, GenericImage, GrayImage};
+use log::debug;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bma::BlockMatcher,
+    metrics::{sad::MadMetric, DistortionMetric},
+    motion_field::MotionField,
+    plane::{luma_frame, LumaPlane},
+    rate::vector_bits,
+    types::MotionVector,
+};
+
+// Forward blocks are predicted from a later frame, as the anchors of frame pairs are, and
+// backward blocks from an earlier one. Bidirectional blocks average one of each.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PredictionMode {
+    Forward,
+    Backward,
+    Bidirectional,
+}
+
+// The reference is a position in the past or future frame list, 0 being the nearest frame
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ReferenceVector {
+    pub reference: usize,
+    pub vector: MotionVector,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ReferenceBlockMotion {
+    pub x_offset: u32,
+    pub y_offset: u32,
+    pub width: u32,
+    pub height: u32,
+    pub mode: PredictionMode,
+    pub backward: Option<ReferenceVector>,
+    pub forward: Option<ReferenceVector>,
+    // Distortion of the chosen prediction, without the rate of its vectors
+    pub cost: f64,
+}
+
+impl ReferenceBlockMotion {
+    fn vectors(&self) -> impl Iterator<Item = &MotionVector> {
+        self.backward
+            .iter()
+            .chain(&self.forward)
+            .map(|reference| &reference.vector)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ReferenceMotionField {
+    pub mb_size: u32,
+    pub cols: u32,
+    pub rows: u32,
+    pub blocks: Vec<ReferenceBlockMotion>,
+}
+
+impl ReferenceMotionField {
+    pub fn compensate(
+        &self,
+        past_frames: &[&DynamicImage],
+        future_frames: &[&DynamicImage],
+    ) -> GrayImage {
+        let past_frames: Vec<_> = past_frames.iter().map(|frame| luma_frame(frame)).collect();
+        let future_frames: Vec<_> = future_frames
+            .iter()
+            .map(|frame| luma_frame(frame))
+            .collect();
+        let past_planes: Vec<LumaPlane> = past_frames
+            .iter()
+            .map(|frame| LumaPlane::from_image(frame))
+            .collect();
+        let future_planes: Vec<LumaPlane> = future_frames
+            .iter()
+            .map(|frame| LumaPlane::from_image(frame))
+            .collect();
+
+        let (width, height) = past_planes
+            .iter()
+            .chain(&future_planes)
+            .next()
+            .expect("At least one reference frame is required")
+            .dimensions();
+        let mut predicted_frame = GrayImage::new(width, height);
+
+        for block in &self.blocks {
+            let backward = block
+                .backward
+                .map(|reference| reference_block(&past_planes, block, &reference));
+            let forward = block
+                .forward
+                .map(|reference| reference_block(&future_planes, block, &reference));
+
+            predicted_frame
+                .copy_from(
+                    &predict_block(backward, forward),
+                    block.x_offset,
+                    block.y_offset,
+                )
+                .unwrap();
+        }
+
+        predicted_frame
+    }
+
+    pub fn average_cost(&self) -> f64 {
+        // Partial border blocks are weighted by their area
+        let (weighted_cost, area) = self.blocks.iter().fold((0.0, 0.0), |(cost, area), block| {
+            let block_area = (block.width * block.height) as f64;
+            (cost + block.cost * block_area, area + block_area)
+        });
+
+        weighted_cost / area
+    }
+
+    pub fn mode_count(&self, mode: PredictionMode) -> usize {
+        self.blocks
+            .iter()
+            .filter(|block| block.mode == mode)
+            .count()
+    }
+}
+
+// Every anchor block is matched in each reference frame separately, then predicted from the
+// best past reference, the best future reference or the average of both. Choices are ranked
+// by distortion + lambda * bits of the vectors used, so bi-prediction pays for two vectors.
+pub struct MultiReferenceMotionEstimator {
+    matcher: Box<dyn BlockMatcher>,
+    metric: Box<dyn DistortionMetric>,
+    lambda: f64,
+}
+
+impl MultiReferenceMotionEstimator {
+    pub fn new(matcher: Box<dyn BlockMatcher>) -> Self {
+        Self {
+            matcher,
+            metric: Box::new(MadMetric::new()),
+            lambda: 0.0,
+        }
+    }
+
+    pub fn with_metric(self, metric: Box<dyn DistortionMetric>) -> Self {
+        Self { metric, ..self }
+    }
+
+    pub fn with_lambda(self, lambda: f64) -> Self {
+        Self { lambda, ..self }
+    }
+
+    // Past and future frames are ordered nearest first
+    pub fn estimate(
+        &self,
+        anchor_frame: &DynamicImage,
+        past_frames: &[&DynamicImage],
+        future_frames: &[&DynamicImage],
+        mb_size: u32,
+    ) -> ReferenceMotionField {
+        assert!(
+            !past_frames.is_empty() || !future_frames.is_empty(),
+            "At least one reference frame is required"
+        );
+
+        let estimate_fields = |frames: &[&DynamicImage]| -> Vec<MotionField> {
+            frames
+                .iter()
+                .map(|frame| {
+                    MotionField::estimate(anchor_frame, frame, mb_size, self.matcher.as_ref())
+                })
+                .collect()
+        };
+        let past_fields = estimate_fields(past_frames);
+        let future_fields = estimate_fields(future_frames);
+
+        let anchor_frame = luma_frame(anchor_frame);
+        let anchor_plane = LumaPlane::from_image(&anchor_frame);
+        let past_frames: Vec<_> = past_frames.iter().map(|frame| luma_frame(frame)).collect();
+        let future_frames: Vec<_> = future_frames
+            .iter()
+            .map(|frame| luma_frame(frame))
+            .collect();
+        let past = ReferenceList::new(&past_fields, &past_frames);
+        let future = ReferenceList::new(&future_fields, &future_frames);
+
+        let layout = past_fields.iter().chain(&future_fields).next().unwrap();
+        let blocks = (0..layout.blocks.len())
+            .into_par_iter()
+            .map(|index| self.choose_prediction(index, &anchor_plane, &past, &future))
+            .collect();
+
+        let motion_field = ReferenceMotionField {
+            mb_size,
+            cols: layout.cols,
+            rows: layout.rows,
+            blocks,
+        };
+        debug!(
+            "{} backward, {} forward and {} bidirectional blocks",
+            motion_field.mode_count(PredictionMode::Backward),
+            motion_field.mode_count(PredictionMode::Forward),
+            motion_field.mode_count(PredictionMode::Bidirectional)
+        );
+
+        motion_field
+    }
+
+    fn choose_prediction(
+        &self,
+        index: usize,
+        anchor_plane: &LumaPlane,
+        past: &ReferenceList,
+        future: &ReferenceList,
+    ) -> ReferenceBlockMotion {
+        let layout = past.fields.iter().chain(future.fields).next().unwrap();
+        let block = &layout.blocks[index];
+        let anchor_block = anchor_plane
+            .view(
+                block.x_offset as i32,
+                block.y_offset as i32,
+                block.width,
+                block.height,
+            )
+            .unwrap();
+
+        let candidate = |mode, backward, forward| {
+            let mut candidate = ReferenceBlockMotion {
+                x_offset: block.x_offset,
+                y_offset: block.y_offset,
+                width: block.width,
+                height: block.height,
+                mode,
+                backward,
+                forward,
+                cost: 0.0,
+            };
+            let backward =
+                backward.map(|reference| reference_block(&past.planes, &candidate, &reference));
+            let forward =
+                forward.map(|reference| reference_block(&future.planes, &candidate, &reference));
+
+            let predicted_block = predict_block(backward, forward);
+            candidate.cost = self
+                .metric
+                .calculate_plane_error(&anchor_block, &LumaPlane::from_image(&predicted_block));
+            candidate
+        };
+
+        // Nearer references win ties, as do single references over bi-prediction
+        let best_backward = self.best_candidate(
+            past.references(index)
+                .map(|reference| candidate(PredictionMode::Backward, Some(reference), None)),
+        );
+        let best_forward = self.best_candidate(
+            future
+                .references(index)
+                .map(|reference| candidate(PredictionMode::Forward, None, Some(reference))),
+        );
+        let bidirectional =
+            best_backward
+                .as_ref()
+                .zip(best_forward.as_ref())
+                .map(|(backward, forward)| {
+                    candidate(
+                        PredictionMode::Bidirectional,
+                        backward.backward,
+                        forward.forward,
+                    )
+                });
+
+        let best = self
+            .best_candidate(
+                best_backward
+                    .into_iter()
+                    .chain(best_forward)
+                    .chain(bidirectional),
+            )
+            .unwrap();
+        debug!(
+            "Block ({}, {}): {:?} prediction, cost {}",
+            best.x_offset, best.y_offset, best.mode, best.cost
+        );
+
+        best
+    }
+
+    fn best_candidate(
+        &self,
+        candidates: impl Iterator<Item = ReferenceBlockMotion>,
+    ) -> Option<ReferenceBlockMotion> {
+        candidates.fold(None, |best, candidate| match best {
+            Some(best)
+                if self.rate_constrained_cost(&best) <= self.rate_constrained_cost(&candidate) =>
+            {
+                Some(best)
+            }
+            _ => Some(candidate),
+        })
+    }
+
+    fn rate_constrained_cost(&self, candidate: &ReferenceBlockMotion) -> f64 {
+        if self.lambda == 0.0 {
+            return candidate.cost;
+        }
+
+        let bits: u32 = candidate
+            .vectors()
+            .map(|vector| vector_bits((vector.dx, vector.dy), (0, 0)))
+            .sum();
+        candidate.cost + self.lambda * bits as f64
+    }
+}
+
+struct ReferenceList<'a> {
+    fields: &'a [MotionField],
+    planes: Vec<LumaPlane<'a>>,
+}
+
+impl<'a> ReferenceList<'a> {
+    fn new(fields: &'a [MotionField], frames: &'a [Cow<'a, GrayImage>]) -> Self {
+        Self {
+            fields,
+            planes: frames
+                .iter()
+                .map(|frame| LumaPlane::from_image(frame))
+                .collect(),
+        }
+    }
+
+    fn references(&self, index: usize) -> impl Iterator<Item = ReferenceVector> + '_ {
+        self.fields
+            .iter()
+            .enumerate()
+            .map(move |(reference, field)| ReferenceVector {
+                reference,
+                vector: field.blocks[index].vector,
+            })
+    }
+}
+
+fn reference_block<'a>(
+    planes: &[LumaPlane<'a>],
+    block: &ReferenceBlockMotion,
+    reference: &ReferenceVector,
+) -> LumaPlane<'a> {
+    planes[reference.reference]
+        .view(
+            block.x_offset as i32 + reference.vector.dx,
+            block.y_offset as i32 + reference.vector.dy,
+            block.width,
+            block.height,
+        )
+        .expect("Reference block lies outside its frame")
+}
+
+// Bi-prediction rounds the average of both blocks to the nearest integer, halves upwards
+fn predict_block(backward: Option<LumaPlane>, forward: Option<LumaPlane>) -> GrayImage {
+    match (backward, forward) {
+        (Some(backward), Some(forward)) => {
+            let pixels = backward
+                .rows()
+                .flatten()
+                .zip(forward.rows().flatten())
+                .map(|(backward, forward)| (*backward as u16 + *forward as u16).div_ceil(2) as u8)
+                .collect();
+
+            GrayImage::from_raw(backward.width(), backward.height(), pixels).unwrap()
+        }
+        (Some(block), None) | (None, Some(block)) => block.to_image(),
+        (None, None) => unreachable!("Blocks are predicted from at least one reference"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bma::{exhaustive::ExhaustiveBlockMatcher, naive::NaiveBlockMatcher},
+        test_utils::{texture, textured_frame},
+    };
+
+    // Texture squeezed into mid-range values, brightened by the offset without clipping
+    fn brightened_frame(offset: i32) -> DynamicImage {
+        DynamicImage::ImageLuma8(GrayImage::from_fn(48, 48, |x, y| {
+            let value = 64 + texture(x as f64, y as f64) as i32 / 2 + offset;
+            image::Luma([value as u8])
+        }))
+    }
+
+    #[test]
+    fn blocks_pick_the_best_past_reference() {
+        // The nearer frame only matches at a fractional shift, the older one exactly
+        let anchor_frame = textured_frame(64, 64, (0.0, 0.0));
+        let nearer_frame = textured_frame(64, 64, (1.5, 0.0));
+        let older_frame = textured_frame(64, 64, (2.0, -1.0));
+        let estimator =
+            MultiReferenceMotionEstimator::new(Box::new(ExhaustiveBlockMatcher::new(4)));
+
+        let motion_field =
+            estimator.estimate(&anchor_frame, &[&nearer_frame, &older_frame], &[], 16);
+
+        assert_eq!((motion_field.cols, motion_field.rows), (4, 4));
+        assert_eq!(
+            motion_field.mode_count(PredictionMode::Backward),
+            motion_field.blocks.len()
+        );
+        let block = &motion_field.blocks[5];
+        assert_eq!(block.forward, None);
+        assert_eq!(
+            block.backward.map(|reference| (
+                reference.reference,
+                reference.vector.dx,
+                reference.vector.dy
+            )),
+            Some((1, 2, -1))
+        );
+        assert_eq!(block.cost, 0.0);
+    }
+
+    #[test]
+    fn bi_prediction_averages_both_references() {
+        // Either reference alone is off by 8 levels, their average is exact
+        let anchor_frame = brightened_frame(0);
+        let past_frame = brightened_frame(8);
+        let future_frame = brightened_frame(-8);
+        let estimator = MultiReferenceMotionEstimator::new(Box::new(NaiveBlockMatcher::new()));
+
+        let motion_field = estimator.estimate(&anchor_frame, &[&past_frame], &[&future_frame], 16);
+
+        assert_eq!(
+            motion_field.mode_count(PredictionMode::Bidirectional),
+            motion_field.blocks.len()
+        );
+        assert_eq!(motion_field.average_cost(), 0.0);
+        assert_eq!(
+            motion_field.compensate(&[&past_frame], &[&future_frame]),
+            anchor_frame.to_luma8()
+        );
+    }
+
+    #[test]
+    fn bi_prediction_pays_for_two_vectors() {
+        let anchor_frame = brightened_frame(0);
+        let past_frame = brightened_frame(8);
+        let future_frame = brightened_frame(-8);
+        // Two zero vectors cost 4 bits against 2, which outweighs the distortion saved
+        let estimator = MultiReferenceMotionEstimator::new(Box::new(NaiveBlockMatcher::new()))
+            .with_lambda(10.0);
+
+        let motion_field = estimator.estimate(&anchor_frame, &[&past_frame], &[&future_frame], 16);
+
+        // Backward prediction wins the tie with forward prediction
+        assert_eq!(
+            motion_field.mode_count(PredictionMode::Backward),
+            motion_field.blocks.len()
+        );
+        assert_eq!(motion_field.average_cost(), 8.0);
+    }
+
+    #[test]
+    fn average_costs_are_weighted_by_block_area() {
+        let block = |width, cost| ReferenceBlockMotion {
+            x_offset: 0,
+            y_offset: 0,
+            width,
+            height: 16,
+            mode: PredictionMode::Forward,
+            backward: None,
+            forward: None,
+            cost,
+        };
+        let motion_field = ReferenceMotionField {
+            mb_size: 16,
+            cols: 2,
+            rows: 1,
+            blocks: vec![block(16, 1.0), block(8, 4.0)],
+        };
+
+        assert_eq!(motion_field.average_cost(), 2.0);
+        assert_eq!(motion_field.mode_count(PredictionMode::Forward), 2);
+        assert_eq!(motion_field.mode_count(PredictionMode::Backward), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "At least one reference frame is required")]
+    fn estimation_needs_a_reference() {
+        let frame = textured_frame(16, 16, (0.0, 0.0));
+
+        MultiReferenceMotionEstimator::new(Box::new(NaiveBlockMatcher::new())).estimate(
+            &frame,
+            &[],
+            &[],
+            16,
+        );
+    }
+}
@@ -0,0 +1,194 @@
+use image::DynamicImage;
+use log::debug;
+
+use super::{upsample_flow, FlowEstimator, Plane};
+use crate::{flow::FlowField, pyramid::build_gaussian_pyramid};
+
+// Laplacian averaging weights of the direct and diagonal neighbours
+const DIRECT_WEIGHT: f32 = 1.0 / 6.0;
+const DIAGONAL_WEIGHT: f32 = 1.0 / 12.0;
+
+pub struct HornSchunckFlowEstimator {
+    alpha: f32,
+    iterations: usize,
+    levels: usize,
+}
+
+impl HornSchunckFlowEstimator {
+    pub fn new(alpha: f32, iterations: usize) -> Self {
+        Self {
+            alpha,
+            iterations,
+            levels: 1,
+        }
+    }
+
+    // Coarse to fine estimation, warping the target frame by the coarser flow at each level
+    pub fn with_levels(self, levels: usize) -> Self {
+        assert!(levels >= 1, "A pyramid needs at least one level");
+        Self { levels, ..self }
+    }
+
+    fn refine_level(&self, anchor: &Plane, target: &Plane, guess: &FlowField) -> FlowField {
+        let (width, height) = (anchor.width, anchor.height);
+
+        let warped_target = Plane {
+            width,
+            height,
+            data: (0..height)
+                .flat_map(|y| (0..width).map(move |x| (x, y)))
+                .map(|(x, y)| {
+                    let (u, v) = guess.get(x, y);
+                    target.sample(x as f32 + u, y as f32 + v)
+                })
+                .collect(),
+        };
+
+        // Derivatives are taken on the average of both frames, as in the original method
+        let average = Plane {
+            width,
+            height,
+            data: anchor
+                .data
+                .iter()
+                .zip(&warped_target.data)
+                .map(|(anchor_pixel, target_pixel)| 0.5 * (anchor_pixel + target_pixel))
+                .collect(),
+        };
+        let (gradient_x, gradient_y) = average.gradients();
+        let temporal: Vec<f32> = warped_target
+            .data
+            .iter()
+            .zip(&anchor.data)
+            .map(|(target_pixel, anchor_pixel)| target_pixel - anchor_pixel)
+            .collect();
+
+        let alpha_squared = self.alpha * self.alpha;
+        let mut increment = vec![(0.0f32, 0.0f32); (width * height) as usize];
+
+        for _ in 0..self.iterations {
+            let total = |x: i64, y: i64| {
+                let x = x.clamp(0, width as i64 - 1) as u32;
+                let y = y.clamp(0, height as i64 - 1) as u32;
+                let index = (y * width + x) as usize;
+                let (u, v) = guess.get(x, y);
+                (u + increment[index].0, v + increment[index].1)
+            };
+
+            // Jacobi update, smoothing the total flow rather than the increment alone
+            let updated: Vec<(f32, f32)> = (0..height as i64)
+                .flat_map(|y| (0..width as i64).map(move |x| (x, y)))
+                .map(|(x, y)| {
+                    let (mut mean_u, mut mean_v) = (0.0, 0.0);
+                    for (dx, dy, weight) in [
+                        (-1, 0, DIRECT_WEIGHT),
+                        (1, 0, DIRECT_WEIGHT),
+                        (0, -1, DIRECT_WEIGHT),
+                        (0, 1, DIRECT_WEIGHT),
+                        (-1, -1, DIAGONAL_WEIGHT),
+                        (1, -1, DIAGONAL_WEIGHT),
+                        (-1, 1, DIAGONAL_WEIGHT),
+                        (1, 1, DIAGONAL_WEIGHT),
+                    ] {
+                        let (u, v) = total(x + dx, y + dy);
+                        mean_u += weight * u;
+                        mean_v += weight * v;
+                    }
+
+                    let index = (y * width as i64 + x) as usize;
+                    let (guess_u, guess_v) = guess.get(x as u32, y as u32);
+                    let (ix, iy, it) = (
+                        gradient_x.data[index],
+                        gradient_y.data[index],
+                        temporal[index],
+                    );
+
+                    // Linearised around the guess: It + Ix * du + Iy * dv = 0
+                    let (mean_du, mean_dv) = (mean_u - guess_u, mean_v - guess_v);
+                    let correction =
+                        (ix * mean_du + iy * mean_dv + it) / (alpha_squared + ix * ix + iy * iy);
+
+                    (mean_du - ix * correction, mean_dv - iy * correction)
+                })
+                .collect();
+
+            increment = updated;
+        }
+
+        let mut flow = FlowField::new(width, height);
+        for (index, (du, dv)) in increment.into_iter().enumerate() {
+            let (x, y) = (index as u32 % width, index as u32 / width);
+            let (u, v) = guess.get(x, y);
+            flow.set(x, y, (u + du, v + dv));
+        }
+
+        flow
+    }
+}
+
+impl FlowEstimator for HornSchunckFlowEstimator {
+    fn estimate(&self, anchor_frame: &DynamicImage, target_frame: &DynamicImage) -> FlowField {
+        let anchor_pyramid = build_gaussian_pyramid(anchor_frame, self.levels);
+        let target_pyramid = build_gaussian_pyramid(target_frame, self.levels);
+
+        let mut flow: Option<FlowField> = None;
+
+        for level in (0..self.levels).rev() {
+            let anchor = Plane::from_image(&anchor_pyramid[level]);
+            let target = Plane::from_image(&target_pyramid[level]);
+
+            let guess = match &flow {
+                Some(coarser_flow) => upsample_flow(coarser_flow, anchor.width, anchor.height),
+                None => FlowField::new(anchor.width, anchor.height),
+            };
+
+            flow = Some(self.refine_level(&anchor, &target, &guess));
+            debug!(
+                "Horn-Schunck level {} ({}x{}) refined",
+                level, anchor.width, anchor.height
+            );
+        }
+
+        flow.unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{interior_mean_flow, textured_frame};
+
+    #[test]
+    fn follows_a_subpixel_shift() {
+        let anchor_frame = textured_frame(64, 64, (0.0, 0.0));
+        let target_frame = textured_frame(64, 64, (1.5, -0.75));
+
+        let flow = HornSchunckFlowEstimator::new(15.0, 200)
+            .with_levels(2)
+            .estimate(&anchor_frame, &target_frame);
+
+        assert_eq!((flow.width, flow.height), (64, 64));
+        let (u, v) = interior_mean_flow(&flow, 12);
+        assert!(
+            (u - 1.5).abs() < 0.2 && (v + 0.75).abs() < 0.2,
+            "({}, {})",
+            u,
+            v
+        );
+    }
+
+    #[test]
+    fn identical_frames_have_no_flow() {
+        let frame = textured_frame(32, 32, (0.0, 0.0));
+
+        let flow = HornSchunckFlowEstimator::new(15.0, 50).estimate(&frame, &frame);
+
+        assert!(flow.max_magnitude() < 1e-6);
+    }
+
+    #[test]
+    #[should_panic(expected = "A pyramid needs at least one level")]
+    fn pyramids_need_a_level() {
+        HornSchunckFlowEstimator::new(15.0, 50).with_levels(0);
+    }
+}
@@ -0,0 +1,163 @@
+use image::DynamicImage;
+use log::debug;
+
+use super::{upsample_flow, FlowEstimator, Plane};
+use crate::{flow::FlowField, pyramid::build_gaussian_pyramid};
+
+const DEFAULT_ITERATIONS: usize = 5;
+const CONVERGENCE_THRESHOLD: f32 = 0.01;
+const MIN_EIGENVALUE: f32 = 1e-3;
+
+pub struct LucasKanadeFlowEstimator {
+    window_size: u32,
+    levels: usize,
+    iterations: usize,
+}
+
+impl LucasKanadeFlowEstimator {
+    pub fn new(window_size: u32, levels: usize) -> Self {
+        assert!(levels >= 1, "A pyramid needs at least one level");
+
+        Self {
+            window_size,
+            levels,
+            iterations: DEFAULT_ITERATIONS,
+        }
+    }
+
+    pub fn with_iterations(self, iterations: usize) -> Self {
+        Self { iterations, ..self }
+    }
+
+    fn refine_level(&self, anchor: &Plane, target: &Plane, guess: &FlowField) -> FlowField {
+        let (gradient_x, gradient_y) = anchor.gradients();
+        let radius = (self.window_size / 2) as i64;
+        let mut flow = FlowField::new(anchor.width, anchor.height);
+
+        for y in 0..anchor.height as i64 {
+            for x in 0..anchor.width as i64 {
+                let window: Vec<(i64, i64)> = (y - radius..=y + radius)
+                    .flat_map(|window_y| (x - radius..=x + radius).map(move |wx| (wx, window_y)))
+                    .collect();
+
+                // Spatial gradient matrix of the window, constant across iterations
+                let (mut gxx, mut gxy, mut gyy) = (0.0, 0.0, 0.0);
+                for (window_x, window_y) in &window {
+                    let (ix, iy) = (
+                        gradient_x.get(*window_x, *window_y),
+                        gradient_y.get(*window_x, *window_y),
+                    );
+                    gxx += ix * ix;
+                    gxy += ix * iy;
+                    gyy += iy * iy;
+                }
+
+                let (mut u, mut v) = guess.get(x as u32, y as u32);
+
+                let determinant = gxx * gyy - gxy * gxy;
+                let min_eigenvalue = 0.5 * (gxx + gyy - f32::hypot(gxx - gyy, 2.0 * gxy));
+
+                // Flat or edge-only windows keep the coarser estimate (aperture problem)
+                if min_eigenvalue / window.len() as f32 > MIN_EIGENVALUE {
+                    for _ in 0..self.iterations {
+                        let (mut bx, mut by) = (0.0, 0.0);
+                        for (window_x, window_y) in &window {
+                            let difference = anchor.get(*window_x, *window_y)
+                                - target.sample(*window_x as f32 + u, *window_y as f32 + v);
+                            bx += difference * gradient_x.get(*window_x, *window_y);
+                            by += difference * gradient_y.get(*window_x, *window_y);
+                        }
+
+                        let du = (gyy * bx - gxy * by) / determinant;
+                        let dv = (gxx * by - gxy * bx) / determinant;
+                        u += du;
+                        v += dv;
+
+                        if f32::hypot(du, dv) < CONVERGENCE_THRESHOLD {
+                            break;
+                        }
+                    }
+                }
+
+                flow.set(x as u32, y as u32, (u, v));
+            }
+        }
+
+        flow
+    }
+}
+
+impl FlowEstimator for LucasKanadeFlowEstimator {
+    fn estimate(&self, anchor_frame: &DynamicImage, target_frame: &DynamicImage) -> FlowField {
+        let anchor_pyramid = build_gaussian_pyramid(anchor_frame, self.levels);
+        let target_pyramid = build_gaussian_pyramid(target_frame, self.levels);
+
+        let mut flow: Option<FlowField> = None;
+
+        for level in (0..self.levels).rev() {
+            let anchor = Plane::from_image(&anchor_pyramid[level]);
+            let target = Plane::from_image(&target_pyramid[level]);
+
+            let guess = match &flow {
+                Some(coarser_flow) => upsample_flow(coarser_flow, anchor.width, anchor.height),
+                None => FlowField::new(anchor.width, anchor.height),
+            };
+
+            flow = Some(self.refine_level(&anchor, &target, &guess));
+            debug!(
+                "Lucas-Kanade level {} ({}x{}) refined",
+                level, anchor.width, anchor.height
+            );
+        }
+
+        flow.unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{interior_mean_flow, textured_frame};
+
+    #[test]
+    fn follows_a_subpixel_shift() {
+        let anchor_frame = textured_frame(64, 64, (0.0, 0.0));
+        let target_frame = textured_frame(64, 64, (1.5, -0.75));
+
+        let flow = LucasKanadeFlowEstimator::new(9, 2).estimate(&anchor_frame, &target_frame);
+
+        assert_eq!((flow.width, flow.height), (64, 64));
+        let (u, v) = interior_mean_flow(&flow, 12);
+        assert!(
+            (u - 1.5).abs() < 0.1 && (v + 0.75).abs() < 0.1,
+            "({}, {})",
+            u,
+            v
+        );
+    }
+
+    #[test]
+    fn pyramids_reach_beyond_the_window() {
+        let anchor_frame = textured_frame(64, 64, (0.0, 0.0));
+        let target_frame = textured_frame(64, 64, (5.0, 3.0));
+
+        let flow = LucasKanadeFlowEstimator::new(7, 3).estimate(&anchor_frame, &target_frame);
+
+        let (u, v) = interior_mean_flow(&flow, 16);
+        assert!(
+            (u - 5.0).abs() < 0.25 && (v - 3.0).abs() < 0.25,
+            "({}, {})",
+            u,
+            v
+        );
+    }
+
+    #[test]
+    fn flat_frames_have_no_flow() {
+        let frame = DynamicImage::new_luma8(16, 16);
+
+        let flow = LucasKanadeFlowEstimator::new(5, 1).estimate(&frame, &frame);
+
+        assert!(flow.vectors.iter().all(|vector| *vector == (0.0, 0.0)));
+    }
+}
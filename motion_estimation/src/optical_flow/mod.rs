@@ -0,0 +1,143 @@
+use image::DynamicImage;
+
+use crate::flow::FlowField;
+
+pub mod horn_schunck;
+pub mod lucas_kanade;
+
+pub trait FlowEstimator: Send + Sync {
+    // Vectors point from each anchor pixel to its position in the target frame
+    fn estimate(&self, anchor_frame: &DynamicImage, target_frame: &DynamicImage) -> FlowField;
+}
+
+// Floating point luma plane, reads outside of the frame being clamped to its borders
+pub(crate) struct Plane {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<f32>,
+}
+
+impl Plane {
+    pub fn from_image(image: &DynamicImage) -> Self {
+        let image = image.to_luma8();
+
+        Self {
+            width: image.width(),
+            height: image.height(),
+            data: image.as_raw().iter().map(|pixel| *pixel as f32).collect(),
+        }
+    }
+
+    pub fn get(&self, x: i64, y: i64) -> f32 {
+        let x = x.clamp(0, self.width as i64 - 1);
+        let y = y.clamp(0, self.height as i64 - 1);
+        self.data[(y * self.width as i64 + x) as usize]
+    }
+
+    pub fn sample(&self, x: f32, y: f32) -> f32 {
+        let (left, top) = (x.floor(), y.floor());
+        let (x_fraction, y_fraction) = (x - left, y - top);
+        let (left, top) = (left as i64, top as i64);
+
+        (1.0 - y_fraction)
+            * ((1.0 - x_fraction) * self.get(left, top) + x_fraction * self.get(left + 1, top))
+            + y_fraction
+                * ((1.0 - x_fraction) * self.get(left, top + 1)
+                    + x_fraction * self.get(left + 1, top + 1))
+    }
+
+    // Central differences along x and y
+    pub fn gradients(&self) -> (Plane, Plane) {
+        let gradient = |dx: i64, dy: i64| Plane {
+            width: self.width,
+            height: self.height,
+            data: (0..self.height as i64)
+                .flat_map(|y| (0..self.width as i64).map(move |x| (x, y)))
+                .map(|(x, y)| 0.5 * (self.get(x + dx, y + dy) - self.get(x - dx, y - dy)))
+                .collect(),
+        };
+
+        (gradient(1, 0), gradient(0, 1))
+    }
+}
+
+// Doubles the resolution of a coarser level flow, scaling the vectors accordingly
+pub(crate) fn upsample_flow(flow: &FlowField, width: u32, height: u32) -> FlowField {
+    let (scale_x, scale_y) = (
+        flow.width as f32 / width as f32,
+        flow.height as f32 / height as f32,
+    );
+    let component = |select: fn(&(f32, f32)) -> f32| Plane {
+        width: flow.width,
+        height: flow.height,
+        data: flow.vectors.iter().map(select).collect(),
+    };
+    let (horizontal, vertical) = (component(|(u, _)| *u), component(|(_, v)| *v));
+
+    let mut upsampled = FlowField::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let coarse_x = (x as f32 + 0.5) * scale_x - 0.5;
+            let coarse_y = (y as f32 + 0.5) * scale_y - 0.5;
+
+            upsampled.set(
+                x,
+                y,
+                (
+                    horizontal.sample(coarse_x, coarse_y) / scale_x,
+                    vertical.sample(coarse_x, coarse_y) / scale_y,
+                ),
+            );
+        }
+    }
+
+    upsampled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ramp() -> Plane {
+        Plane {
+            width: 4,
+            height: 3,
+            data: (0..3)
+                .flat_map(|y| (0..4).map(move |x| (10 * x + 100 * y) as f32))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn samples_are_bilinear_and_clamped() {
+        let plane = ramp();
+
+        assert_eq!(plane.sample(1.5, 0.0), 15.0);
+        assert_eq!(plane.sample(1.25, 1.5), 162.5);
+        assert_eq!(plane.get(-2, 7), 200.0);
+        assert_eq!(plane.sample(5.0, -1.0), 30.0);
+    }
+
+    #[test]
+    fn gradients_are_central_differences() {
+        let (gradient_x, gradient_y) = ramp().gradients();
+
+        assert_eq!((gradient_x.get(1, 1), gradient_y.get(1, 1)), (10.0, 100.0));
+        // Borders are replicated, halving the difference
+        assert_eq!((gradient_x.get(0, 0), gradient_y.get(0, 0)), (5.0, 50.0));
+    }
+
+    #[test]
+    fn upsampled_flow_scales_the_vectors() {
+        let mut flow = FlowField::new(2, 2);
+        flow.vectors = vec![(1.0, -0.5); 4];
+
+        let upsampled = upsample_flow(&flow, 4, 4);
+
+        assert_eq!((upsampled.width, upsampled.height), (4, 4));
+        assert!(upsampled
+            .vectors
+            .iter()
+            .all(|vector| *vector == (2.0, -1.0)));
+    }
+}
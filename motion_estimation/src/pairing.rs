@@ -0,0 +1,399 @@
+use std::{collections::VecDeque, io, path::Path, str::FromStr, sync::Arc};
+
+use itertools::Itertools;
+use rayon::prelude::*;
+
+use crate::source::{Frame, FrameSource};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FramePairing {
+    Consecutive,
+    FirstAnchor,
+    AllPairs,
+}
+
+impl FramePairing {
+    pub fn pair_frames<S: FrameSource>(self, source: S) -> FramePairs<S> {
+        FramePairs {
+            source,
+            pairing: self,
+            anchors: Vec::new(),
+            pending: VecDeque::new(),
+            frame_count: 0,
+        }
+    }
+}
+
+impl FromStr for FramePairing {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "consecutive" => Ok(FramePairing::Consecutive),
+            "first" => Ok(FramePairing::FirstAnchor),
+            "all" => Ok(FramePairing::AllPairs),
+            _ => Err(format!(
+                "unknown frame pairing '{}', expected one of: consecutive, first, all",
+                name
+            )),
+        }
+    }
+}
+
+pub struct FramePair {
+    pub anchor_index: usize,
+    pub target_index: usize,
+    pub anchor: Arc<Frame>,
+    pub target: Arc<Frame>,
+}
+
+impl FramePair {
+    // Outputs of a pair are named after both frames
+    pub fn file_stem(&self) -> String {
+        format!(
+            "{}_{}",
+            frame_stem(&self.anchor.0),
+            frame_stem(&self.target.0)
+        )
+    }
+}
+
+// Image sequence frames are named after their files, without the extension
+pub fn frame_stem(frame_name: &str) -> &str {
+    Path::new(frame_name)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or(frame_name)
+}
+
+// Frames are pulled from the source as pairs are consumed, and only the frames
+// that can still act as anchors are retained
+pub struct FramePairs<S: FrameSource> {
+    source: S,
+    pairing: FramePairing,
+    anchors: Vec<(usize, Arc<Frame>)>,
+    pending: VecDeque<FramePair>,
+    frame_count: usize,
+}
+
+impl<S: FrameSource> Iterator for FramePairs<S> {
+    type Item = io::Result<FramePair>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pending.is_empty() {
+            let target = match self.source.next()? {
+                Ok(frame) => Arc::new(frame),
+                Err(error) => return Some(Err(error)),
+            };
+            let target_index = self.frame_count;
+            self.frame_count += 1;
+
+            self.pending
+                .extend(self.anchors.iter().map(|(anchor_index, anchor)| FramePair {
+                    anchor_index: *anchor_index,
+                    target_index,
+                    anchor: anchor.clone(),
+                    target: target.clone(),
+                }));
+
+            match self.pairing {
+                FramePairing::Consecutive => self.anchors = vec![(target_index, target)],
+                FramePairing::FirstAnchor if self.anchors.is_empty() => {
+                    self.anchors.push((target_index, target))
+                }
+                FramePairing::FirstAnchor => {}
+                FramePairing::AllPairs => self.anchors.push((target_index, target)),
+            }
+        }
+
+        self.pending.pop_front().map(Ok)
+    }
+}
+
+// Past frames are ordered nearest first
+pub struct ReferenceWindow {
+    pub anchor_index: usize,
+    pub anchor: Arc<Frame>,
+    pub past: Vec<(usize, Arc<Frame>)>,
+    pub future: Vec<(usize, Arc<Frame>)>,
+}
+
+// Every frame with all of its future references, and at least one past reference when any
+// are wanted, becomes an anchor. Only the frames still needed by later windows are retained.
+pub struct ReferenceWindows<S: FrameSource> {
+    source: S,
+    past_references: usize,
+    future_references: usize,
+    frames: VecDeque<(usize, Arc<Frame>)>,
+    frame_count: usize,
+    anchor_index: usize,
+}
+
+impl<S: FrameSource> ReferenceWindows<S> {
+    pub fn new(source: S, past_references: usize, future_references: usize) -> Self {
+        assert!(
+            past_references + future_references > 0,
+            "At least one reference frame is required"
+        );
+
+        Self {
+            source,
+            past_references,
+            future_references,
+            frames: VecDeque::new(),
+            frame_count: 0,
+            anchor_index: past_references.min(1),
+        }
+    }
+
+    fn frame(&self, index: usize) -> (usize, Arc<Frame>) {
+        let (_, frame) = &self.frames[index - self.frames[0].0];
+        (index, frame.clone())
+    }
+}
+
+impl<S: FrameSource> Iterator for ReferenceWindows<S> {
+    type Item = io::Result<ReferenceWindow>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.frame_count <= self.anchor_index + self.future_references {
+            match self.source.next()? {
+                Ok(frame) => self.frames.push_back((self.frame_count, Arc::new(frame))),
+                Err(error) => return Some(Err(error)),
+            }
+            self.frame_count += 1;
+        }
+
+        let anchor_index = self.anchor_index;
+        let first_past_index = anchor_index.saturating_sub(self.past_references);
+        let window = ReferenceWindow {
+            anchor_index,
+            anchor: self.frame(anchor_index).1,
+            past: (first_past_index..anchor_index)
+                .rev()
+                .map(|index| self.frame(index))
+                .collect(),
+            future: (anchor_index + 1..=anchor_index + self.future_references)
+                .map(|index| self.frame(index))
+                .collect(),
+        };
+
+        self.anchor_index += 1;
+        while self.frames[0].0 + self.past_references < self.anchor_index {
+            self.frames.pop_front();
+        }
+
+        Some(Ok(window))
+    }
+}
+
+// Pairs, or reference windows, are estimated in parallel batches, so only a bounded number
+// of frames is decoded at once, and the results are yielded in order for exports and logging
+pub fn estimate_pairs<P, T, F>(
+    pairs: impl Iterator<Item = P>,
+    estimate: F,
+) -> impl Iterator<Item = (P, T)>
+where
+    P: Sync,
+    T: Send,
+    F: Fn(&P) -> T + Sync,
+{
+    let batch_size = rayon::current_num_threads();
+
+    pairs
+        .batching(move |pairs| {
+            let batch: Vec<P> = pairs.take(batch_size).collect();
+            if batch.is_empty() {
+                return None;
+            }
+
+            let results: Vec<T> = batch.par_iter().map(&estimate).collect();
+            Some(batch.into_iter().zip(results))
+        })
+        .flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use image::DynamicImage;
+
+    use super::*;
+    use crate::{
+        bma::arps::{AdaptiveRoodPatternBlockMatcher, PredictorMode},
+        motion_field::MotionField,
+        test_utils::textured_frame,
+    };
+
+    fn frames(count: usize) -> impl FrameSource {
+        (0..count).map(|index| Ok((format!("{}", index), DynamicImage::new_luma8(1, 1))))
+    }
+
+    fn pair_indices(pairing: FramePairing, count: usize) -> Vec<(usize, usize)> {
+        pairing
+            .pair_frames(frames(count))
+            .map(|pair| {
+                let pair = pair.unwrap();
+                (pair.anchor_index, pair.target_index)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn pairings_choose_their_anchors() {
+        assert_eq!(
+            pair_indices(FramePairing::Consecutive, 4),
+            vec![(0, 1), (1, 2), (2, 3)]
+        );
+        assert_eq!(
+            pair_indices(FramePairing::FirstAnchor, 4),
+            vec![(0, 1), (0, 2), (0, 3)]
+        );
+        assert_eq!(
+            pair_indices(FramePairing::AllPairs, 4),
+            vec![(0, 1), (0, 2), (1, 2), (0, 3), (1, 3), (2, 3)]
+        );
+    }
+
+    #[test]
+    fn single_frames_have_no_pairs() {
+        assert!(pair_indices(FramePairing::Consecutive, 1).is_empty());
+        assert!(pair_indices(FramePairing::AllPairs, 0).is_empty());
+    }
+
+    #[test]
+    fn pairs_share_the_decoded_frames() {
+        let pairs: Vec<FramePair> = FramePairing::FirstAnchor
+            .pair_frames(frames(3))
+            .collect::<io::Result<_>>()
+            .unwrap();
+
+        assert!(Arc::ptr_eq(&pairs[0].anchor, &pairs[1].anchor));
+        assert_eq!(pairs[1].target.0, "2");
+    }
+
+    #[test]
+    fn source_errors_are_passed_on() {
+        let source = frames(2).chain(std::iter::once(Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "corrupt frame",
+        ))));
+        let mut pairs = FramePairing::Consecutive.pair_frames(source);
+
+        assert!(pairs.next().unwrap().is_ok());
+        assert!(pairs.next().unwrap().is_err());
+    }
+
+    fn window_indices(
+        past_references: usize,
+        future_references: usize,
+        count: usize,
+    ) -> Vec<(usize, Vec<usize>, Vec<usize>)> {
+        ReferenceWindows::new(frames(count), past_references, future_references)
+            .map(|window| {
+                let window = window.unwrap();
+                let indices = |frames: &[(usize, Arc<Frame>)]| {
+                    frames.iter().map(|(index, _)| *index).collect()
+                };
+                (
+                    window.anchor_index,
+                    indices(&window.past),
+                    indices(&window.future),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reference_windows_need_every_future_reference() {
+        assert_eq!(
+            window_indices(2, 1, 5),
+            vec![
+                (1, vec![0], vec![2]),
+                (2, vec![1, 0], vec![3]),
+                (3, vec![2, 1], vec![4])
+            ]
+        );
+        assert_eq!(
+            window_indices(0, 2, 4),
+            vec![(0, vec![], vec![1, 2]), (1, vec![], vec![2, 3])]
+        );
+        assert_eq!(
+            window_indices(1, 0, 3),
+            vec![(1, vec![0], vec![]), (2, vec![1], vec![])]
+        );
+        assert!(window_indices(1, 1, 2).is_empty());
+    }
+
+    #[test]
+    fn reference_windows_retain_only_the_frames_still_needed() {
+        let mut windows = ReferenceWindows::new(frames(8), 2, 1);
+        let first = windows.next().unwrap().unwrap();
+        let second = windows.next().unwrap().unwrap();
+
+        assert!(Arc::ptr_eq(&first.future[0].1, &second.anchor));
+        assert!(Arc::ptr_eq(&first.anchor, &second.past[0].1));
+        while let Some(window) = windows.next() {
+            window.unwrap();
+            assert!(windows.frames.len() <= 2 + 1 + 1);
+        }
+    }
+
+    #[test]
+    fn reference_window_errors_are_passed_on() {
+        let source = frames(2).chain(std::iter::once(Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "corrupt frame",
+        ))));
+        let mut windows = ReferenceWindows::new(source, 1, 1);
+
+        assert!(windows.next().unwrap().is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "At least one reference frame is required")]
+    fn reference_windows_need_a_reference() {
+        ReferenceWindows::new(frames(2), 0, 0);
+    }
+
+    #[test]
+    fn clips_are_estimated_identically_on_any_thread_count() {
+        let clip = || {
+            (0..6).map(|index| {
+                let shift = (1.5 * index as f64, -0.5 * index as f64);
+                Ok((format!("{}", index), textured_frame(48, 48, shift)))
+            })
+        };
+        let matcher = AdaptiveRoodPatternBlockMatcher::new(6)
+            .with_predictor_mode(PredictorMode::Median)
+            .with_lambda(1.0);
+
+        let estimate_clip = |threads| {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap()
+                .install(|| {
+                    let pairs = FramePairing::AllPairs
+                        .pair_frames(clip())
+                        .map(|pair| pair.unwrap());
+
+                    estimate_pairs(pairs, |pair| {
+                        MotionField::estimate(&pair.anchor.1, &pair.target.1, 16, &matcher)
+                    })
+                    .map(|(pair, motion_field)| {
+                        (pair.anchor_index, pair.target_index, motion_field)
+                    })
+                    .collect::<Vec<_>>()
+                })
+        };
+
+        let estimates = estimate_clip(1);
+        // Results come back in pairing order
+        let indices: Vec<(usize, usize)> = estimates
+            .iter()
+            .map(|(anchor_index, target_index, _)| (*anchor_index, *target_index))
+            .collect();
+        assert_eq!(indices, pair_indices(FramePairing::AllPairs, 6));
+        assert_eq!(estimate_clip(4), estimates);
+    }
+}
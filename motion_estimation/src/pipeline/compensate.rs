@@ -0,0 +1,247 @@
+use std::{io, time::Instant};
+
+use image::ImageResult;
+use itertools::process_results;
+use log::info;
+
+use crate::{
+    bma::BlockMatcher,
+    compensation::CompensationResult,
+    formats::LoadedMotion,
+    motion_field::MotionField,
+    pairing::{estimate_pairs, FramePair},
+    subpel::SubpixelRefiner,
+};
+
+use super::{average, compensate_motion_field, estimate_motion_field, log_compensation_quality};
+
+// Anchors are predicted from motion estimated with a matcher, optionally refined to subpixel
+// positions, or from reloaded motion
+#[derive(Copy, Clone)]
+pub enum MotionSource<'a> {
+    Estimated {
+        matcher: &'a dyn BlockMatcher,
+        mb_size: u32,
+        refiner: Option<&'a SubpixelRefiner>,
+    },
+    Loaded(&'a LoadedMotion),
+}
+
+impl MotionSource<'_> {
+    // Estimated motion fields are returned along with the prediction
+    fn compensate(
+        &self,
+        pair: &FramePair,
+    ) -> io::Result<(Option<MotionField>, CompensationResult)> {
+        match self {
+            MotionSource::Estimated {
+                matcher,
+                mb_size,
+                refiner,
+            } => {
+                let motion_field = estimate_motion_field(pair, *mb_size, *matcher);
+                let compensation_result = compensate_motion_field(pair, &motion_field, *refiner);
+
+                Ok((Some(motion_field), compensation_result))
+            }
+            MotionSource::Loaded(loaded_motion) => {
+                let predicted_frame = loaded_motion.predict(pair)?;
+
+                Ok((
+                    None,
+                    CompensationResult::new(&pair.anchor.1, predicted_frame),
+                ))
+            }
+        }
+    }
+
+    fn is_estimated(&self) -> bool {
+        matches!(self, MotionSource::Estimated { .. })
+    }
+}
+
+// Predictions and residuals amplified by the gain are saved for every pair
+pub fn compensate_pairs(
+    pairs: impl Iterator<Item = io::Result<FramePair>>,
+    motion_source: MotionSource,
+    output_folder: &str,
+    gain: f64,
+) -> ImageResult<()> {
+    let (mut field_errors, mut compensation_results) = (Vec::new(), Vec::new());
+    let start_time = Instant::now();
+
+    process_results(pairs, |pairs| -> ImageResult<()> {
+        let estimates = estimate_pairs(pairs, |pair| motion_source.compensate(pair));
+
+        for (pair, compensation) in estimates {
+            let (motion_field, compensation_result) = compensation?;
+            field_errors.extend(motion_field.map(|motion_field| motion_field.average_cost()));
+
+            compensation_result.export(output_folder, &pair.file_stem(), gain)?;
+            compensation_results.push(compensation_result);
+        }
+
+        Ok(())
+    })??;
+
+    if motion_source.is_estimated() {
+        info!(" Average motion field error: {}", average(&field_errors));
+    }
+    log_compensation_quality(&compensation_results);
+    info!(" Execution time: {}s", start_time.elapsed().as_secs_f64());
+
+    Ok(())
+}
+
+// Logs the compensation quality of the motion, and for estimated motion the distortion,
+// rate and search effort of the matcher
+pub fn compare_motion(
+    pairs: impl Iterator<Item = io::Result<FramePair>>,
+    motion_source: MotionSource,
+) -> ImageResult<()> {
+    let (mut field_errors, mut points_evaluated, mut points_pruned) =
+        (Vec::new(), Vec::new(), Vec::new());
+    let (mut vector_bits, mut compensation_results) = (Vec::new(), Vec::new());
+    let start_time = Instant::now();
+
+    process_results(pairs, |pairs| -> ImageResult<()> {
+        let estimates = estimate_pairs(pairs, |pair| motion_source.compensate(pair));
+
+        for (_, compensation) in estimates {
+            let (motion_field, compensation_result) = compensation?;
+
+            if let Some(motion_field) = motion_field {
+                field_errors.push(motion_field.average_cost());
+                vector_bits.push(motion_field.average_vector_bits());
+                points_evaluated.extend(
+                    motion_field
+                        .blocks
+                        .iter()
+                        .map(|block| block.vector.points_evaluated as f64),
+                );
+                points_pruned.extend(
+                    motion_field
+                        .blocks
+                        .iter()
+                        .map(|block| block.vector.points_pruned as f64),
+                );
+            }
+            compensation_results.push(compensation_result);
+        }
+
+        Ok(())
+    })??;
+
+    if motion_source.is_estimated() {
+        info!(" Average motion field error: {}", average(&field_errors));
+        info!(" Average vector bits per block: {}", average(&vector_bits));
+        info!(
+            " Average points evaluated per block: {}",
+            average(&points_evaluated)
+        );
+        if points_pruned.iter().any(|pruned| *pruned > 0.0) {
+            info!(
+                " Average points pruned per block: {}",
+                average(&points_pruned)
+            );
+        }
+    }
+    log_compensation_quality(&compensation_results);
+    info!(" Execution time: {}s", start_time.elapsed().as_secs_f64());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc};
+
+    use super::*;
+    use crate::{
+        bma::exhaustive::ExhaustiveBlockMatcher,
+        subpel::{interpolation::InterpolationFilter, SubpixelPrecision},
+        test_utils::{shifted_pairs, temp_folder, textured_frame},
+    };
+
+    #[test]
+    fn predictions_and_residuals_are_saved_per_pair() {
+        let output_folder = temp_folder("pipeline_compensate");
+        let matcher = ExhaustiveBlockMatcher::new(4);
+        let motion_source = MotionSource::Estimated {
+            matcher: &matcher,
+            mb_size: 16,
+            refiner: None,
+        };
+
+        compensate_pairs(
+            shifted_pairs(3),
+            motion_source,
+            output_folder.to_str().unwrap(),
+            4.0,
+        )
+        .unwrap();
+
+        for file_name in [
+            "0001_0002_prediction.png",
+            "0001_0002_residual.png",
+            "0002_0003_prediction.png",
+            "0002_0003_residual.png",
+        ] {
+            assert!(output_folder.join(file_name).is_file(), "{}", file_name);
+        }
+    }
+
+    #[test]
+    fn loaded_motion_needs_a_field_for_every_pair() {
+        let matcher = ExhaustiveBlockMatcher::new(4);
+        let motion_source = MotionSource::Estimated {
+            matcher: &matcher,
+            mb_size: 16,
+            refiner: None,
+        };
+        let pair = shifted_pairs(2).next().unwrap().unwrap();
+        let (motion_field, _) = motion_source.compensate(&pair).unwrap();
+
+        let loaded_motion = LoadedMotion::Fields(HashMap::from([((0, 1), motion_field.unwrap())]));
+        assert!(compare_motion(shifted_pairs(2), MotionSource::Loaded(&loaded_motion)).is_ok());
+        assert!(compare_motion(shifted_pairs(3), MotionSource::Loaded(&loaded_motion)).is_err());
+        assert!(compare_motion(shifted_pairs(3), motion_source).is_ok());
+    }
+
+    #[test]
+    fn quarter_pel_predictions_beat_whole_pixels_on_subpixel_motion() {
+        let pair = FramePair {
+            anchor_index: 0,
+            target_index: 1,
+            anchor: Arc::new(("0001.png".to_owned(), textured_frame(64, 48, (0.0, 0.0)))),
+            target: Arc::new(("0002.png".to_owned(), textured_frame(64, 48, (1.75, 1.25)))),
+        };
+        let matcher = ExhaustiveBlockMatcher::new(4);
+        let refiner =
+            SubpixelRefiner::new(SubpixelPrecision::Quarter, InterpolationFilter::H264SixTap);
+
+        // Squared errors of the blocks whose match stays inside the frame
+        let interior_error = |refiner| {
+            let motion_source = MotionSource::Estimated {
+                matcher: &matcher,
+                mb_size: 16,
+                refiner,
+            };
+            let (_, compensation_result) = motion_source.compensate(&pair).unwrap();
+            let anchor_frame = pair.anchor.1.to_luma8();
+
+            compensation_result
+                .predicted_frame
+                .enumerate_pixels()
+                .filter(|(x, y, _)| *x < 48 && *y < 32)
+                .map(|(x, y, pixel)| {
+                    (pixel[0] as f64 - anchor_frame.get_pixel(x, y)[0] as f64).powi(2)
+                })
+                .sum::<f64>()
+        };
+
+        let (whole_pixel_error, quarter_pel_error) =
+            (interior_error(None), interior_error(Some(&refiner)));
+        assert!(quarter_pel_error < whole_pixel_error / 4.0);
+    }
+}
@@ -0,0 +1,424 @@
+use std::{io, ops::Div, time::Instant};
+
+use image::{DynamicImage, ImageResult};
+use itertools::process_results;
+use log::{debug, info};
+
+use crate::{
+    bma::BlockMatcher,
+    formats::MotionExport,
+    hierarchical::HierarchicalMotionEstimator,
+    motion_field::MotionField,
+    pairing::{estimate_pairs, frame_stem, FramePair},
+    plane::{luma_frame, LumaPlane},
+    quadtree::QuadtreeMotionEstimator,
+    subpel::{FractionalMotionVector, SubpixelRefiner},
+    types::{ExtractedBlock, SearchContext},
+    utils::{crop_block_from_image, crop_target_block, export_block},
+    visualisation::Visualisation,
+};
+
+use super::{average, estimate_motion_field};
+
+// A single block whose anchor pixels and predictions are exported for every pair, under
+// <output_folder>/original and <output_folder>/<matcher_name>
+pub struct BlockPrediction {
+    pub position: (u32, u32),
+    pub output_folder: String,
+    pub matcher_name: String,
+}
+
+pub fn estimate_motion_fields(
+    pairs: impl Iterator<Item = io::Result<FramePair>>,
+    mb_size: u32,
+    matcher: &dyn BlockMatcher,
+    refiner: Option<&SubpixelRefiner>,
+    block_prediction: Option<&BlockPrediction>,
+    mut visualisation: Option<Visualisation>,
+    mut export: Option<MotionExport>,
+) -> ImageResult<()> {
+    let (mut block_errors, mut field_errors, mut refined_errors) =
+        (Vec::new(), Vec::new(), Vec::new());
+    let mut vector_bits = Vec::new();
+    let start_time = Instant::now();
+
+    process_results(pairs, |pairs| -> ImageResult<()> {
+        let estimates = estimate_pairs(pairs, |pair| {
+            let block_error = block_prediction
+                .map(|block_prediction| {
+                    predict_with_matcher(pair, mb_size, block_prediction, matcher)
+                })
+                .transpose()?;
+            let motion_field = estimate_motion_field(pair, mb_size, matcher);
+            let refined = refiner.map(|refiner| refine_motion_field(pair, &motion_field, refiner));
+
+            ImageResult::Ok((block_error, motion_field, refined))
+        });
+
+        for (pair, estimate) in estimates {
+            let (block_error, motion_field, refined) = estimate?;
+            block_errors.extend(block_error);
+            field_errors.push(motion_field.average_cost());
+            vector_bits.push(motion_field.average_vector_bits());
+
+            // Visualisations and .flo files show the refined vectors when there are some
+            match refined {
+                Some(refined_vectors) => {
+                    refined_errors.extend(refined_vectors.iter().map(|vector| vector.cost));
+
+                    if let Some(visualisation) = visualisation.as_mut() {
+                        visualisation.export_refined(
+                            &pair,
+                            &motion_field.blocks,
+                            &refined_vectors,
+                        )?;
+                    }
+                    if let Some(export) = export.as_mut() {
+                        export.add_refined(&pair, motion_field, &refined_vectors)?;
+                    }
+                }
+                None => {
+                    if let Some(visualisation) = visualisation.as_mut() {
+                        visualisation.export(&pair, &motion_field.blocks)?;
+                    }
+                    if let Some(export) = export.as_mut() {
+                        export.add(&pair, motion_field)?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    })??;
+
+    if let Some(export) = export {
+        export.finish()?;
+    }
+
+    if block_prediction.is_some() {
+        info!(" Average error: {}", average(&block_errors));
+    }
+    info!(" Average motion field error: {}", average(&field_errors));
+    info!(" Average vector bits per block: {}", average(&vector_bits));
+    if refiner.is_some() {
+        info!(" Average refined block error: {}", average(&refined_errors));
+    }
+    info!(" Execution time: {}s", start_time.elapsed().as_secs_f64());
+
+    Ok(())
+}
+
+pub fn estimate_hierarchical_motion_fields(
+    pairs: impl Iterator<Item = io::Result<FramePair>>,
+    mb_size: u32,
+    estimator: &HierarchicalMotionEstimator,
+    mut export: Option<MotionExport>,
+) -> ImageResult<()> {
+    let mut field_errors = Vec::new();
+    let start_time = Instant::now();
+
+    process_results(pairs, |pairs| -> ImageResult<()> {
+        let estimates = estimate_pairs(pairs, |pair| {
+            let (_, anchor_frame) = pair.anchor.as_ref();
+            let (_, target_frame) = pair.target.as_ref();
+
+            estimator.estimate(anchor_frame, target_frame, mb_size)
+        });
+
+        for (pair, motion_field) in estimates {
+            field_errors.push(motion_field.average_cost());
+
+            if let Some(export) = export.as_mut() {
+                export.add(&pair, motion_field)?;
+            }
+        }
+
+        Ok(())
+    })??;
+
+    if let Some(export) = export {
+        export.finish()?;
+    }
+
+    info!(" Average motion field error: {}", average(&field_errors));
+    info!(
+        " Motion fields execution time: {}s",
+        start_time.elapsed().as_secs_f64()
+    );
+
+    Ok(())
+}
+
+pub fn estimate_quadtree_motion_fields(
+    pairs: impl Iterator<Item = io::Result<FramePair>>,
+    estimator: &QuadtreeMotionEstimator,
+    mut visualisation: Option<Visualisation>,
+) -> ImageResult<()> {
+    let mut field_count = 0;
+    let mut leaf_errors = Vec::new();
+    let start_time = Instant::now();
+
+    process_results(pairs, |pairs| -> ImageResult<()> {
+        let estimates = estimate_pairs(pairs, |pair| {
+            let (_, anchor_frame) = pair.anchor.as_ref();
+            let (_, target_frame) = pair.target.as_ref();
+
+            estimator.estimate(anchor_frame, target_frame)
+        });
+
+        for (pair, motion_field) in estimates {
+            debug!(
+                "{}x{} trees of {} pixels blocks",
+                motion_field.cols, motion_field.rows, motion_field.max_block_size
+            );
+
+            if let Some(visualisation) = visualisation.as_mut() {
+                visualisation.export(&pair, motion_field.leaves())?;
+            }
+
+            field_count += 1;
+            leaf_errors.extend(motion_field.leaves().iter().map(|leaf| leaf.vector.cost));
+        }
+
+        Ok(())
+    })??;
+
+    info!(
+        " Average partitions per frame: {}",
+        (leaf_errors.len() as f64).div(field_count as f64)
+    );
+    info!(" Average partition error: {}", average(&leaf_errors));
+    info!(
+        " Motion fields execution time: {}s",
+        start_time.elapsed().as_secs_f64()
+    );
+
+    Ok(())
+}
+
+fn predict_with_matcher(
+    pair: &FramePair,
+    mb_size: u32,
+    block_prediction: &BlockPrediction,
+    matcher: &dyn BlockMatcher,
+) -> ImageResult<f64> {
+    let (anchor_frame_id, anchor_frame) = pair.anchor.as_ref();
+    let (target_frame_id, target_frame) = pair.target.as_ref();
+    let (x_offset, y_offset) = block_prediction.position;
+
+    debug!(
+        "Matching block from anchor {} to target frame {}",
+        anchor_frame_id, target_frame_id
+    );
+
+    let pixels = crop_block_from_image(anchor_frame, x_offset, y_offset, mb_size)?;
+    export_block(
+        &format!("{}/original", block_prediction.output_folder),
+        &pixels,
+        &format!("{}.png", frame_stem(anchor_frame_id)),
+    )?;
+
+    let anchor_block = ExtractedBlock {
+        x_offset,
+        y_offset,
+        pixels: DynamicImage::ImageLuma8(pixels),
+    };
+
+    let target_plane = luma_frame(target_frame);
+    let vector = matcher.match_block(
+        &anchor_block,
+        &LumaPlane::from_image(&target_plane),
+        &SearchContext::default(),
+    );
+    let target_block = crop_target_block(&anchor_block, &vector, target_frame);
+
+    export_block(
+        &format!(
+            "{}/{}",
+            block_prediction.output_folder, block_prediction.matcher_name
+        ),
+        target_block.pixels.as_luma8().unwrap(),
+        &format!(
+            "{}_{}.png",
+            frame_stem(anchor_frame_id),
+            frame_stem(target_frame_id)
+        ),
+    )?;
+
+    debug!(
+        "Error between anchor {} and target {}: {} ({} points evaluated)",
+        pair.anchor_index, pair.target_index, vector.cost, vector.points_evaluated
+    );
+
+    Ok(vector.cost)
+}
+
+fn refine_motion_field(
+    pair: &FramePair,
+    motion_field: &MotionField,
+    refiner: &SubpixelRefiner,
+) -> Vec<FractionalMotionVector> {
+    let (_, anchor_frame) = pair.anchor.as_ref();
+    let (_, target_frame) = pair.target.as_ref();
+
+    let refined_vectors = motion_field.refine_subpixel(anchor_frame, target_frame, refiner);
+
+    debug!(
+        "Central block refined to a {:?} vector",
+        refined_vectors[motion_field.blocks.len() / 2].as_pixels()
+    );
+
+    refined_vectors
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, io::BufReader};
+
+    use super::*;
+    use crate::{
+        bma::exhaustive::ExhaustiveBlockMatcher,
+        formats::{flo::read_flo, read_motion_fields, ExportFormat},
+        pairing::FramePairing,
+        subpel::{interpolation::InterpolationFilter, SubpixelPrecision},
+        test_utils::{shifted_pairs, temp_folder, textured_frame},
+        visualisation::VectorOverlay,
+    };
+
+    #[test]
+    fn motion_fields_are_exported_with_the_block_predictions() {
+        let output_folder = temp_folder("pipeline_estimate");
+        let output = output_folder.to_str().unwrap();
+        let matcher = ExhaustiveBlockMatcher::new(4);
+        let block_prediction = BlockPrediction {
+            position: (16, 16),
+            output_folder: format!("{}/block", output),
+            matcher_name: "exhaustive".to_owned(),
+        };
+        let export = MotionExport::new(&format!("{}/export", output), &[ExportFormat::Json]);
+
+        estimate_motion_fields(
+            shifted_pairs(3),
+            16,
+            &matcher,
+            None,
+            Some(&block_prediction),
+            None,
+            Some(export.unwrap()),
+        )
+        .unwrap();
+
+        let motion_fields = read_motion_fields(&output_folder.join("export/motion.json")).unwrap();
+        assert_eq!(motion_fields.len(), 2);
+        let block = motion_fields[1].motion_field.block(1, 1);
+        assert_eq!((block.vector.dx, block.vector.dy), (2, -1));
+
+        for file_name in [
+            "original/0001.png",
+            "original/0002.png",
+            "exhaustive/0002_0003.png",
+        ] {
+            let path = output_folder.join("block").join(file_name);
+            assert!(path.is_file(), "{:?}", path);
+        }
+    }
+
+    #[test]
+    fn refined_vectors_are_exported_and_visualised() {
+        let output_folder = temp_folder("pipeline_refined");
+        let output = output_folder.to_str().unwrap();
+        let frames = [(0.0, 0.0), (1.75, 1.25)]
+            .into_iter()
+            .enumerate()
+            .map(|(index, shift)| {
+                Ok((
+                    format!("{:04}.png", index + 1),
+                    textured_frame(64, 48, shift),
+                ))
+            });
+        let refiner =
+            SubpixelRefiner::new(SubpixelPrecision::Quarter, InterpolationFilter::H264SixTap);
+        let visualisation = Visualisation::new(
+            &format!("{}/visualisation", output),
+            VectorOverlay::new(),
+            4.0,
+            None,
+        );
+        let export = MotionExport::new(&format!("{}/export", output), &[ExportFormat::Flo]);
+
+        estimate_motion_fields(
+            FramePairing::Consecutive.pair_frames(frames),
+            16,
+            &ExhaustiveBlockMatcher::new(4),
+            Some(&refiner),
+            None,
+            Some(visualisation.unwrap()),
+            Some(export.unwrap()),
+        )
+        .unwrap();
+
+        // The top left block keeps its match inside the frame, and refines it to the shift
+        let file = File::open(output_folder.join("export/0001_0002.flo")).unwrap();
+        let flow = read_flo(BufReader::new(file)).unwrap();
+        assert_eq!(flow.get(8, 8), (1.75, 1.25));
+        assert!(output_folder
+            .join("visualisation/0001_0002_flow.png")
+            .is_file());
+    }
+
+    #[test]
+    fn hierarchical_and_quadtree_runs_export_their_fields() {
+        let output_folder = temp_folder("pipeline_variable_blocks");
+        let output = output_folder.to_str().unwrap();
+
+        let estimator =
+            HierarchicalMotionEstimator::new(Box::new(ExhaustiveBlockMatcher::new(2)), 2);
+        let export = MotionExport::new(&format!("{}/export", output), &[ExportFormat::Csv]);
+        estimate_hierarchical_motion_fields(
+            shifted_pairs(2),
+            16,
+            &estimator,
+            Some(export.unwrap()),
+        )
+        .unwrap();
+        assert_eq!(
+            read_motion_fields(&output_folder.join("export/motion.csv"))
+                .unwrap()
+                .len(),
+            1
+        );
+
+        let estimator =
+            QuadtreeMotionEstimator::new(Box::new(ExhaustiveBlockMatcher::new(4)), 16, 8);
+        let visualisation = Visualisation::new(
+            &format!("{}/visualisation", output),
+            VectorOverlay::new(),
+            4.0,
+            None,
+        );
+        estimate_quadtree_motion_fields(shifted_pairs(2), &estimator, Some(visualisation.unwrap()))
+            .unwrap();
+        assert!(output_folder
+            .join("visualisation/0001_0002_vectors.png")
+            .is_file());
+    }
+
+    #[test]
+    fn read_errors_end_the_run() {
+        let pairs = shifted_pairs(2).chain(std::iter::once(Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "corrupt frame",
+        ))));
+
+        let result = estimate_motion_fields(
+            pairs,
+            16,
+            &ExhaustiveBlockMatcher::new(4),
+            None,
+            None,
+            None,
+            None,
+        );
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,102 @@
+use std::{io, time::Instant};
+
+use image::ImageResult;
+use itertools::process_results;
+use log::{debug, info};
+
+use crate::{
+    compensation::{compensate_frame_with_flow, CompensationResult},
+    formats::MotionExport,
+    optical_flow::FlowEstimator,
+    pairing::{estimate_pairs, FramePair},
+    visualisation::Visualisation,
+};
+
+use super::log_compensation_quality;
+
+// Visualisations draw one arrow per grid cell of the dense flow
+pub fn estimate_flow_fields(
+    pairs: impl Iterator<Item = io::Result<FramePair>>,
+    estimator: &dyn FlowEstimator,
+    grid_size: u32,
+    mut visualisation: Option<Visualisation>,
+    mut export: Option<MotionExport>,
+) -> ImageResult<()> {
+    let mut compensation_results = Vec::new();
+    let start_time = Instant::now();
+
+    process_results(pairs, |pairs| -> ImageResult<()> {
+        let estimates = estimate_pairs(pairs, |pair| {
+            let (_, anchor_frame) = pair.anchor.as_ref();
+            let (_, target_frame) = pair.target.as_ref();
+
+            let flow = estimator.estimate(anchor_frame, target_frame);
+            let predicted_frame = compensate_frame_with_flow(target_frame, &flow);
+
+            (flow, CompensationResult::new(anchor_frame, predicted_frame))
+        });
+
+        for (pair, (flow, compensation_result)) in estimates {
+            debug!(
+                "Flow from anchor {} to target frame {}, largest vector {}",
+                pair.anchor_index,
+                pair.target_index,
+                flow.max_magnitude()
+            );
+
+            if let Some(visualisation) = visualisation.as_mut() {
+                visualisation.export_flow(&pair, &flow, grid_size)?;
+            }
+            if let Some(export) = export.as_mut() {
+                export.add_flow(&pair, &flow)?;
+            }
+
+            compensation_results.push(compensation_result);
+        }
+
+        Ok(())
+    })??;
+
+    if let Some(export) = export {
+        export.finish()?;
+    }
+
+    log_compensation_quality(&compensation_results);
+    info!(" Execution time: {}s", start_time.elapsed().as_secs_f64());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, io::BufReader};
+
+    use super::*;
+    use crate::{
+        formats::{flo::read_flo, ExportFormat},
+        optical_flow::lucas_kanade::LucasKanadeFlowEstimator,
+        test_utils::{shifted_pairs, temp_folder},
+    };
+
+    #[test]
+    fn flow_fields_are_exported_per_pair() {
+        let output_folder = temp_folder("pipeline_flow");
+        let output = output_folder.to_str().unwrap();
+        let export = MotionExport::new(output, &[ExportFormat::Flo]).unwrap();
+
+        estimate_flow_fields(
+            shifted_pairs(3),
+            &LucasKanadeFlowEstimator::new(9, 2),
+            16,
+            None,
+            Some(export),
+        )
+        .unwrap();
+
+        for file_name in ["0001_0002.flo", "0002_0003.flo"] {
+            let file = File::open(output_folder.join(file_name)).unwrap();
+            let flow = read_flo(BufReader::new(file)).unwrap();
+            assert_eq!((flow.width, flow.height), (48, 32));
+        }
+    }
+}
@@ -0,0 +1,173 @@
+// The runs behind each command of the binary. They take frames as read from their source,
+// stop at the first read error, and log their averages once every frame is done.
+pub mod compensate;
+pub mod estimate;
+pub mod flow;
+pub mod references;
+
+use std::{io, ops::Div, path::Path};
+
+use log::{debug, info};
+
+use crate::{
+    bma::BlockMatcher,
+    compensation::{compensate_frame, compensate_frame_with_subpixel, CompensationResult},
+    motion_field::MotionField,
+    pairing::{FramePair, FramePairing, ReferenceWindow, ReferenceWindows},
+    source::{open_frame_source, raw_yuv::RawYuvFormat},
+    subpel::SubpixelRefiner,
+};
+
+pub fn open_frame_pairs(
+    input: &Path,
+    raw_format: Option<RawYuvFormat>,
+    pairing: FramePairing,
+) -> io::Result<impl Iterator<Item = io::Result<FramePair>>> {
+    Ok(pairing.pair_frames(open_frame_source(input, raw_format)?))
+}
+
+pub fn open_reference_windows(
+    input: &Path,
+    raw_format: Option<RawYuvFormat>,
+    past_references: usize,
+    future_references: usize,
+) -> io::Result<impl Iterator<Item = io::Result<ReferenceWindow>>> {
+    Ok(ReferenceWindows::new(
+        open_frame_source(input, raw_format)?,
+        past_references,
+        future_references,
+    ))
+}
+
+fn average(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>().div(values.len() as f64)
+}
+
+fn estimate_motion_field(
+    pair: &FramePair,
+    mb_size: u32,
+    matcher: &dyn BlockMatcher,
+) -> MotionField {
+    let (anchor_frame_id, anchor_frame) = pair.anchor.as_ref();
+    let (target_frame_id, target_frame) = pair.target.as_ref();
+
+    debug!(
+        "Estimating motion field from anchor {} to target frame {}",
+        anchor_frame_id, target_frame_id
+    );
+
+    let motion_field = MotionField::estimate(anchor_frame, target_frame, mb_size, matcher);
+
+    let central_block = motion_field.block(motion_field.cols / 2, motion_field.rows / 2);
+    debug!(
+        "{}x{} blocks of size {}, central block vector: ({}, {})",
+        motion_field.cols,
+        motion_field.rows,
+        motion_field.mb_size,
+        central_block.vector.dx,
+        central_block.vector.dy
+    );
+
+    motion_field
+}
+
+// With a refiner, blocks are predicted from their refined subpixel positions
+fn compensate_motion_field(
+    pair: &FramePair,
+    motion_field: &MotionField,
+    refiner: Option<&SubpixelRefiner>,
+) -> CompensationResult {
+    let (anchor_frame_id, anchor_frame) = pair.anchor.as_ref();
+    let (target_frame_id, target_frame) = pair.target.as_ref();
+
+    let predicted_frame = match refiner {
+        Some(refiner) => {
+            let refined_vectors = motion_field.refine_subpixel(anchor_frame, target_frame, refiner);
+            compensate_frame_with_subpixel(target_frame, motion_field, &refined_vectors, refiner)
+        }
+        None => compensate_frame(target_frame, motion_field),
+    };
+    let compensation_result = CompensationResult::new(anchor_frame, predicted_frame);
+
+    debug!(
+        "Compensated anchor {} from target frame {}, PSNR: {}dB, SSIM: {}",
+        anchor_frame_id, target_frame_id, compensation_result.psnr, compensation_result.ssim
+    );
+
+    compensation_result
+}
+
+fn log_compensation_quality(compensation_results: &[CompensationResult]) {
+    let psnr_values: Vec<f64> = compensation_results
+        .iter()
+        .map(|result| result.psnr)
+        .collect();
+    let ssim_values: Vec<f64> = compensation_results
+        .iter()
+        .map(|result| result.ssim)
+        .collect();
+
+    info!(" Average compensated PSNR: {}dB", average(&psnr_values));
+    info!(" Average compensated SSIM: {}", average(&ssim_values));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bma::exhaustive::ExhaustiveBlockMatcher,
+        test_utils::{shifted_pairs, temp_folder, textured_frame},
+    };
+
+    #[test]
+    fn frame_pairs_are_opened_from_image_sequences() {
+        let input_folder = temp_folder("pipeline_input");
+        for index in 0..3 {
+            textured_frame(32, 16, (index as f64, 0.0))
+                .save(input_folder.join(format!("{:04}.png", index + 1)))
+                .unwrap();
+        }
+
+        let pairs: Vec<FramePair> = open_frame_pairs(&input_folder, None, FramePairing::AllPairs)
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(pairs.len(), 3);
+        assert_eq!(pairs[2].file_stem(), "0002_0003");
+
+        let windows: Vec<ReferenceWindow> = open_reference_windows(&input_folder, None, 1, 1)
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(windows.len(), 1);
+
+        assert!(open_frame_pairs(
+            &input_folder.join("missing.y4m"),
+            None,
+            FramePairing::Consecutive
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn motion_fields_compensate_their_anchor() {
+        let pair = shifted_pairs(2).next().unwrap().unwrap();
+        let matcher = ExhaustiveBlockMatcher::new(4);
+
+        let motion_field = estimate_motion_field(&pair, 16, &matcher);
+        let block = motion_field.block(1, 1);
+        assert_eq!(
+            (block.vector.dx, block.vector.dy, block.vector.cost),
+            (2, -1, 0.0)
+        );
+
+        let uncompensated = CompensationResult::new(&pair.anchor.1, pair.target.1.to_luma8());
+        assert!(compensate_motion_field(&pair, &motion_field, None).psnr > uncompensated.psnr);
+    }
+
+    #[test]
+    fn averages_are_arithmetic_means() {
+        assert_eq!(average(&[1.0, 2.0, 6.0]), 3.0);
+        assert!(average(&[]).is_nan());
+    }
+}
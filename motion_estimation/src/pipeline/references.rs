@@ -0,0 +1,236 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter},
+    time::Instant,
+};
+
+use image::{DynamicImage, ImageResult};
+use itertools::process_results;
+use log::{debug, info};
+
+use crate::{
+    compensation::CompensationResult,
+    formats::{delimited::write_reference_csv, FrameReferenceMotionField},
+    multi_reference::{MultiReferenceMotionEstimator, PredictionMode},
+    pairing::{estimate_pairs, ReferenceWindow},
+};
+
+use super::{average, log_compensation_quality};
+
+// The chosen modes and references of every block are reported in <output_folder>/modes.csv
+pub fn estimate_reference_motion_fields(
+    windows: impl Iterator<Item = io::Result<ReferenceWindow>>,
+    estimator: &MultiReferenceMotionEstimator,
+    mb_size: u32,
+    output_folder: &str,
+) -> ImageResult<()> {
+    let (mut field_errors, mut compensation_results, mut motion_fields) =
+        (Vec::new(), Vec::new(), Vec::new());
+    let start_time = Instant::now();
+
+    process_results(windows, |windows| {
+        let estimates = estimate_pairs(windows, |window| {
+            let (anchor_frame_id, anchor_frame) = window.anchor.as_ref();
+            let past_frames: Vec<&DynamicImage> =
+                window.past.iter().map(|(_, frame)| &frame.1).collect();
+            let future_frames: Vec<&DynamicImage> =
+                window.future.iter().map(|(_, frame)| &frame.1).collect();
+
+            let motion_field =
+                estimator.estimate(anchor_frame, &past_frames, &future_frames, mb_size);
+            let compensation_result = CompensationResult::new(
+                anchor_frame,
+                motion_field.compensate(&past_frames, &future_frames),
+            );
+
+            debug!(
+                "Compensated anchor {} from {} references, PSNR: {}dB, SSIM: {}",
+                anchor_frame_id,
+                past_frames.len() + future_frames.len(),
+                compensation_result.psnr,
+                compensation_result.ssim
+            );
+
+            (motion_field, compensation_result)
+        });
+
+        for (window, (motion_field, compensation_result)) in estimates {
+            field_errors.push(motion_field.average_cost());
+            compensation_results.push(compensation_result);
+            motion_fields.push(FrameReferenceMotionField {
+                anchor_frame_index: window.anchor_index,
+                past_frame_indices: window.past.iter().map(|(index, _)| *index).collect(),
+                future_frame_indices: window.future.iter().map(|(index, _)| *index).collect(),
+                motion_field,
+            });
+        }
+    })?;
+
+    fs::create_dir_all(output_folder)?;
+    let report = BufWriter::new(File::create(format!("{}/modes.csv", output_folder))?);
+    write_reference_csv(report, &motion_fields)?;
+
+    info!(" Average motion field error: {}", average(&field_errors));
+    ReferenceUsage::new(&motion_fields).log();
+    log_compensation_quality(&compensation_results);
+    info!(" Execution time: {}s", start_time.elapsed().as_secs_f64());
+
+    Ok(())
+}
+
+// Percentages of blocks using each prediction mode, and referencing each past frame, nearest
+// first. Bidirectional blocks count towards the past frame they average.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReferenceUsage {
+    pub modes: Vec<(PredictionMode, f64)>,
+    pub past_references: Vec<f64>,
+}
+
+impl ReferenceUsage {
+    pub fn new(motion_fields: &[FrameReferenceMotionField]) -> Self {
+        let blocks: Vec<_> = motion_fields
+            .iter()
+            .flat_map(|frame_motion_field| &frame_motion_field.motion_field.blocks)
+            .collect();
+        let share = |count: usize| 100.0 * count as f64 / blocks.len() as f64;
+
+        let modes = [
+            PredictionMode::Backward,
+            PredictionMode::Forward,
+            PredictionMode::Bidirectional,
+        ]
+        .into_iter()
+        .map(|mode| {
+            let count = blocks.iter().filter(|block| block.mode == mode).count();
+            (mode, share(count))
+        })
+        .collect();
+
+        let past_reference_count = motion_fields
+            .iter()
+            .map(|frame_motion_field| frame_motion_field.past_frame_indices.len())
+            .max()
+            .unwrap_or(0);
+        let past_references = (0..past_reference_count)
+            .map(|reference| {
+                let count = blocks
+                    .iter()
+                    .filter_map(|block| block.backward)
+                    .filter(|backward| backward.reference == reference)
+                    .count();
+                share(count)
+            })
+            .collect();
+
+        Self {
+            modes,
+            past_references,
+        }
+    }
+
+    fn log(&self) {
+        for (mode, share) in &self.modes {
+            info!(" {:?} predicted blocks: {}%", mode, share);
+        }
+        for (reference, share) in self.past_references.iter().enumerate() {
+            info!(" Blocks referencing frame t-{}: {}%", reference + 1, share);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bma::naive::NaiveBlockMatcher,
+        multi_reference::{ReferenceBlockMotion, ReferenceMotionField, ReferenceVector},
+        pairing::ReferenceWindows,
+        test_utils::{temp_folder, textured_frame},
+        types::MotionVector,
+    };
+
+    fn reference(reference: usize) -> Option<ReferenceVector> {
+        Some(ReferenceVector {
+            reference,
+            vector: MotionVector {
+                dx: 0,
+                dy: 0,
+                cost: 0.0,
+                points_evaluated: 1,
+                points_pruned: 0,
+            },
+        })
+    }
+
+    fn block(mode: PredictionMode, backward: Option<usize>) -> ReferenceBlockMotion {
+        ReferenceBlockMotion {
+            x_offset: 0,
+            y_offset: 0,
+            width: 16,
+            height: 16,
+            mode,
+            backward: backward.and_then(reference),
+            forward: match mode {
+                PredictionMode::Backward => None,
+                _ => reference(0),
+            },
+            cost: 0.0,
+        }
+    }
+
+    #[test]
+    fn usage_shares_count_bidirectional_blocks_as_backward_references() {
+        let motion_field = FrameReferenceMotionField {
+            anchor_frame_index: 2,
+            past_frame_indices: vec![1, 0],
+            future_frame_indices: vec![3],
+            motion_field: ReferenceMotionField {
+                mb_size: 16,
+                cols: 4,
+                rows: 1,
+                blocks: vec![
+                    block(PredictionMode::Backward, Some(0)),
+                    block(PredictionMode::Backward, Some(1)),
+                    block(PredictionMode::Forward, None),
+                    block(PredictionMode::Bidirectional, Some(0)),
+                ],
+            },
+        };
+
+        assert_eq!(
+            ReferenceUsage::new(&[motion_field]),
+            ReferenceUsage {
+                modes: vec![
+                    (PredictionMode::Backward, 50.0),
+                    (PredictionMode::Forward, 25.0),
+                    (PredictionMode::Bidirectional, 25.0),
+                ],
+                past_references: vec![50.0, 25.0],
+            }
+        );
+    }
+
+    #[test]
+    fn modes_are_reported_for_every_block() {
+        let output_folder = temp_folder("pipeline_references");
+        let frames = (0..4).map(|index| {
+            Ok((
+                format!("{:04}.png", index + 1),
+                textured_frame(32, 32, (index as f64, 0.0)),
+            ))
+        });
+        let estimator = MultiReferenceMotionEstimator::new(Box::new(NaiveBlockMatcher::new()));
+
+        estimate_reference_motion_fields(
+            ReferenceWindows::new(frames, 2, 1),
+            &estimator,
+            16,
+            output_folder.to_str().unwrap(),
+        )
+        .unwrap();
+
+        // Anchors 1 and 2 have a next frame, with 4 blocks each
+        let report = fs::read_to_string(output_folder.join("modes.csv")).unwrap();
+        assert_eq!(report.lines().count(), 1 + 2 * 4);
+    }
+}
@@ -0,0 +1,99 @@
+use std::borrow::Cow;
+
+use image::{DynamicImage, GrayImage};
+
+// A borrowed 8-bit luma region, rows are `stride` bytes apart in the underlying buffer
+#[derive(Copy, Clone, Debug)]
+pub struct LumaPlane<'a> {
+    data: &'a [u8],
+    width: u32,
+    height: u32,
+    stride: usize,
+}
+
+impl<'a> LumaPlane<'a> {
+    pub fn new(data: &'a [u8], width: u32, height: u32, stride: usize) -> Self {
+        assert!(stride >= width as usize, "Stride must cover a whole row");
+        assert!(
+            height == 0 || data.len() >= (height as usize - 1) * stride + width as usize,
+            "Plane buffer is too small for its dimensions"
+        );
+
+        Self {
+            data,
+            width,
+            height,
+            stride,
+        }
+    }
+
+    pub fn from_image(image: &'a GrayImage) -> Self {
+        let (width, height) = image.dimensions();
+        Self::new(image.as_raw(), width, height, width as usize)
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+
+    pub fn row(&self, y: u32) -> &'a [u8] {
+        let start = y as usize * self.stride;
+        &self.data[start..start + self.width as usize]
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &'a [u8]> + '_ {
+        (0..self.height).map(move |y| self.row(y))
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> u8 {
+        self.row(y)[x as usize]
+    }
+
+    // Returns None when the region is not entirely inside the plane
+    pub fn view(&self, x: i32, y: i32, width: u32, height: u32) -> Option<LumaPlane<'a>> {
+        if x < 0
+            || y < 0
+            || x as i64 + width as i64 > self.width as i64
+            || y as i64 + height as i64 > self.height as i64
+        {
+            return None;
+        }
+
+        let start = y as usize * self.stride + x as usize;
+        Some(LumaPlane {
+            data: self.data.get(start..).unwrap_or_default(),
+            width,
+            height,
+            stride: self.stride,
+        })
+    }
+
+    pub fn to_image(&self) -> GrayImage {
+        GrayImage::from_raw(
+            self.width,
+            self.height,
+            self.rows().flatten().copied().collect(),
+        )
+        .unwrap()
+    }
+}
+
+// Frames decoded by the sources are already luma, so this only copies other colour types
+pub fn luma_frame(frame: &DynamicImage) -> Cow<'_, GrayImage> {
+    match frame.as_luma8() {
+        Some(frame) => Cow::Borrowed(frame),
+        None => Cow::Owned(frame.to_luma8()),
+    }
+}
@@ -0,0 +1,45 @@
+use image::{DynamicImage, GrayImage, Luma};
+
+const BINOMIAL_KERNEL: [u32; 5] = [1, 4, 6, 4, 1];
+
+pub fn build_gaussian_pyramid(frame: &DynamicImage, levels: usize) -> Vec<DynamicImage> {
+    let mut pyramid = vec![DynamicImage::ImageLuma8(frame.to_luma8())];
+
+    for _ in 1..levels {
+        let finer_level = pyramid.last().unwrap().as_luma8().unwrap();
+        pyramid.push(DynamicImage::ImageLuma8(downsample(finer_level)));
+    }
+
+    pyramid
+}
+
+// Binomial 5x5 smoothing followed by a 2:1 decimation
+pub fn downsample(frame: &GrayImage) -> GrayImage {
+    let (width, height) = frame.dimensions();
+    let pixel = |x: i64, y: i64| {
+        let x = x.clamp(0, width as i64 - 1) as u32;
+        let y = y.clamp(0, height as i64 - 1) as u32;
+        frame.get_pixel(x, y).0[0] as u32
+    };
+
+    GrayImage::from_fn(width.div_ceil(2), height.div_ceil(2), |x, y| {
+        let (centre_x, centre_y) = (2 * x as i64, 2 * y as i64);
+
+        let sum: u32 = BINOMIAL_KERNEL
+            .iter()
+            .enumerate()
+            .map(|(j, vertical_weight)| {
+                BINOMIAL_KERNEL
+                    .iter()
+                    .enumerate()
+                    .map(|(i, horizontal_weight)| {
+                        horizontal_weight * pixel(centre_x + i as i64 - 2, centre_y + j as i64 - 2)
+                    })
+                    .sum::<u32>()
+                    * vertical_weight
+            })
+            .sum();
+
+        Luma([((sum + 128) / 256) as u8])
+    })
+}
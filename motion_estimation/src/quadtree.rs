@@ -0,0 +1,444 @@
+use image::{DynamicImage, GenericImageView};
+use log::debug;
+use rayon::prelude::*;
+
+use crate::{
+    bma::BlockMatcher,
+    metrics::{sad::SadMetric, DistortionMetric},
+    plane::{luma_frame, LumaPlane},
+    rate::RateConstraint,
+    types::{BlockMotion, MotionVector, SearchContext},
+    utils::calculate_candidate_error,
+    ExtractedBlock,
+};
+
+const SPLIT_FLAG_BITS: f64 = 1.0;
+
+pub enum PartitionNode {
+    Leaf(BlockMotion),
+    Split(Vec<PartitionNode>),
+}
+
+impl PartitionNode {
+    pub fn leaves(&self) -> Vec<&BlockMotion> {
+        match self {
+            PartitionNode::Leaf(motion) => vec![motion],
+            PartitionNode::Split(children) => {
+                children.iter().flat_map(PartitionNode::leaves).collect()
+            }
+        }
+    }
+}
+
+pub struct QuadtreeMotionField {
+    pub max_block_size: u32,
+    pub cols: u32,
+    pub rows: u32,
+    pub roots: Vec<PartitionNode>,
+}
+
+impl QuadtreeMotionField {
+    pub fn leaves(&self) -> Vec<&BlockMotion> {
+        self.roots.iter().flat_map(PartitionNode::leaves).collect()
+    }
+}
+
+pub struct QuadtreeMotionEstimator {
+    matcher: Box<dyn BlockMatcher>,
+    max_block_size: u32,
+    min_block_size: u32,
+    lambda: f64,
+    metric: Box<dyn DistortionMetric>,
+}
+
+impl QuadtreeMotionEstimator {
+    pub fn new(matcher: Box<dyn BlockMatcher>, max_block_size: u32, min_block_size: u32) -> Self {
+        assert!(
+            min_block_size >= 4 && max_block_size >= min_block_size,
+            "Invalid quadtree block sizes"
+        );
+
+        Self {
+            matcher,
+            max_block_size,
+            min_block_size,
+            lambda: 4.0,
+            metric: Box::new(SadMetric::new()),
+        }
+    }
+
+    // Matchers given the same lambda propose the vectors this estimator would rank first
+    pub fn with_lambda(self, lambda: f64) -> Self {
+        Self { lambda, ..self }
+    }
+
+    // The distortion must be additive over sub-blocks for split decisions to be fair
+    pub fn with_metric(self, metric: Box<dyn DistortionMetric>) -> Self {
+        Self { metric, ..self }
+    }
+
+    pub fn estimate(
+        &self,
+        anchor_frame: &DynamicImage,
+        target_frame: &DynamicImage,
+    ) -> QuadtreeMotionField {
+        let (frame_width, frame_height) = anchor_frame.dimensions();
+        let cols = frame_width.div_ceil(self.max_block_size);
+        let rows = frame_height.div_ceil(self.max_block_size);
+        let target_frame = luma_frame(target_frame);
+        let target_plane = LumaPlane::from_image(&target_frame);
+
+        let mut decided = LeafVectors::new(0, 0, frame_width, frame_height, self.min_block_size);
+        let mut roots: Vec<Option<PartitionNode>> = (0..cols * rows).map(|_| None).collect();
+
+        // Leaves are predicted from the leaves on their left, top and top right, so trees on
+        // the same wavefront col + 2 * row are independent and partitioned in parallel
+        for wavefront in 0..(cols + 2 * rows).saturating_sub(2) {
+            let wavefront_roots: Vec<(u32, u32)> = (0..rows)
+                .filter_map(|row| {
+                    wavefront
+                        .checked_sub(2 * row)
+                        .filter(|col| *col < cols)
+                        .map(|col| (col, row))
+                })
+                .collect();
+
+            let nodes: Vec<(usize, PartitionNode)> = wavefront_roots
+                .into_par_iter()
+                .map(|(col, row)| {
+                    let (x_offset, y_offset) =
+                        (col * self.max_block_size, row * self.max_block_size);
+                    let mut vectors = CausalVectors {
+                        frame: &decided,
+                        tree: LeafVectors::new(
+                            x_offset,
+                            y_offset,
+                            u32::min(self.max_block_size, frame_width - x_offset),
+                            u32::min(self.max_block_size, frame_height - y_offset),
+                            self.min_block_size,
+                        ),
+                    };
+                    let (node, cost) = self.partition(
+                        anchor_frame,
+                        &target_plane,
+                        &mut vectors,
+                        x_offset,
+                        y_offset,
+                        self.max_block_size,
+                    );
+
+                    debug!("Block ({}, {}): rate-distortion cost {}", col, row, cost);
+
+                    ((row * cols + col) as usize, node)
+                })
+                .collect();
+
+            for (index, node) in nodes {
+                for leaf in node.leaves() {
+                    decided.insert(leaf);
+                }
+                roots[index] = Some(node);
+            }
+        }
+
+        let roots = roots.into_iter().map(Option::unwrap).collect();
+
+        QuadtreeMotionField {
+            max_block_size: self.max_block_size,
+            cols,
+            rows,
+            roots,
+        }
+    }
+
+    fn partition(
+        &self,
+        anchor_frame: &DynamicImage,
+        target_frame: &LumaPlane,
+        vectors: &mut CausalVectors,
+        x_offset: u32,
+        y_offset: u32,
+        block_size: u32,
+    ) -> (PartitionNode, f64) {
+        let block_width = u32::min(block_size, anchor_frame.width() - x_offset);
+        let block_height = u32::min(block_size, anchor_frame.height() - y_offset);
+
+        let block = ExtractedBlock {
+            x_offset,
+            y_offset,
+            pixels: anchor_frame.crop_imm(x_offset, y_offset, block_width, block_height),
+        };
+
+        let context = vectors.context(x_offset, y_offset, block_size);
+        let rate = RateConstraint::new(self.lambda, &context);
+        let matched = self.matcher.match_block(&block, target_frame, &context);
+
+        // The matcher may rank candidates with another lambda, so its vector is ranked again
+        // against the predicted vector, which costs the fewest bits, and the zero vector
+        let ((dx, dy), distortion) = [(matched.dx, matched.dy), rate.predicted_vector(), (0, 0)]
+            .into_iter()
+            .filter_map(|candidate| {
+                calculate_candidate_error(&block, target_frame, candidate, self.metric.as_ref())
+                    .map(|distortion| (candidate, distortion))
+            })
+            .min_by(|(first, first_distortion), (second, second_distortion)| {
+                rate.cost(*first, *first_distortion)
+                    .partial_cmp(&rate.cost(*second, *second_distortion))
+                    .expect("Comparing NaN errors")
+            })
+            .unwrap();
+        let vector = MotionVector {
+            dx,
+            dy,
+            cost: distortion,
+            ..matched
+        };
+
+        // Blocks that may be split also pay for the split flag
+        let half_size = block_size / 2;
+        let can_split = half_size >= self.min_block_size;
+        let split_flag_bits = if can_split { SPLIT_FLAG_BITS } else { 0.0 };
+
+        let leaf_cost = rate.cost((dx, dy), distortion) + self.lambda * split_flag_bits;
+
+        let leaf = BlockMotion {
+            x_offset,
+            y_offset,
+            width: block_width,
+            height: block_height,
+            vector,
+        };
+
+        if !can_split {
+            vectors.tree.insert(&leaf);
+            return (PartitionNode::Leaf(leaf), leaf_cost);
+        }
+
+        // Sub-blocks starting outside the frame are dropped. They are partitioned in Z order, each
+        // predicted from the leaves its earlier siblings settled on.
+        let children: Vec<(PartitionNode, f64)> = [(0, 0), (1, 0), (0, 1), (1, 1)]
+            .iter()
+            .map(|(col, row)| (x_offset + col * half_size, y_offset + row * half_size))
+            .filter(|(x, y)| *x < anchor_frame.width() && *y < anchor_frame.height())
+            .map(|(x, y)| self.partition(anchor_frame, target_frame, vectors, x, y, half_size))
+            .collect();
+
+        let split_cost =
+            self.lambda * SPLIT_FLAG_BITS + children.iter().map(|(_, cost)| cost).sum::<f64>();
+
+        if split_cost < leaf_cost {
+            let children = children.into_iter().map(|(node, _)| node).collect();
+            (PartitionNode::Split(children), split_cost)
+        } else {
+            // The leaf covers every vector its sub-blocks left behind
+            vectors.tree.insert(&leaf);
+            (PartitionNode::Leaf(leaf), leaf_cost)
+        }
+    }
+}
+
+// Vectors of the decided leaves over an area, one per smallest partition
+struct LeafVectors {
+    x_offset: u32,
+    y_offset: u32,
+    width: u32,
+    height: u32,
+    cell_size: u32,
+    vectors: Vec<Option<MotionVector>>,
+}
+
+impl LeafVectors {
+    fn new(x_offset: u32, y_offset: u32, width: u32, height: u32, cell_size: u32) -> Self {
+        let cell_count = width.div_ceil(cell_size) * height.div_ceil(cell_size);
+
+        Self {
+            x_offset,
+            y_offset,
+            width,
+            height,
+            cell_size,
+            vectors: vec![None; cell_count as usize],
+        }
+    }
+
+    fn cell(&self, x: u32, y: u32) -> Option<usize> {
+        let x = x.checked_sub(self.x_offset).filter(|x| *x < self.width)?;
+        let y = y.checked_sub(self.y_offset).filter(|y| *y < self.height)?;
+        let cols = self.width.div_ceil(self.cell_size);
+
+        Some(((y / self.cell_size) * cols + x / self.cell_size) as usize)
+    }
+
+    fn get(&self, x: u32, y: u32) -> Option<MotionVector> {
+        self.cell(x, y).and_then(|cell| self.vectors[cell])
+    }
+
+    fn insert(&mut self, leaf: &BlockMotion) {
+        for y in (leaf.y_offset..leaf.y_offset + leaf.height).step_by(self.cell_size as usize) {
+            for x in (leaf.x_offset..leaf.x_offset + leaf.width).step_by(self.cell_size as usize) {
+                if let Some(cell) = self.cell(x, y) {
+                    self.vectors[cell] = Some(leaf.vector);
+                }
+            }
+        }
+    }
+}
+
+// The leaves of the trees partitioned on earlier wavefronts, and of the tree being partitioned
+struct CausalVectors<'a> {
+    frame: &'a LeafVectors,
+    tree: LeafVectors,
+}
+
+impl CausalVectors<'_> {
+    // Neighbours outside the frame, or not decided yet, are unavailable
+    fn context(&self, x_offset: u32, y_offset: u32, block_size: u32) -> SearchContext {
+        let vector = |x: u32, y: u32| self.tree.get(x, y).or_else(|| self.frame.get(x, y));
+
+        SearchContext {
+            left: x_offset
+                .checked_sub(1)
+                .and_then(|left| vector(left, y_offset)),
+            top: y_offset
+                .checked_sub(1)
+                .and_then(|top| vector(x_offset, top)),
+            top_right: y_offset
+                .checked_sub(1)
+                .and_then(|top| vector(x_offset + block_size, top)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::GrayImage;
+
+    use super::*;
+    use crate::{
+        bma::exhaustive::ExhaustiveBlockMatcher,
+        test_utils::{shifted_pair, texture},
+    };
+
+    fn estimator(lambda: f64) -> QuadtreeMotionEstimator {
+        QuadtreeMotionEstimator::new(Box::new(ExhaustiveBlockMatcher::new(4)), 32, 8)
+            .with_lambda(lambda)
+    }
+
+    #[test]
+    fn uniform_motion_keeps_the_largest_blocks() {
+        let (anchor_frame, target_frame) = shifted_pair(64, 64, (2, 1));
+
+        let motion_field = estimator(4.0).estimate(&anchor_frame, &target_frame);
+
+        assert_eq!((motion_field.cols, motion_field.rows), (2, 2));
+        // The top left block is the only one whose match stays inside the frame
+        let block = motion_field.roots[0].leaves()[0];
+        assert_eq!((block.width, block.vector.dx, block.vector.dy), (32, 2, 1));
+        assert_eq!(block.vector.cost, 0.0);
+    }
+
+    #[test]
+    fn blocks_straddling_two_motions_are_split() {
+        // The left quarter of the frame moves right and the rest moves left
+        let anchor_frame = DynamicImage::ImageLuma8(GrayImage::from_fn(64, 32, |x, y| {
+            image::Luma([texture(x as f64, y as f64)])
+        }));
+        let target_frame = DynamicImage::ImageLuma8(GrayImage::from_fn(64, 32, |x, y| {
+            let dx = if x < 16 { 2.0 } else { -2.0 };
+            image::Luma([texture(x as f64 - dx, y as f64)])
+        }));
+
+        let motion_field = estimator(4.0).estimate(&anchor_frame, &target_frame);
+
+        assert!(matches!(motion_field.roots[0], PartitionNode::Split(_)));
+        assert!(matches!(motion_field.roots[1], PartitionNode::Leaf(_)));
+        let block = motion_field.roots[1].leaves()[0];
+        assert_eq!((block.vector.dx, block.vector.dy), (-2, 0));
+    }
+
+    #[test]
+    fn large_lambdas_prefer_the_zero_vector() {
+        let (anchor_frame, target_frame) = shifted_pair(64, 64, (2, -1));
+
+        // The matcher itself ranks by distortion alone
+        let motion_field = estimator(1e6).estimate(&anchor_frame, &target_frame);
+
+        for block in motion_field.leaves() {
+            assert_eq!((block.width, block.vector.dx, block.vector.dy), (32, 0, 0));
+            assert!(block.vector.cost > 0.0);
+        }
+    }
+
+    #[test]
+    fn flat_blocks_follow_the_neighbouring_motion() {
+        // Only the left tree is textured, and it moves right by 2 pixels
+        let anchor_frame = DynamicImage::ImageLuma8(GrayImage::from_fn(96, 32, |x, y| {
+            let value = if x < 32 {
+                texture(x as f64, y as f64)
+            } else {
+                128
+            };
+            image::Luma([value])
+        }));
+        let target_frame = DynamicImage::ImageLuma8(GrayImage::from_fn(96, 32, |x, y| {
+            let value = if x < 34 {
+                texture(x as f64 - 2.0, y as f64)
+            } else {
+                128
+            };
+            image::Luma([value])
+        }));
+
+        let motion_field = estimator(4.0).estimate(&anchor_frame, &target_frame);
+
+        // Every vector matches the flat tree, the predicted one costs the fewest bits
+        let block = motion_field.roots[1].leaves()[0];
+        assert_eq!((block.width, block.vector.dx, block.vector.dy), (32, 2, 0));
+        assert_eq!(block.vector.cost, 0.0);
+    }
+
+    #[test]
+    fn contexts_hold_the_decided_neighbouring_leaves() {
+        let leaf = |x_offset, y_offset, size, dx| BlockMotion {
+            x_offset,
+            y_offset,
+            width: size,
+            height: size,
+            vector: MotionVector {
+                dx,
+                dy: 0,
+                cost: 0.0,
+                points_evaluated: 1,
+                points_pruned: 0,
+            },
+        };
+        let predictor = |vector: Option<MotionVector>| vector.map(|vector| vector.dx);
+
+        let mut decided = LeafVectors::new(0, 0, 64, 32, 8);
+        decided.insert(&leaf(0, 0, 32, 1));
+        let mut vectors = CausalVectors {
+            frame: &decided,
+            tree: LeafVectors::new(32, 0, 32, 32, 8),
+        };
+        vectors.tree.insert(&leaf(32, 0, 16, 2));
+        vectors.tree.insert(&leaf(48, 0, 16, 3));
+
+        let context = vectors.context(32, 16, 8);
+        assert_eq!(
+            [context.left, context.top, context.top_right].map(predictor),
+            [Some(1), Some(2), Some(2)]
+        );
+
+        // Leaves of the tree that are not partitioned yet, or outside the frame, are unavailable
+        let context = vectors.context(40, 16, 8);
+        assert_eq!(
+            [context.left, context.top, context.top_right].map(predictor),
+            [None, Some(2), Some(3)]
+        );
+        let context = vectors.context(48, 16, 16);
+        assert_eq!(
+            [context.left, context.top, context.top_right].map(predictor),
+            [None, Some(3), None]
+        );
+    }
+}
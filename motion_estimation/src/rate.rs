@@ -0,0 +1,113 @@
+use crate::types::SearchContext;
+
+// Length of the signed Exp-Golomb code used for motion vector differences
+pub fn exp_golomb_bits(value: i32) -> u32 {
+    // Code numbers of the extreme values need 33 bits
+    let code_number = if value > 0 {
+        2 * value as u64 - 1
+    } else {
+        2 * value.unsigned_abs() as u64
+    };
+
+    2 * (63 - (code_number + 1).leading_zeros()) + 1
+}
+
+pub fn vector_bits(vector: (i32, i32), predicted_vector: (i32, i32)) -> u32 {
+    exp_golomb_bits(vector.0 - predicted_vector.0) + exp_golomb_bits(vector.1 - predicted_vector.1)
+}
+
+// Candidates are ranked by distortion + lambda * bits, where the bits code the difference to
+// the median of the causal neighbours. A zero lambda ranks by distortion alone.
+#[derive(Copy, Clone, Debug)]
+pub struct RateConstraint {
+    lambda: f64,
+    predicted_vector: (i32, i32),
+}
+
+impl RateConstraint {
+    pub fn new(lambda: f64, context: &SearchContext) -> Self {
+        Self {
+            lambda,
+            predicted_vector: context.median_predictor().unwrap_or((0, 0)),
+        }
+    }
+
+    pub fn predicted_vector(&self) -> (i32, i32) {
+        self.predicted_vector
+    }
+
+    pub fn rate_cost(&self, vector: (i32, i32)) -> f64 {
+        if self.lambda == 0.0 {
+            return 0.0;
+        }
+
+        self.lambda * vector_bits(vector, self.predicted_vector) as f64
+    }
+
+    pub fn cost(&self, vector: (i32, i32), distortion: f64) -> f64 {
+        distortion + self.rate_cost(vector)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::MotionVector;
+
+    fn neighbour(dx: i32, dy: i32) -> Option<MotionVector> {
+        Some(MotionVector {
+            dx,
+            dy,
+            cost: 0.0,
+            points_evaluated: 0,
+            points_pruned: 0,
+        })
+    }
+
+    #[test]
+    fn exp_golomb_lengths_follow_the_signed_mapping() {
+        // 0, 1, -1, 2, -2, ... map to code numbers 0, 1, 2, 3, 4, ...
+        let lengths: Vec<u32> = [0, 1, -1, 2, -2, 3, -3, 4, -4, 7, -7, 8]
+            .iter()
+            .map(|value| exp_golomb_bits(*value))
+            .collect();
+
+        assert_eq!(lengths, [1, 3, 3, 5, 5, 5, 5, 7, 7, 7, 7, 9]);
+        assert_eq!(exp_golomb_bits(i32::MAX), 63);
+        assert_eq!(exp_golomb_bits(i32::MIN), 65);
+    }
+
+    #[test]
+    fn vector_bits_code_the_difference_to_the_prediction() {
+        assert_eq!(vector_bits((0, 0), (0, 0)), 2);
+        assert_eq!(vector_bits((3, -1), (3, -1)), 2);
+        assert_eq!(vector_bits((3, -1), (0, 0)), 5 + 3);
+        assert_eq!(vector_bits((0, 0), (3, -1)), 5 + 3);
+    }
+
+    #[test]
+    fn the_constraint_predicts_from_the_neighbour_median() {
+        let context = SearchContext {
+            left: neighbour(4, 0),
+            top: neighbour(2, -2),
+            top_right: neighbour(-1, 1),
+        };
+        let rate = RateConstraint::new(2.0, &context);
+
+        assert_eq!(rate.predicted_vector(), (2, 0));
+        assert_eq!(rate.rate_cost((2, 0)), 2.0 * 2.0);
+        assert_eq!(rate.cost((0, 0), 10.0), 10.0 + 2.0 * (5.0 + 1.0));
+        assert_eq!(
+            RateConstraint::new(2.0, &SearchContext::default()).predicted_vector(),
+            (0, 0)
+        );
+    }
+
+    #[test]
+    fn a_zero_lambda_ranks_by_distortion_alone() {
+        let rate = RateConstraint::new(0.0, &SearchContext::default());
+
+        assert_eq!(rate.rate_cost((20, -20)), 0.0);
+        assert_eq!(rate.cost((20, -20), 7.5), 7.5);
+    }
+}
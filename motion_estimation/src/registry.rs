@@ -0,0 +1,204 @@
+use crate::{
+    bma::{
+        arps::{AdaptiveRoodPatternBlockMatcher, PredictorMode},
+        diamond::DiamondSearchBlockMatcher,
+        exhaustive::ExhaustiveBlockMatcher,
+        fast_exhaustive::FastExhaustiveBlockMatcher,
+        hexagon::HexagonSearchBlockMatcher,
+        naive::NaiveBlockMatcher,
+        three_step::ThreeStepBlockMatcher,
+        BlockMatcher,
+    },
+    metrics::{
+        ncc::NccMetric,
+        sad::{MadMetric, SadMetric},
+        satd::SatdMetric,
+        ssd::{MseMetric, SsdMetric},
+        DistortionMetric,
+    },
+};
+
+// Matchers take the search range, metric, lambda and zero-motion threshold, ignoring the
+// parameters they have no use for
+type MatcherConstructor =
+    fn(u16, Box<dyn DistortionMetric>, f64, Option<f64>) -> Box<dyn BlockMatcher>;
+type MetricConstructor = fn() -> Box<dyn DistortionMetric>;
+
+const MATCHERS: &[(&str, MatcherConstructor)] = &[
+    ("naive", |_, metric, _, _| {
+        Box::new(NaiveBlockMatcher::new().with_metric(metric))
+    }),
+    ("exhaustive", |search_range, metric, lambda, _| {
+        Box::new(
+            ExhaustiveBlockMatcher::new(search_range)
+                .with_metric(metric)
+                .with_lambda(lambda),
+        )
+    }),
+    (
+        "fast_exhaustive",
+        |search_range, metric, lambda, threshold| {
+            let matcher = FastExhaustiveBlockMatcher::new(search_range)
+                .with_metric(metric)
+                .with_lambda(lambda);
+
+            Box::new(match threshold {
+                Some(threshold) => matcher.with_zero_motion_threshold(threshold),
+                None => matcher,
+            })
+        },
+    ),
+    ("three_step", |search_range, metric, lambda, _| {
+        Box::new(
+            ThreeStepBlockMatcher::new(search_range)
+                .with_metric(metric)
+                .with_lambda(lambda),
+        )
+    }),
+    ("diamond", |search_range, metric, lambda, _| {
+        Box::new(
+            DiamondSearchBlockMatcher::new(search_range)
+                .with_metric(metric)
+                .with_lambda(lambda),
+        )
+    }),
+    ("hexagon", |search_range, metric, lambda, _| {
+        Box::new(
+            HexagonSearchBlockMatcher::new(search_range)
+                .with_metric(metric)
+                .with_lambda(lambda),
+        )
+    }),
+    ("arps", |search_range, metric, lambda, _| {
+        Box::new(
+            AdaptiveRoodPatternBlockMatcher::new(search_range)
+                .with_predictor_mode(PredictorMode::Left)
+                .with_metric(metric)
+                .with_lambda(lambda),
+        )
+    }),
+    ("arps_median", |search_range, metric, lambda, _| {
+        Box::new(
+            AdaptiveRoodPatternBlockMatcher::new(search_range)
+                .with_predictor_mode(PredictorMode::Median)
+                .with_metric(metric)
+                .with_lambda(lambda),
+        )
+    }),
+];
+
+const METRICS: &[(&str, MetricConstructor)] = &[
+    ("sad", || Box::new(SadMetric::new())),
+    ("mad", || Box::new(MadMetric::new())),
+    ("ssd", || Box::new(SsdMetric::new())),
+    ("mse", || Box::new(MseMetric::new())),
+    ("satd4", || Box::new(SatdMetric::new(4))),
+    ("satd8", || Box::new(SatdMetric::new(8))),
+    ("ncc", || Box::new(NccMetric::new())),
+];
+
+pub fn matcher_names() -> Vec<&'static str> {
+    MATCHERS.iter().map(|(name, _)| *name).collect()
+}
+
+pub fn metric_names() -> Vec<&'static str> {
+    METRICS.iter().map(|(name, _)| *name).collect()
+}
+
+pub fn create_matcher(
+    name: &str,
+    search_range: u16,
+    metric: Box<dyn DistortionMetric>,
+    lambda: f64,
+    zero_motion_threshold: Option<f64>,
+) -> Option<Box<dyn BlockMatcher>> {
+    MATCHERS
+        .iter()
+        .find(|(matcher_name, _)| *matcher_name == name)
+        .map(|(_, constructor)| constructor(search_range, metric, lambda, zero_motion_threshold))
+}
+
+pub fn create_metric(name: &str) -> Option<Box<dyn DistortionMetric>> {
+    METRICS
+        .iter()
+        .find(|(metric_name, _)| *metric_name == name)
+        .map(|(_, constructor)| constructor())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_utils::match_shifted_block, SearchContext};
+
+    #[test]
+    fn every_registered_name_builds() {
+        for name in metric_names() {
+            assert!(create_metric(name).is_some(), "{}", name);
+        }
+        for name in matcher_names() {
+            let metric = create_metric("sad").unwrap();
+            assert!(
+                create_matcher(name, 8, metric, 0.0, None).is_some(),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_names_are_rejected() {
+        assert!(create_metric("psnr").is_none());
+        assert!(create_matcher("gradient", 8, create_metric("sad").unwrap(), 0.0, None).is_none());
+    }
+
+    #[test]
+    fn matchers_are_built_with_the_search_range_and_lambda() {
+        let build =
+            |lambda| create_matcher("exhaustive", 4, create_metric("sad").unwrap(), lambda, None);
+
+        let vector = match_shifted_block(
+            build(0.0).unwrap().as_ref(),
+            (2, 1),
+            &SearchContext::default(),
+        );
+        assert_eq!(
+            (vector.dx, vector.dy, vector.points_evaluated),
+            (2, 1, 8 * 8)
+        );
+
+        // Vector bits outweigh any distortion
+        let vector = match_shifted_block(
+            build(1e6).unwrap().as_ref(),
+            (2, 1),
+            &SearchContext::default(),
+        );
+        assert_eq!((vector.dx, vector.dy), (0, 0));
+    }
+
+    #[test]
+    fn the_zero_motion_threshold_reaches_fast_exhaustive() {
+        let build = |threshold| {
+            create_matcher(
+                "fast_exhaustive",
+                4,
+                create_metric("mad").unwrap(),
+                0.0,
+                threshold,
+            )
+        };
+
+        let vector = match_shifted_block(
+            build(None).unwrap().as_ref(),
+            (1, 0),
+            &SearchContext::default(),
+        );
+        assert_eq!((vector.dx, vector.dy), (1, 0));
+
+        let vector = match_shifted_block(
+            build(Some(1000.0)).unwrap().as_ref(),
+            (1, 0),
+            &SearchContext::default(),
+        );
+        assert_eq!((vector.dx, vector.dy, vector.points_evaluated), (0, 0, 1));
+    }
+}
@@ -0,0 +1,141 @@
+use std::{
+    cmp::Ordering,
+    fs, io,
+    path::{Path, PathBuf},
+    vec,
+};
+
+use image::{DynamicImage, ImageFormat};
+use log::info;
+
+use super::Frame;
+
+pub struct ImageSequenceSource {
+    paths: vec::IntoIter<(String, PathBuf)>,
+}
+
+impl ImageSequenceSource {
+    pub fn open(folder: &Path) -> io::Result<Self> {
+        let mut paths = Vec::new();
+
+        for entry in fs::read_dir(folder)? {
+            let path = entry?.path();
+            let is_image = path
+                .extension()
+                .and_then(|extension| extension.to_str())
+                .is_some_and(|extension| ImageFormat::from_extension(extension).is_some());
+
+            if path.is_file() && is_image {
+                let file_name = path.file_name().unwrap().to_string_lossy().into_owned();
+                paths.push((file_name, path));
+            }
+        }
+
+        paths.sort_by(|(first, _), (second, _)| natural_cmp(first, second));
+
+        Ok(Self {
+            paths: paths.into_iter(),
+        })
+    }
+}
+
+impl Iterator for ImageSequenceSource {
+    type Item = io::Result<Frame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (file_name, path) = self.paths.next()?;
+        info!("Loading frame {:?}...", path);
+
+        Some(
+            image::open(&path)
+                .map(|img| (file_name, DynamicImage::ImageLuma8(img.to_luma8())))
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error)),
+        )
+    }
+}
+
+// Orders names so that embedded numbers compare by value, e.g. frame2 before frame10
+pub fn natural_cmp(first: &str, second: &str) -> Ordering {
+    let (mut first, mut second) = (first, second);
+
+    loop {
+        match (first.chars().next(), second.chars().next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(first_char), Some(second_char))
+                if first_char.is_ascii_digit() && second_char.is_ascii_digit() =>
+            {
+                let (first_number, first_rest) = split_digits(first);
+                let (second_number, second_rest) = split_digits(second);
+
+                let first_value = first_number.trim_start_matches('0');
+                let second_value = second_number.trim_start_matches('0');
+
+                let ordering = first_value
+                    .len()
+                    .cmp(&second_value.len())
+                    .then_with(|| first_value.cmp(second_value))
+                    .then_with(|| first_number.len().cmp(&second_number.len()));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+
+                first = first_rest;
+                second = second_rest;
+            }
+            (Some(first_char), Some(second_char)) => {
+                if first_char != second_char {
+                    return first_char.cmp(&second_char);
+                }
+
+                first = &first[first_char.len_utf8()..];
+                second = &second[second_char.len_utf8()..];
+            }
+        }
+    }
+}
+
+fn split_digits(name: &str) -> (&str, &str) {
+    let digits_end = name
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(name.len());
+
+    name.split_at(digits_end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn embedded_numbers_compare_by_value() {
+        let mut names = vec![
+            "frame10.png",
+            "frame2.png",
+            "frame1.png",
+            "clip.png",
+            "frame02.png",
+        ];
+        names.sort_by(|first, second| natural_cmp(first, second));
+
+        assert_eq!(
+            names,
+            vec![
+                "clip.png",
+                "frame1.png",
+                "frame2.png",
+                "frame02.png",
+                "frame10.png"
+            ]
+        );
+    }
+
+    #[test]
+    fn equal_numbers_fall_back_to_the_rest_of_the_name() {
+        assert_eq!(natural_cmp("a1b", "a1c"), Ordering::Less);
+        assert_eq!(natural_cmp("a1", "a1"), Ordering::Equal);
+        assert_eq!(natural_cmp("a1", "a1b"), Ordering::Less);
+        assert_eq!(natural_cmp("é2", "é10"), Ordering::Less);
+    }
+}
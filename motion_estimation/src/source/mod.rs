@@ -0,0 +1,125 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, Read},
+    path::Path,
+    str::FromStr,
+};
+
+use image::{DynamicImage, GrayImage};
+
+pub mod image_sequence;
+pub mod raw_yuv;
+pub mod y4m;
+
+use image_sequence::ImageSequenceSource;
+use raw_yuv::{RawYuvFormat, RawYuvSource};
+use y4m::Y4mSource;
+
+pub type Frame = (String, DynamicImage);
+
+pub trait FrameSource: Iterator<Item = io::Result<Frame>> {}
+
+impl<T: Iterator<Item = io::Result<Frame>>> FrameSource for T {}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ChromaSubsampling {
+    Yuv420,
+    Yuv444,
+    Monochrome,
+}
+
+impl ChromaSubsampling {
+    // Number of samples in both chroma planes together
+    pub fn chroma_samples(&self, width: u32, height: u32) -> usize {
+        let (width, height) = (width as usize, height as usize);
+        match self {
+            ChromaSubsampling::Yuv420 => 2 * width.div_ceil(2) * height.div_ceil(2),
+            ChromaSubsampling::Yuv444 => 2 * width * height,
+            ChromaSubsampling::Monochrome => 0,
+        }
+    }
+}
+
+impl FromStr for ChromaSubsampling {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "420" => Ok(ChromaSubsampling::Yuv420),
+            "444" => Ok(ChromaSubsampling::Yuv444),
+            "mono" => Ok(ChromaSubsampling::Monochrome),
+            _ => Err(format!(
+                "unknown chroma subsampling '{}', expected one of: 420, 444, mono",
+                name
+            )),
+        }
+    }
+}
+
+// Directories are read as image sequences, files as Y4M unless a raw geometry is given
+pub fn open_frame_source(
+    path: &Path,
+    raw_format: Option<RawYuvFormat>,
+) -> io::Result<Box<dyn FrameSource>> {
+    if path.is_dir() {
+        return Ok(Box::new(ImageSequenceSource::open(path)?));
+    }
+
+    let reader = BufReader::new(File::open(path)?);
+    match raw_format {
+        Some(raw_format) => Ok(Box::new(RawYuvSource::new(reader, raw_format)?)),
+        None => Ok(Box::new(Y4mSource::new(reader)?)),
+    }
+}
+
+pub(crate) fn frame_name(frame_index: usize) -> String {
+    format!("{:04}", frame_index + 1)
+}
+
+pub(crate) fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+pub(crate) fn bytes_per_sample(bit_depth: u8) -> usize {
+    if bit_depth > 8 {
+        2
+    } else {
+        1
+    }
+}
+
+// Fills the buffer, returning false if the stream ended before its first byte
+pub(crate) fn read_frame_bytes<R: Read>(reader: &mut R, buffer: &mut [u8]) -> io::Result<bool> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => return Err(invalid_data("Truncated frame at end of stream")),
+            Ok(read) => filled += read,
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+            Err(error) => return Err(error),
+        }
+    }
+
+    Ok(true)
+}
+
+// High bit depth samples are little endian and are rounded down to 8 bits
+pub(crate) fn luma_plane(frame_bytes: &[u8], width: u32, height: u32, bit_depth: u8) -> GrayImage {
+    let luma_samples = (width * height) as usize;
+
+    let luma = if bit_depth > 8 {
+        let shift = bit_depth - 8;
+        frame_bytes[..2 * luma_samples]
+            .chunks_exact(2)
+            .map(|sample| {
+                let sample = u16::from_le_bytes([sample[0], sample[1]]) as u32;
+                ((sample + (1 << (shift - 1))) >> shift).min(255) as u8
+            })
+            .collect()
+    } else {
+        frame_bytes[..luma_samples].to_vec()
+    };
+
+    GrayImage::from_raw(width, height, luma).unwrap()
+}
@@ -0,0 +1,149 @@
+use std::io::{self, Read};
+
+use image::DynamicImage;
+
+use super::{
+    bytes_per_sample, frame_name, invalid_data, luma_plane, read_frame_bytes, ChromaSubsampling,
+    Frame,
+};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RawYuvFormat {
+    pub width: u32,
+    pub height: u32,
+    pub subsampling: ChromaSubsampling,
+    pub bit_depth: u8,
+}
+
+impl RawYuvFormat {
+    pub fn frame_size(&self) -> usize {
+        let samples = (self.width * self.height) as usize
+            + self.subsampling.chroma_samples(self.width, self.height);
+
+        samples * bytes_per_sample(self.bit_depth)
+    }
+}
+
+pub struct RawYuvSource<R: Read> {
+    reader: R,
+    format: RawYuvFormat,
+    frame_index: usize,
+}
+
+impl<R: Read> RawYuvSource<R> {
+    pub fn new(reader: R, format: RawYuvFormat) -> io::Result<Self> {
+        if !(8..=16).contains(&format.bit_depth) {
+            return Err(invalid_data(&format!(
+                "Unsupported raw YUV bit depth {}",
+                format.bit_depth
+            )));
+        }
+
+        Ok(Self {
+            reader,
+            format,
+            frame_index: 0,
+        })
+    }
+}
+
+impl<R: Read> Iterator for RawYuvSource<R> {
+    type Item = io::Result<Frame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut frame_bytes = vec![0; self.format.frame_size()];
+        match read_frame_bytes(&mut self.reader, &mut frame_bytes) {
+            Ok(true) => {}
+            Ok(false) => return None,
+            Err(error) => return Some(Err(error)),
+        }
+
+        let RawYuvFormat {
+            width,
+            height,
+            bit_depth,
+            ..
+        } = self.format;
+        let luma = luma_plane(&frame_bytes, width, height, bit_depth);
+
+        let frame_name = frame_name(self.frame_index);
+        self.frame_index += 1;
+
+        Some(Ok((frame_name, DynamicImage::ImageLuma8(luma))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(subsampling: ChromaSubsampling, bit_depth: u8) -> RawYuvFormat {
+        RawYuvFormat {
+            width: 3,
+            height: 3,
+            subsampling,
+            bit_depth,
+        }
+    }
+
+    #[test]
+    fn frame_sizes_round_chroma_planes_up() {
+        assert_eq!(format(ChromaSubsampling::Yuv420, 8).frame_size(), 9 + 2 * 4);
+        assert_eq!(format(ChromaSubsampling::Yuv444, 8).frame_size(), 3 * 9);
+        assert_eq!(
+            format(ChromaSubsampling::Monochrome, 10).frame_size(),
+            2 * 9
+        );
+    }
+
+    #[test]
+    fn frames_are_read_back_to_back() {
+        let stream: Vec<u8> = (0..2 * 17).collect();
+        let frames: Vec<Frame> =
+            RawYuvSource::new(stream.as_slice(), format(ChromaSubsampling::Yuv420, 8))
+                .unwrap()
+                .collect::<io::Result<_>>()
+                .unwrap();
+
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[1].0, "0002");
+        assert_eq!(
+            frames[1].1.to_luma8().into_raw(),
+            (17..26).collect::<Vec<u8>>()
+        );
+    }
+
+    #[test]
+    fn high_bit_depth_samples_are_rounded_to_8_bits() {
+        let stream: Vec<u8> = [4092u16, 4095, 8, 7, 0, 0, 2048, 2049, 2056]
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect();
+        let mut source =
+            RawYuvSource::new(stream.as_slice(), format(ChromaSubsampling::Monochrome, 12))
+                .unwrap();
+
+        let (_, frame) = source.next().unwrap().unwrap();
+        assert_eq!(
+            frame.to_luma8().into_raw(),
+            vec![255, 255, 1, 0, 0, 0, 128, 128, 129]
+        );
+        assert!(source.next().is_none());
+    }
+
+    #[test]
+    fn truncated_frames_are_errors() {
+        let stream = vec![0; 17 + 5];
+        let mut source =
+            RawYuvSource::new(stream.as_slice(), format(ChromaSubsampling::Yuv420, 8)).unwrap();
+
+        assert!(source.next().unwrap().is_ok());
+        assert!(source.next().unwrap().is_err());
+    }
+
+    #[test]
+    fn unsupported_bit_depths_are_rejected() {
+        assert!(RawYuvSource::new(&[][..], format(ChromaSubsampling::Yuv420, 4)).is_err());
+        assert!(RawYuvSource::new(&[][..], format(ChromaSubsampling::Yuv420, 17)).is_err());
+    }
+}
@@ -0,0 +1,258 @@
+use std::io::{self, BufRead};
+
+use image::DynamicImage;
+
+use super::{
+    bytes_per_sample, frame_name, invalid_data, luma_plane, read_frame_bytes, ChromaSubsampling,
+    Frame,
+};
+
+const STREAM_MAGIC: &str = "YUV4MPEG2";
+const FRAME_MAGIC: &str = "FRAME";
+
+pub struct Y4mSource<R: BufRead> {
+    reader: R,
+    width: u32,
+    height: u32,
+    subsampling: ChromaSubsampling,
+    bit_depth: u8,
+    frame_index: usize,
+}
+
+impl<R: BufRead> Y4mSource<R> {
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let header =
+            read_line(&mut reader)?.ok_or_else(|| invalid_data("Missing Y4M stream header"))?;
+
+        let mut tokens = header.split_ascii_whitespace();
+        if tokens.next() != Some(STREAM_MAGIC) {
+            return Err(invalid_data("Not a YUV4MPEG2 stream"));
+        }
+
+        let (mut width, mut height) = (None, None);
+        let mut colorspace = "420jpeg";
+
+        for token in tokens {
+            let mut chars = token.chars();
+            let tag = chars.next();
+            let value = chars.as_str();
+            match tag {
+                Some('W') => width = Some(parse_number(value)?),
+                Some('H') => height = Some(parse_number(value)?),
+                Some('C') => colorspace = value,
+                _ => {}
+            }
+        }
+
+        let width = width.ok_or_else(|| invalid_data("Missing Y4M width"))?;
+        let height = height.ok_or_else(|| invalid_data("Missing Y4M height"))?;
+        let (subsampling, bit_depth) = parse_colorspace(colorspace)?;
+
+        Ok(Self {
+            reader,
+            width,
+            height,
+            subsampling,
+            bit_depth,
+            frame_index: 0,
+        })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    fn frame_size(&self) -> usize {
+        let samples = (self.width * self.height) as usize
+            + self.subsampling.chroma_samples(self.width, self.height);
+
+        samples * bytes_per_sample(self.bit_depth)
+    }
+}
+
+impl<R: BufRead> Iterator for Y4mSource<R> {
+    type Item = io::Result<Frame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let frame_header = match read_line(&mut self.reader) {
+            Ok(Some(frame_header)) => frame_header,
+            Ok(None) => return None,
+            Err(error) => return Some(Err(error)),
+        };
+
+        if !frame_header.starts_with(FRAME_MAGIC) {
+            return Some(Err(invalid_data("Malformed Y4M frame header")));
+        }
+
+        let mut frame_bytes = vec![0; self.frame_size()];
+        match read_frame_bytes(&mut self.reader, &mut frame_bytes) {
+            Ok(true) => {}
+            Ok(false) => return Some(Err(invalid_data("Missing Y4M frame data"))),
+            Err(error) => return Some(Err(error)),
+        }
+
+        let luma = luma_plane(&frame_bytes, self.width, self.height, self.bit_depth);
+
+        let frame_name = frame_name(self.frame_index);
+        self.frame_index += 1;
+
+        Some(Ok((frame_name, DynamicImage::ImageLuma8(luma))))
+    }
+}
+
+fn parse_colorspace(colorspace: &str) -> io::Result<(ChromaSubsampling, u8)> {
+    let (subsampling, bit_depth) = match colorspace {
+        "420jpeg" | "420paldv" | "420mpeg2" => ("420", 8),
+        _ => match colorspace.split_once('p') {
+            Some((subsampling, bit_depth)) => (subsampling, parse_number(bit_depth)?),
+            None => (colorspace, 8),
+        },
+    };
+
+    match (subsampling.parse(), bit_depth) {
+        (Ok(subsampling), 8..=16) => Ok((subsampling, bit_depth as u8)),
+        _ => Err(invalid_data(&format!(
+            "Unsupported Y4M colorspace {}",
+            colorspace
+        ))),
+    }
+}
+
+fn read_line<R: BufRead>(reader: &mut R) -> io::Result<Option<String>> {
+    let mut line = Vec::new();
+    if reader.read_until(b'\n', &mut line)? == 0 {
+        return Ok(None);
+    }
+
+    String::from_utf8(line)
+        .map(|line| Some(line.trim_end().to_owned()))
+        .map_err(|_| invalid_data("Y4M header is not valid UTF-8"))
+}
+
+fn parse_number(value: &str) -> io::Result<u32> {
+    value
+        .parse()
+        .map_err(|_| invalid_data(&format!("Invalid Y4M header value {}", value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stream(header: &str, frames: &[Vec<u8>]) -> Vec<u8> {
+        let mut stream = format!("{}\n", header).into_bytes();
+        for frame in frames {
+            stream.extend_from_slice(b"FRAME\n");
+            stream.extend_from_slice(frame);
+        }
+        stream
+    }
+
+    // A 4x2 frame with luma 0..8 followed by two 2x1 chroma planes
+    fn frame_420() -> Vec<u8> {
+        (0..8).chain([128, 128, 128, 128]).collect()
+    }
+
+    fn read_luma(header: &str, frames: &[Vec<u8>]) -> io::Result<Vec<Vec<u8>>> {
+        Y4mSource::new(stream(header, frames).as_slice())?
+            .map(|frame| frame.map(|(_, frame)| frame.to_luma8().into_raw()))
+            .collect()
+    }
+
+    #[test]
+    fn named_420_colorspaces_are_8_bit() {
+        for header in [
+            "YUV4MPEG2 W4 H2 F25:1 C420jpeg",
+            "YUV4MPEG2 W4 H2 C420paldv",
+            "YUV4MPEG2 W4 H2 C420mpeg2 Ip",
+            "YUV4MPEG2 W4 H2 C420",
+            // The colorspace defaults to 420jpeg
+            "YUV4MPEG2 W4 H2 F30000:1001 A1:1",
+        ] {
+            let frames = read_luma(header, &[frame_420(), frame_420()]).unwrap();
+            assert_eq!(frames, vec![(0..8).collect::<Vec<u8>>(); 2], "{}", header);
+        }
+    }
+
+    #[test]
+    fn frames_are_named_by_their_position() {
+        let names: Vec<String> =
+            Y4mSource::new(stream("YUV4MPEG2 W4 H2", &[frame_420(), frame_420()]).as_slice())
+                .unwrap()
+                .map(|frame| frame.unwrap().0)
+                .collect();
+
+        assert_eq!(names, vec!["0001", "0002"]);
+    }
+
+    #[test]
+    fn high_bit_depth_samples_are_rounded_to_8_bits() {
+        // 10-bit little endian samples, 4x2 luma and 2x1 chroma planes
+        let luma: [u16; 8] = [0, 1, 2, 3, 1020, 1023, 512, 514];
+        let frame = luma
+            .iter()
+            .chain(&[512; 4])
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect();
+
+        let frames = read_luma("YUV4MPEG2 W4 H2 C420p10", &[frame]).unwrap();
+        assert_eq!(frames, vec![vec![0, 0, 1, 1, 255, 255, 128, 129]]);
+    }
+
+    #[test]
+    fn mono_and_444_frames_skip_their_chroma() {
+        let frames = read_luma("YUV4MPEG2 W2 H1 Cmono", &[vec![1, 2], vec![3, 4]]).unwrap();
+        assert_eq!(frames, vec![vec![1, 2], vec![3, 4]]);
+
+        let frames = read_luma("YUV4MPEG2 W2 H1 C444", &[vec![1, 2, 0, 0, 0, 0]]).unwrap();
+        assert_eq!(frames, vec![vec![1, 2]]);
+    }
+
+    #[test]
+    fn multibyte_tags_are_ignored() {
+        let frames = read_luma("YUV4MPEG2 W4 H2 Xé ÿ", &[frame_420()]).unwrap();
+        assert_eq!(frames.len(), 1);
+    }
+
+    #[test]
+    fn invalid_headers_are_rejected() {
+        for header in [
+            "YUV4MPEG W4 H2",
+            "YUV4MPEG2 H2",
+            "YUV4MPEG2 W4 H2 C422",
+            "YUV4MPEG2 W4 H2 C420p7",
+            "YUV4MPEG2 W4 H2 C420p264",
+            "YUV4MPEG2 Wfour H2",
+        ] {
+            let error = Y4mSource::new(stream(header, &[]).as_slice()).err();
+            assert_eq!(
+                error.map(|error| error.kind()),
+                Some(io::ErrorKind::InvalidData),
+                "{}",
+                header
+            );
+        }
+    }
+
+    #[test]
+    fn truncated_and_malformed_frames_are_errors() {
+        let mut truncated = stream("YUV4MPEG2 W4 H2", &[frame_420()]);
+        truncated.truncate(truncated.len() - 1);
+        let mut source = Y4mSource::new(truncated.as_slice()).unwrap();
+        assert!(source.next().unwrap().is_err());
+
+        let mut missing = stream("YUV4MPEG2 W4 H2", &[]);
+        missing.extend_from_slice(b"FRAME\n");
+        let mut source = Y4mSource::new(missing.as_slice()).unwrap();
+        assert!(source.next().unwrap().is_err());
+
+        let mut malformed = stream("YUV4MPEG2 W4 H2", &[]);
+        malformed.extend_from_slice(b"FRAMS\n");
+        let mut source = Y4mSource::new(malformed.as_slice()).unwrap();
+        assert!(source.next().unwrap().is_err());
+    }
+}
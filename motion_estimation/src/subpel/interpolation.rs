@@ -0,0 +1,267 @@
+use std::str::FromStr;
+
+use image::{GrayImage, Luma};
+
+const H264_TAPS: [i32; 6] = [1, -5, 20, 20, -5, 1];
+
+const HEVC_TAPS: [[i32; 8]; 4] = [
+    [0, 0, 0, 64, 0, 0, 0, 0],
+    [-1, 4, -10, 58, 17, -5, 1, 0],
+    [-1, 4, -11, 40, 40, -11, 4, -1],
+    [0, 1, -5, 17, 58, -10, 4, -1],
+];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum InterpolationFilter {
+    Bilinear,
+    H264SixTap,
+    HevcEightTap,
+}
+
+impl InterpolationFilter {
+    // Samples the frame at a position expressed in quarter-pel units
+    pub fn sample(&self, frame: &GrayImage, x_quarter: i32, y_quarter: i32) -> u8 {
+        let (x, y) = (x_quarter.div_euclid(4), y_quarter.div_euclid(4));
+        let (x_fraction, y_fraction) = (x_quarter.rem_euclid(4), y_quarter.rem_euclid(4));
+
+        if x_fraction == 0 && y_fraction == 0 {
+            return pixel(frame, x, y) as u8;
+        }
+
+        match self {
+            InterpolationFilter::Bilinear => bilinear_sample(frame, x, y, x_fraction, y_fraction),
+            InterpolationFilter::H264SixTap => h264_sample(frame, x, y, x_fraction, y_fraction),
+            InterpolationFilter::HevcEightTap => hevc_sample(frame, x, y, x_fraction, y_fraction),
+        }
+    }
+}
+
+impl FromStr for InterpolationFilter {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "bilinear" => Ok(InterpolationFilter::Bilinear),
+            "h264" => Ok(InterpolationFilter::H264SixTap),
+            "hevc" => Ok(InterpolationFilter::HevcEightTap),
+            _ => Err(format!(
+                "unknown interpolation filter '{}', expected one of: bilinear, h264, hevc",
+                name
+            )),
+        }
+    }
+}
+
+pub fn interpolate_block(
+    frame: &GrayImage,
+    filter: InterpolationFilter,
+    x_quarter: i32,
+    y_quarter: i32,
+    width: u32,
+    height: u32,
+) -> GrayImage {
+    GrayImage::from_fn(width, height, |x, y| {
+        Luma([filter.sample(frame, x_quarter + 4 * x as i32, y_quarter + 4 * y as i32)])
+    })
+}
+
+// Out of frame coordinates replicate the border pixels
+fn pixel(frame: &GrayImage, x: i32, y: i32) -> i32 {
+    let x = x.clamp(0, frame.width() as i32 - 1) as u32;
+    let y = y.clamp(0, frame.height() as i32 - 1) as u32;
+    frame.get_pixel(x, y).0[0] as i32
+}
+
+fn clip(value: i32) -> u8 {
+    value.clamp(0, 255) as u8
+}
+
+fn bilinear_sample(frame: &GrayImage, x: i32, y: i32, x_fraction: i32, y_fraction: i32) -> u8 {
+    let top = pixel(frame, x, y) * (4 - x_fraction) + pixel(frame, x + 1, y) * x_fraction;
+    let bottom =
+        pixel(frame, x, y + 1) * (4 - x_fraction) + pixel(frame, x + 1, y + 1) * x_fraction;
+
+    clip((top * (4 - y_fraction) + bottom * y_fraction + 8) >> 4)
+}
+
+// Unrounded 6-tap sums centred between (x, y) and the next pixel along each axis
+fn h264_horizontal_sum(frame: &GrayImage, x: i32, y: i32) -> i32 {
+    H264_TAPS
+        .iter()
+        .enumerate()
+        .map(|(i, tap)| tap * pixel(frame, x + i as i32 - 2, y))
+        .sum()
+}
+
+fn h264_vertical_sum(frame: &GrayImage, x: i32, y: i32) -> i32 {
+    H264_TAPS
+        .iter()
+        .enumerate()
+        .map(|(i, tap)| tap * pixel(frame, x, y + i as i32 - 2))
+        .sum()
+}
+
+fn h264_sample(frame: &GrayImage, x: i32, y: i32, x_fraction: i32, y_fraction: i32) -> u8 {
+    let average = |a: i32, b: i32| (a + b + 1) >> 1;
+
+    let horizontal_half = |y: i32| clip((h264_horizontal_sum(frame, x, y) + 16) >> 5) as i32;
+    let vertical_half = |x: i32| clip((h264_vertical_sum(frame, x, y) + 16) >> 5) as i32;
+    let centre_half = || {
+        let sum: i32 = H264_TAPS
+            .iter()
+            .enumerate()
+            .map(|(i, tap)| tap * h264_horizontal_sum(frame, x, y + i as i32 - 2))
+            .sum();
+        clip((sum + 512) >> 10) as i32
+    };
+
+    // Sample naming follows the H.264 specification (8.4.2.2.1)
+    let value = match (x_fraction, y_fraction) {
+        (2, 0) => horizontal_half(y),
+        (0, 2) => vertical_half(x),
+        (2, 2) => centre_half(),
+        (1, 0) => average(pixel(frame, x, y), horizontal_half(y)),
+        (3, 0) => average(horizontal_half(y), pixel(frame, x + 1, y)),
+        (0, 1) => average(pixel(frame, x, y), vertical_half(x)),
+        (0, 3) => average(vertical_half(x), pixel(frame, x, y + 1)),
+        (2, 1) => average(horizontal_half(y), centre_half()),
+        (2, 3) => average(centre_half(), horizontal_half(y + 1)),
+        (1, 2) => average(vertical_half(x), centre_half()),
+        (3, 2) => average(centre_half(), vertical_half(x + 1)),
+        (x_fraction, y_fraction) => {
+            let horizontal = horizontal_half(if y_fraction == 1 { y } else { y + 1 });
+            let vertical = vertical_half(if x_fraction == 1 { x } else { x + 1 });
+            average(horizontal, vertical)
+        }
+    };
+
+    value as u8
+}
+
+fn hevc_sample(frame: &GrayImage, x: i32, y: i32, x_fraction: i32, y_fraction: i32) -> u8 {
+    let horizontal_taps = HEVC_TAPS[x_fraction as usize];
+    let vertical_taps = HEVC_TAPS[y_fraction as usize];
+
+    let horizontal_sum = |y: i32| -> i32 {
+        horizontal_taps
+            .iter()
+            .enumerate()
+            .map(|(i, tap)| tap * pixel(frame, x + i as i32 - 3, y))
+            .sum()
+    };
+
+    if y_fraction == 0 {
+        return clip((horizontal_sum(y) + 32) >> 6);
+    }
+
+    if x_fraction == 0 {
+        let sum: i32 = vertical_taps
+            .iter()
+            .enumerate()
+            .map(|(i, tap)| tap * pixel(frame, x, y + i as i32 - 3))
+            .sum();
+        return clip((sum + 32) >> 6);
+    }
+
+    // For 8-bit input the intermediate horizontal samples are kept unshifted
+    let sum: i32 = vertical_taps
+        .iter()
+        .enumerate()
+        .map(|(i, tap)| tap * horizontal_sum(y + i as i32 - 3))
+        .sum();
+
+    clip((sum + 2048) >> 12)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILTERS: [InterpolationFilter; 3] = [
+        InterpolationFilter::Bilinear,
+        InterpolationFilter::H264SixTap,
+        InterpolationFilter::HevcEightTap,
+    ];
+
+    // A horizontal ramp, constant along each column
+    fn ramp() -> GrayImage {
+        GrayImage::from_fn(16, 16, |x, _| Luma([(x * 10) as u8]))
+    }
+
+    #[test]
+    fn whole_pixel_positions_return_the_pixels() {
+        let frame = ramp();
+
+        for filter in FILTERS {
+            assert_eq!(filter.sample(&frame, 4 * 5, 4 * 3), 50);
+        }
+    }
+
+    #[test]
+    fn constant_frames_stay_constant() {
+        let frame = GrayImage::from_pixel(16, 16, Luma([77]));
+
+        for filter in FILTERS {
+            for (x_quarter, y_quarter) in [(21, 20), (22, 22), (23, 25), (-3, 61)] {
+                assert_eq!(filter.sample(&frame, x_quarter, y_quarter), 77);
+            }
+        }
+    }
+
+    #[test]
+    fn fractional_positions_follow_a_ramp() {
+        let frame = ramp();
+
+        // Away from the borders every filter reproduces a linear ramp
+        for filter in FILTERS {
+            assert_eq!(filter.sample(&frame, 4 * 6 + 2, 4 * 6), 65);
+            assert_eq!(filter.sample(&frame, 4 * 6 + 2, 4 * 6 + 2), 65);
+            assert_eq!(filter.sample(&frame, 4 * 6, 4 * 6 + 1), 60);
+        }
+        assert_eq!(
+            InterpolationFilter::Bilinear.sample(&frame, 4 * 6 + 1, 0),
+            63
+        );
+        assert_eq!(
+            InterpolationFilter::H264SixTap.sample(&frame, 4 * 6 + 1, 0),
+            63
+        );
+        // The asymmetric quarter-pel taps undershoot a ramp slightly
+        assert_eq!(
+            InterpolationFilter::HevcEightTap.sample(&frame, 4 * 6 + 1, 0),
+            62
+        );
+    }
+
+    #[test]
+    fn h264_half_pel_uses_the_six_tap_filter() {
+        let frame = GrayImage::from_fn(8, 1, |x, _| Luma([if x == 3 { 64 } else { 0 }]));
+
+        // Taps 1, -5, 20, 20, -5, 1 over x = 1..=6, centred between 3 and 4
+        assert_eq!(
+            InterpolationFilter::H264SixTap.sample(&frame, 4 * 3 + 2, 0),
+            40
+        );
+        // Negative lobes are clipped to zero
+        assert_eq!(
+            InterpolationFilter::H264SixTap.sample(&frame, 4 * 4 + 2, 0),
+            0
+        );
+    }
+
+    #[test]
+    fn interpolated_blocks_step_whole_pixels() {
+        let block = interpolate_block(&ramp(), InterpolationFilter::Bilinear, 4 * 2 + 2, 4, 3, 2);
+
+        assert_eq!(block.into_raw(), vec![25, 35, 45, 25, 35, 45]);
+    }
+
+    #[test]
+    fn filters_are_parsed_by_name() {
+        assert_eq!(
+            "hevc".parse::<InterpolationFilter>(),
+            Ok(InterpolationFilter::HevcEightTap)
+        );
+        assert!("lanczos".parse::<InterpolationFilter>().is_err());
+    }
+}
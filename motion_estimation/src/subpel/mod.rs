@@ -0,0 +1,295 @@
+use std::str::FromStr;
+
+use image::{DynamicImage, GenericImageView, GrayImage};
+use log::debug;
+
+use crate::{
+    metrics::{sad::MadMetric, DistortionMetric},
+    plane::{luma_frame, LumaPlane},
+    types::MotionVector,
+    ExtractedBlock,
+};
+
+pub mod interpolation;
+
+use interpolation::{interpolate_block, InterpolationFilter};
+
+const REFINEMENT_OFFSETS: [(i32, i32); 8] = [
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+    (-1, 0),
+    (1, 0),
+    (-1, 1),
+    (0, 1),
+    (1, 1),
+];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SubpixelPrecision {
+    Half,
+    Quarter,
+}
+
+impl SubpixelPrecision {
+    pub fn factor(&self) -> i32 {
+        match self {
+            SubpixelPrecision::Half => 2,
+            SubpixelPrecision::Quarter => 4,
+        }
+    }
+}
+
+impl FromStr for SubpixelPrecision {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "half" => Ok(SubpixelPrecision::Half),
+            "quarter" => Ok(SubpixelPrecision::Quarter),
+            _ => Err(format!(
+                "unknown subpixel precision '{}', expected one of: half, quarter",
+                name
+            )),
+        }
+    }
+}
+
+// Vector components are expressed in units of 1 / precision.factor() pixels
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FractionalMotionVector {
+    pub dx: i32,
+    pub dy: i32,
+    pub precision: SubpixelPrecision,
+    pub cost: f64,
+    pub points_evaluated: u32,
+}
+
+impl FractionalMotionVector {
+    pub fn from_integer(vector: &MotionVector, precision: SubpixelPrecision) -> Self {
+        Self {
+            dx: vector.dx * precision.factor(),
+            dy: vector.dy * precision.factor(),
+            precision,
+            cost: vector.cost,
+            points_evaluated: vector.points_evaluated,
+        }
+    }
+
+    pub fn to_quarter_pel(self) -> (i32, i32) {
+        let scale = 4 / self.precision.factor();
+        (self.dx * scale, self.dy * scale)
+    }
+
+    pub fn as_pixels(&self) -> (f64, f64) {
+        let factor = self.precision.factor() as f64;
+        (self.dx as f64 / factor, self.dy as f64 / factor)
+    }
+}
+
+pub struct SubpixelRefiner {
+    precision: SubpixelPrecision,
+    filter: InterpolationFilter,
+    metric: Box<dyn DistortionMetric>,
+}
+
+impl SubpixelRefiner {
+    pub fn new(precision: SubpixelPrecision, filter: InterpolationFilter) -> Self {
+        Self {
+            precision,
+            filter,
+            metric: Box::new(MadMetric::new()),
+        }
+    }
+
+    pub fn with_metric(self, metric: Box<dyn DistortionMetric>) -> Self {
+        Self { metric, ..self }
+    }
+
+    pub fn refine(
+        &self,
+        block: &ExtractedBlock,
+        frame: &DynamicImage,
+        vector: &MotionVector,
+    ) -> FractionalMotionVector {
+        let frame = luma_frame(frame);
+        let mut best = FractionalMotionVector::from_integer(vector, self.precision);
+
+        // Half-pel steps first, then quarter-pel ones around the best half-pel position
+        let mut step = self.precision.factor() / 2;
+        while step >= 1 {
+            let centre = best;
+
+            for (x_offset, y_offset) in REFINEMENT_OFFSETS {
+                let candidate = FractionalMotionVector {
+                    dx: centre.dx + x_offset * step,
+                    dy: centre.dy + y_offset * step,
+                    ..centre
+                };
+
+                if let Some(error) = self.calculate_candidate_error(block, &frame, &candidate) {
+                    best.points_evaluated += 1;
+                    if error < best.cost {
+                        best = FractionalMotionVector {
+                            cost: error,
+                            points_evaluated: best.points_evaluated,
+                            ..candidate
+                        };
+                    }
+                }
+            }
+
+            step /= 2;
+        }
+
+        debug!(
+            "Refined prediction: ({}, {}) -> {:?}",
+            vector.dx,
+            vector.dy,
+            best.as_pixels()
+        );
+
+        best
+    }
+
+    pub fn compensate(
+        &self,
+        block: &ExtractedBlock,
+        frame: &DynamicImage,
+        vector: &FractionalMotionVector,
+    ) -> GrayImage {
+        let (x_quarter, y_quarter) = candidate_position(block, vector);
+        let (width, height) = block.pixels.dimensions();
+
+        interpolate_block(
+            &luma_frame(frame),
+            self.filter,
+            x_quarter,
+            y_quarter,
+            width,
+            height,
+        )
+    }
+
+    fn calculate_candidate_error(
+        &self,
+        block: &ExtractedBlock,
+        frame: &GrayImage,
+        vector: &FractionalMotionVector,
+    ) -> Option<f64> {
+        let (x_quarter, y_quarter) = candidate_position(block, vector);
+        let (width, height) = block.pixels.dimensions();
+
+        // The candidate must lie between whole pixels of the frame
+        if x_quarter < 0
+            || y_quarter < 0
+            || x_quarter + 4 * width as i32 > 4 * frame.width() as i32
+            || y_quarter + 4 * height as i32 > 4 * frame.height() as i32
+        {
+            return None;
+        }
+
+        let candidate = interpolate_block(frame, self.filter, x_quarter, y_quarter, width, height);
+        let block_pixels = luma_frame(&block.pixels);
+
+        Some(self.metric.calculate_plane_error(
+            &LumaPlane::from_image(&block_pixels),
+            &LumaPlane::from_image(&candidate),
+        ))
+    }
+}
+
+fn candidate_position(block: &ExtractedBlock, vector: &FractionalMotionVector) -> (i32, i32) {
+    let (dx, dy) = vector.to_quarter_pel();
+    (
+        4 * block.x_offset as i32 + dx,
+        4 * block.y_offset as i32 + dy,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_utils::textured_frame, utils::tile_frame};
+
+    // Without a known integer cost the first valid candidate replaces the starting vector
+    fn integer_vector(dx: i32, dy: i32) -> MotionVector {
+        MotionVector {
+            dx,
+            dy,
+            cost: f64::MAX,
+            points_evaluated: 1,
+            points_pruned: 0,
+        }
+    }
+
+    #[test]
+    fn refines_to_a_half_pel_shift() {
+        let anchor_frame = textured_frame(64, 64, (0.0, 0.0));
+        let target_frame = textured_frame(64, 64, (2.5, -1.5));
+        let block = tile_frame(&anchor_frame, 16).swap_remove(5);
+        let refiner = SubpixelRefiner::new(SubpixelPrecision::Half, InterpolationFilter::Bilinear);
+
+        let vector = refiner.refine(&block, &target_frame, &integer_vector(2, -1));
+
+        assert_eq!((vector.dx, vector.dy), (5, -3));
+        assert_eq!(vector.as_pixels(), (2.5, -1.5));
+        assert_eq!(vector.points_evaluated, 1 + 8);
+    }
+
+    #[test]
+    fn quarter_pel_refines_around_the_best_half_pel_position() {
+        let anchor_frame = textured_frame(64, 64, (0.0, 0.0));
+        let target_frame = textured_frame(64, 64, (1.25, 0.75));
+        let block = tile_frame(&anchor_frame, 16).swap_remove(5);
+        let refiner = SubpixelRefiner::new(
+            SubpixelPrecision::Quarter,
+            InterpolationFilter::HevcEightTap,
+        );
+
+        let vector = refiner.refine(&block, &target_frame, &integer_vector(1, 1));
+
+        assert_eq!((vector.dx, vector.dy), (5, 3));
+        assert_eq!(vector.to_quarter_pel(), (5, 3));
+        assert_eq!(vector.points_evaluated, 1 + 8 + 8);
+    }
+
+    #[test]
+    fn compensation_samples_the_refined_position() {
+        let frame = textured_frame(64, 64, (0.0, 0.0));
+        let block = tile_frame(&frame, 16).swap_remove(5);
+        let refiner =
+            SubpixelRefiner::new(SubpixelPrecision::Half, InterpolationFilter::H264SixTap);
+        let vector =
+            FractionalMotionVector::from_integer(&integer_vector(3, -2), refiner.precision);
+
+        let predicted_block = refiner.compensate(&block, &frame, &vector);
+
+        assert_eq!(
+            predicted_block,
+            *frame.crop_imm(19, 14, 16, 16).as_luma8().unwrap()
+        );
+    }
+
+    #[test]
+    fn candidates_outside_the_frame_are_skipped() {
+        let frame = textured_frame(32, 32, (0.0, 0.0));
+        let block = tile_frame(&frame, 16).swap_remove(0);
+        let refiner = SubpixelRefiner::new(SubpixelPrecision::Half, InterpolationFilter::Bilinear);
+
+        let vector = refiner.refine(
+            &block,
+            &frame,
+            &MotionVector {
+                cost: 0.0,
+                ..integer_vector(0, 0)
+            },
+        );
+
+        // Only the three half-pel neighbours towards the frame are evaluated
+        assert_eq!(
+            (vector.dx, vector.dy, vector.points_evaluated),
+            (0, 0, 1 + 3)
+        );
+    }
+}
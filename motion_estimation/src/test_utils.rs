@@ -0,0 +1,107 @@
+use std::{fs, io, path::PathBuf};
+
+use image::{DynamicImage, GrayImage};
+
+use crate::{
+    flow::FlowField,
+    pairing::{FramePair, FramePairing},
+    plane::LumaPlane,
+    utils::tile_frame,
+    BlockMatcher, MotionVector, SearchContext,
+};
+
+// Smooth value noise with blobs of about a block, so every block has a single best match
+// and the costs decrease towards it, as gradient descent searches expect
+pub fn texture(x: f64, y: f64) -> u8 {
+    let value = 128.0
+        + 160.0 * (value_noise(x / 20.0, y / 20.0) - 0.5)
+        + 80.0 * (value_noise(x / 9.0 + 31.0, y / 9.0 + 17.0) - 0.5);
+
+    value.round().clamp(0.0, 255.0) as u8
+}
+
+fn value_noise(x: f64, y: f64) -> f64 {
+    let lattice = |x: i64, y: i64| {
+        let hash = (x.wrapping_mul(73_856_093) ^ y.wrapping_mul(19_349_663)) as u64;
+        let hash = hash.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        (hash >> 40) as f64 / (1u64 << 24) as f64
+    };
+    let smooth = |t: f64| t * t * (3.0 - 2.0 * t);
+
+    let (x0, y0) = (x.floor(), y.floor());
+    let (tx, ty) = (smooth(x - x0), smooth(y - y0));
+    let (x0, y0) = (x0 as i64, y0 as i64);
+
+    let top = lattice(x0, y0) * (1.0 - tx) + lattice(x0 + 1, y0) * tx;
+    let bottom = lattice(x0, y0 + 1) * (1.0 - tx) + lattice(x0 + 1, y0 + 1) * tx;
+    top * (1.0 - ty) + bottom * ty
+}
+
+// The texture moved by (dx, dy), so that frame(x, y) = textured_frame(.., (0, 0))(x - dx, y - dy)
+// and anchor blocks are found at +(dx, dy) in the shifted frame
+pub fn textured_frame(width: u32, height: u32, (dx, dy): (f64, f64)) -> DynamicImage {
+    DynamicImage::ImageLuma8(GrayImage::from_fn(width, height, |x, y| {
+        image::Luma([texture(x as f64 - dx, y as f64 - dy)])
+    }))
+}
+
+// An anchor frame and a target frame holding the same content displaced by the vector
+pub fn shifted_pair(width: u32, height: u32, vector: (i32, i32)) -> (DynamicImage, DynamicImage) {
+    (
+        textured_frame(width, height, (0.0, 0.0)),
+        textured_frame(width, height, (vector.0 as f64, vector.1 as f64)),
+    )
+}
+
+// Consecutive pairs of a 48x32 clip whose content moves by (2, -1) pixels per frame
+pub fn shifted_pairs(count: usize) -> impl Iterator<Item = io::Result<FramePair>> {
+    let frames = (0..count).map(|index| {
+        let shift = (2.0 * index as f64, -(index as f64));
+        Ok((
+            format!("{:04}.png", index + 1),
+            textured_frame(48, 32, shift),
+        ))
+    });
+
+    FramePairing::Consecutive.pair_frames(frames)
+}
+
+pub fn block(width: u32, height: u32, pixels: Vec<u8>) -> DynamicImage {
+    DynamicImage::ImageLuma8(GrayImage::from_raw(width, height, pixels).unwrap())
+}
+
+// Matches the block at (16, 16) of a 64x64 anchor frame into a frame shifted by the vector
+pub fn match_shifted_block(
+    matcher: &dyn BlockMatcher,
+    vector: (i32, i32),
+    context: &SearchContext,
+) -> MotionVector {
+    let (anchor_frame, target_frame) = shifted_pair(64, 64, vector);
+    let block = tile_frame(&anchor_frame, 16).swap_remove(5);
+    let target_frame = target_frame.to_luma8();
+
+    matcher.match_block(&block, &LumaPlane::from_image(&target_frame), context)
+}
+
+// Mean flow over the pixels away from the borders
+pub fn interior_mean_flow(flow: &FlowField, margin: u32) -> (f32, f32) {
+    let vectors: Vec<(f32, f32)> = (margin..flow.height - margin)
+        .flat_map(|y| (margin..flow.width - margin).map(move |x| (x, y)))
+        .map(|(x, y)| flow.get(x, y))
+        .collect();
+    let count = vectors.len() as f32;
+
+    vectors.iter().fold((0.0, 0.0), |(u, v), vector| {
+        (u + vector.0 / count, v + vector.1 / count)
+    })
+}
+
+// An empty folder under the system temporary directory, unique to the test and process
+pub fn temp_folder(name: &str) -> PathBuf {
+    let folder =
+        std::env::temp_dir().join(format!("motion_estimation_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&folder);
+    fs::create_dir_all(&folder).unwrap();
+
+    folder
+}
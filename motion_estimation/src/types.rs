@@ -1,4 +1,5 @@
 use image::DynamicImage;
+use serde::{Deserialize, Serialize};
 
 pub struct ExtractedBlock {
     pub x_offset: u32,
@@ -6,9 +7,56 @@ pub struct ExtractedBlock {
     pub pixels: DynamicImage,
 }
 
-pub struct PredictionResult<'a> {
-    pub anchor_frame_index: usize,
-    pub target_frame_index: usize,
-    pub anchor_block: &'a ExtractedBlock,
-    pub target_block: ExtractedBlock,
-}
\ No newline at end of file
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MotionVector {
+    pub dx: i32,
+    pub dy: i32,
+    pub cost: f64,
+    pub points_evaluated: u32,
+    // Candidates rejected by fast searches before their distortion was complete
+    #[serde(default)]
+    pub points_pruned: u32,
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SearchContext {
+    pub left: Option<MotionVector>,
+    pub top: Option<MotionVector>,
+    pub top_right: Option<MotionVector>,
+}
+
+impl SearchContext {
+    pub fn left_predictor(&self) -> Option<(i32, i32)> {
+        self.left.map(|vector| (vector.dx, vector.dy))
+    }
+
+    pub fn median_predictor(&self) -> Option<(i32, i32)> {
+        let neighbours = [self.left, self.top, self.top_right];
+
+        // Unavailable neighbours count as zero vectors, unless only the left one exists
+        match neighbours {
+            [None, None, None] => None,
+            [Some(left), None, None] => Some((left.dx, left.dy)),
+            _ => {
+                let vectors = neighbours.map(|vector| vector.map_or((0, 0), |v| (v.dx, v.dy)));
+                Some((
+                    median_of_three(vectors[0].0, vectors[1].0, vectors[2].0),
+                    median_of_three(vectors[0].1, vectors[1].1, vectors[2].1),
+                ))
+            }
+        }
+    }
+}
+
+fn median_of_three(a: i32, b: i32, c: i32) -> i32 {
+    a.max(b).min(a.min(b).max(c))
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BlockMotion {
+    pub x_offset: u32,
+    pub y_offset: u32,
+    pub width: u32,
+    pub height: u32,
+    pub vector: MotionVector,
+}
@@ -1,37 +1,74 @@
 use std::fs;
 
-use image::{DynamicImage, GrayImage, ImageError};
-use log::debug;
+use image::{DynamicImage, GenericImageView, GrayImage, ImageError};
+use itertools::Itertools;
 
-use crate::types::ExtractedBlock;
+use crate::{
+    metrics::DistortionMetric,
+    plane::{luma_frame, LumaPlane},
+    types::{ExtractedBlock, MotionVector},
+};
 
-pub fn extract_blocks(
-    parent_folder: &str,
-    images: &Vec<(String, DynamicImage)>,
-    x_offset: u32,
-    y_offset: u32,
-    mb_size: u32,
-) -> Vec<ExtractedBlock> {
-    debug!(
-        "Extracting '{}' blocks with offset ({}, {})...",
-        parent_folder, x_offset, y_offset
-    );
-
-    images
-        .iter()
-        .map(|(file_name, img)| {
-            let pixels = crop_block_from_image(&img, x_offset, y_offset, mb_size).unwrap();
-            export_block(parent_folder, &pixels, &file_name).unwrap();
+pub fn tile_frame(frame: &DynamicImage, mb_size: u32) -> Vec<ExtractedBlock> {
+    let (frame_width, frame_height) = frame.dimensions();
+
+    // Blocks on the right and bottom borders are cropped to the frame
+    (0..frame_height)
+        .step_by(mb_size as usize)
+        .cartesian_product((0..frame_width).step_by(mb_size as usize))
+        .map(|(y_offset, x_offset)| {
+            let block_width = u32::min(mb_size, frame_width - x_offset);
+            let block_height = u32::min(mb_size, frame_height - y_offset);
 
             ExtractedBlock {
                 x_offset,
                 y_offset,
-                pixels: DynamicImage::ImageLuma8(pixels),
+                pixels: frame.crop_imm(x_offset, y_offset, block_width, block_height),
             }
         })
         .collect()
 }
 
+pub fn crop_target_block(
+    block: &ExtractedBlock,
+    vector: &MotionVector,
+    frame: &DynamicImage,
+) -> ExtractedBlock {
+    let x_offset = (block.x_offset as i32 + vector.dx) as u32;
+    let y_offset = (block.y_offset as i32 + vector.dy) as u32;
+
+    ExtractedBlock {
+        x_offset,
+        y_offset,
+        pixels: frame.crop_imm(
+            x_offset,
+            y_offset,
+            block.pixels.width(),
+            block.pixels.height(),
+        ),
+    }
+}
+
+pub fn calculate_candidate_error(
+    block: &ExtractedBlock,
+    frame: &LumaPlane,
+    vector: (i32, i32),
+    metric: &dyn DistortionMetric,
+) -> Option<f64> {
+    let (width, height) = block.pixels.dimensions();
+
+    // Candidates are borrowed from the frame, only blocks with another colour type are copied
+    let candidate = frame.view(
+        block.x_offset as i32 + vector.0,
+        block.y_offset as i32 + vector.1,
+        width,
+        height,
+    )?;
+    let block_pixels = luma_frame(&block.pixels);
+
+    Some(metric.calculate_plane_error(&LumaPlane::from_image(&block_pixels), &candidate))
+}
+
 pub fn crop_block_from_image(
     img: &image::DynamicImage,
     x_offset: u32,
@@ -44,28 +81,79 @@ pub fn crop_block_from_image(
 }
 
 pub fn export_block(
-    parent_folder: &str,
+    output_folder: &str,
     block_img: &image::ImageBuffer<image::Luma<u8>, Vec<u8>>,
     file_name: &str,
 ) -> Result<(), ImageError> {
-    let output_folder = format!("output/{}", parent_folder);
-    fs::create_dir_all(&output_folder)?;
+    fs::create_dir_all(output_folder)?;
     block_img.save(format!("{}/{}", output_folder, file_name))?;
     Ok(())
 }
 
-pub fn calculate_block_prediction_error(anchor_block: &DynamicImage, target_block: &DynamicImage) -> f64 {
-    let anchor_pixels = anchor_block.to_luma8().into_raw();
-    let target_pixels = target_block.to_luma8().into_raw();
-    let error_weight: f64 = 1.0 / anchor_pixels.len() as f64;
-    let error: f64 = anchor_pixels
-        .into_iter()
-        .zip(target_pixels.into_iter())
-        .map(|(anchor_pixel, target_pixel)| (anchor_pixel as i16, target_pixel as i16))
-        .map(|(anchor_pixel, target_pixel)| {
-            let pixel_error = (anchor_pixel - target_pixel).abs() as f64;
-            pixel_error * error_weight
-        })
-        .sum();
-    error
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{metrics::sad::SadMetric, test_utils::shifted_pair};
+
+    #[test]
+    fn tiles_cover_the_frame_in_raster_order() {
+        let frame = DynamicImage::new_luma8(40, 20);
+        let blocks = tile_frame(&frame, 16);
+
+        let layout: Vec<(u32, u32, u32, u32)> = blocks
+            .iter()
+            .map(|block| {
+                let (width, height) = block.pixels.dimensions();
+                (block.x_offset, block.y_offset, width, height)
+            })
+            .collect();
+        assert_eq!(
+            layout,
+            [
+                (0, 0, 16, 16),
+                (16, 0, 16, 16),
+                (32, 0, 8, 16),
+                (0, 16, 16, 4),
+                (16, 16, 16, 4),
+                (32, 16, 8, 4)
+            ]
+        );
+    }
+
+    #[test]
+    fn candidates_are_measured_at_the_vector() {
+        let (anchor_frame, target_frame) = shifted_pair(48, 48, (2, 1));
+        let block = &tile_frame(&anchor_frame, 16)[4];
+        let target_frame = target_frame.to_luma8();
+        let target_plane = LumaPlane::from_image(&target_frame);
+
+        let metric = SadMetric::new();
+        assert_eq!(
+            calculate_candidate_error(block, &target_plane, (2, 1), &metric),
+            Some(0.0)
+        );
+        assert!(calculate_candidate_error(block, &target_plane, (0, 0), &metric).unwrap() > 0.0);
+        assert_eq!(
+            calculate_candidate_error(block, &target_plane, (17, 0), &metric),
+            None
+        );
+    }
+
+    #[test]
+    fn target_blocks_are_cropped_at_the_vector() {
+        let (anchor_frame, target_frame) = shifted_pair(48, 48, (-3, 2));
+        let block = &tile_frame(&anchor_frame, 16)[4];
+        let vector = MotionVector {
+            dx: -3,
+            dy: 2,
+            cost: 0.0,
+            points_evaluated: 1,
+            points_pruned: 0,
+        };
+
+        let target_block = crop_target_block(block, &vector, &target_frame);
+
+        assert_eq!((target_block.x_offset, target_block.y_offset), (13, 18));
+        assert_eq!(target_block.pixels.to_luma8(), block.pixels.to_luma8());
+    }
 }
@@ -0,0 +1,540 @@
+use std::{
+    f64::consts::PI,
+    fs::{self, File},
+    io::{BufWriter, Write},
+};
+
+use image::{
+    codecs::gif::{GifEncoder, Repeat},
+    Delay, DynamicImage, Frame, ImageResult, Rgb, RgbImage,
+};
+
+use crate::{
+    flow::FlowField, pairing::FramePair, subpel::FractionalMotionVector, types::BlockMotion,
+};
+
+const GRID_COLOUR: Rgb<u8> = Rgb([96, 96, 96]);
+const COST_TINT_OPACITY: f64 = 0.35;
+const ARROW_HEAD_ANGLE: f64 = PI / 7.0;
+const GIF_ENCODING_SPEED: i32 = 10;
+
+// Hue segment lengths of the Middlebury colour wheel
+const WHEEL_SEGMENTS: [usize; 6] = [15, 6, 4, 11, 13, 6];
+
+pub struct VectorOverlay {
+    grid: bool,
+    cost_colouring: bool,
+    arrow_colour: Rgb<u8>,
+}
+
+impl VectorOverlay {
+    pub fn new() -> Self {
+        Self {
+            grid: true,
+            cost_colouring: true,
+            arrow_colour: Rgb([255, 255, 0]),
+        }
+    }
+
+    pub fn with_grid(self, grid: bool) -> Self {
+        Self { grid, ..self }
+    }
+
+    pub fn with_cost_colouring(self, cost_colouring: bool) -> Self {
+        Self {
+            cost_colouring,
+            ..self
+        }
+    }
+
+    pub fn with_arrow_colour(self, arrow_colour: Rgb<u8>) -> Self {
+        Self {
+            arrow_colour,
+            ..self
+        }
+    }
+
+    // Arrows start at each block centre and point to where the block was found in the target frame
+    pub fn render<'a>(
+        &self,
+        anchor_frame: &DynamicImage,
+        blocks: impl IntoIterator<Item = &'a BlockMotion>,
+    ) -> RgbImage {
+        let block_vectors = blocks.into_iter().map(|block| {
+            let vector = (block.vector.dx as f64, block.vector.dy as f64);
+            (block, vector)
+        });
+
+        self.render_vectors(anchor_frame, block_vectors.collect())
+    }
+
+    // Arrows point to the subpixel positions refined for the blocks
+    pub fn render_refined(
+        &self,
+        anchor_frame: &DynamicImage,
+        blocks: &[BlockMotion],
+        refined_vectors: &[FractionalMotionVector],
+    ) -> RgbImage {
+        let block_vectors = blocks
+            .iter()
+            .zip(refined_vectors)
+            .map(|(block, vector)| (block, vector.as_pixels()));
+
+        self.render_vectors(anchor_frame, block_vectors.collect())
+    }
+
+    fn render_vectors(
+        &self,
+        anchor_frame: &DynamicImage,
+        block_vectors: Vec<(&BlockMotion, (f64, f64))>,
+    ) -> RgbImage {
+        let blocks: Vec<&BlockMotion> = block_vectors.iter().map(|(block, _)| *block).collect();
+        let mut overlay = anchor_frame.to_rgb8();
+
+        if self.cost_colouring {
+            let max_cost = blocks
+                .iter()
+                .map(|block| block.vector.cost)
+                .fold(0.0, f64::max);
+
+            for block in &blocks {
+                let relative_cost = if max_cost > 0.0 {
+                    block.vector.cost / max_cost
+                } else {
+                    0.0
+                };
+                tint_block(&mut overlay, block, cost_colour(relative_cost));
+            }
+        }
+
+        if self.grid {
+            for block in &blocks {
+                draw_block_outline(&mut overlay, block);
+            }
+        }
+
+        for (block, (dx, dy)) in &block_vectors {
+            let centre_x = block.x_offset as f64 + block.width as f64 / 2.0;
+            let centre_y = block.y_offset as f64 + block.height as f64 / 2.0;
+
+            draw_arrow(
+                &mut overlay,
+                (centre_x, centre_y),
+                (centre_x + dx, centre_y + dy),
+                self.arrow_colour,
+            );
+        }
+
+        overlay
+    }
+}
+
+impl Default for VectorOverlay {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Colour codes each vector with the Middlebury wheel, saturation growing with the
+// magnitude relative to the given maximum, or the largest one in the field
+pub fn render_flow(flow: &FlowField, max_magnitude: Option<f32>) -> RgbImage {
+    let max_magnitude = max_magnitude.unwrap_or_else(|| flow.max_magnitude());
+    let wheel = colour_wheel();
+
+    RgbImage::from_fn(flow.width, flow.height, |x, y| {
+        let (u, v) = flow.get(x, y);
+        if !u.is_finite() || !v.is_finite() || max_magnitude <= 0.0 {
+            return Rgb([255, 255, 255]);
+        }
+
+        flow_colour(
+            &wheel,
+            (u / max_magnitude) as f64,
+            (v / max_magnitude) as f64,
+        )
+    })
+}
+
+pub struct GifAnimation<W: Write> {
+    encoder: GifEncoder<W>,
+    frame_delay: Delay,
+}
+
+impl<W: Write> GifAnimation<W> {
+    pub fn new(writer: W, frame_delay_ms: u32) -> ImageResult<Self> {
+        let mut encoder = GifEncoder::new_with_speed(writer, GIF_ENCODING_SPEED);
+        encoder.set_repeat(Repeat::Infinite)?;
+
+        Ok(Self {
+            encoder,
+            frame_delay: Delay::from_numer_denom_ms(frame_delay_ms, 1),
+        })
+    }
+
+    pub fn add_frame(&mut self, frame: &RgbImage) -> ImageResult<()> {
+        let frame = DynamicImage::ImageRgb8(frame.clone()).to_rgba8();
+        self.encoder
+            .encode_frame(Frame::from_parts(frame, 0, 0, self.frame_delay))
+    }
+}
+
+type FileAnimation = GifAnimation<BufWriter<File>>;
+
+// Vector overlays and flow colourings of every pair, saved as images and optionally
+// animated. The animations are finished when the visualisation is dropped.
+pub struct Visualisation {
+    output_folder: String,
+    max_magnitude: f32,
+    overlay: VectorOverlay,
+    animations: Option<(FileAnimation, FileAnimation)>,
+}
+
+impl Visualisation {
+    pub fn new(
+        output_folder: &str,
+        overlay: VectorOverlay,
+        max_magnitude: f32,
+        frame_delay_ms: Option<u32>,
+    ) -> ImageResult<Self> {
+        fs::create_dir_all(output_folder)?;
+
+        let animations = match frame_delay_ms {
+            Some(frame_delay_ms) => {
+                let create_animation = |name: &str| -> ImageResult<FileAnimation> {
+                    let file = File::create(format!("{}/{}.gif", output_folder, name))?;
+                    GifAnimation::new(BufWriter::new(file), frame_delay_ms)
+                };
+                Some((create_animation("vectors")?, create_animation("flow")?))
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            output_folder: output_folder.to_owned(),
+            max_magnitude,
+            overlay,
+            animations,
+        })
+    }
+
+    pub fn export<'a>(
+        &mut self,
+        pair: &FramePair,
+        blocks: impl IntoIterator<Item = &'a BlockMotion>,
+    ) -> ImageResult<()> {
+        let blocks: Vec<&BlockMotion> = blocks.into_iter().collect();
+        let vectors = self.overlay.render(&pair.anchor.1, blocks.iter().copied());
+
+        self.write(pair, &vectors, &FlowField::from_blocks(blocks))
+    }
+
+    pub fn export_refined(
+        &mut self,
+        pair: &FramePair,
+        blocks: &[BlockMotion],
+        refined_vectors: &[FractionalMotionVector],
+    ) -> ImageResult<()> {
+        let vectors = self
+            .overlay
+            .render_refined(&pair.anchor.1, blocks, refined_vectors);
+
+        self.write(
+            pair,
+            &vectors,
+            &FlowField::from_refined_blocks(blocks, refined_vectors),
+        )
+    }
+
+    // Dense fields are drawn as one arrow per grid cell
+    pub fn export_flow(
+        &mut self,
+        pair: &FramePair,
+        flow: &FlowField,
+        grid_size: u32,
+    ) -> ImageResult<()> {
+        let vectors = self
+            .overlay
+            .render(&pair.anchor.1, &flow.to_blocks(grid_size));
+
+        self.write(pair, &vectors, flow)
+    }
+
+    fn write(&mut self, pair: &FramePair, vectors: &RgbImage, flow: &FlowField) -> ImageResult<()> {
+        // A fixed maximum magnitude keeps the flow colour scale consistent across frames
+        let flow = render_flow(flow, Some(self.max_magnitude));
+
+        let file_prefix = format!("{}/{}", self.output_folder, pair.file_stem());
+        vectors.save(format!("{}_vectors.png", file_prefix))?;
+        flow.save(format!("{}_flow.png", file_prefix))?;
+
+        if let Some((vector_animation, flow_animation)) = self.animations.as_mut() {
+            vector_animation.add_frame(vectors)?;
+            flow_animation.add_frame(&flow)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn cost_colour(relative_cost: f64) -> Rgb<u8> {
+    // Green for the best matches through yellow to red for the worst ones
+    let red = (2.0 * relative_cost).min(1.0);
+    let green = (2.0 * (1.0 - relative_cost)).min(1.0);
+
+    Rgb([(red * 255.0) as u8, (green * 255.0) as u8, 0])
+}
+
+fn tint_block(image: &mut RgbImage, block: &BlockMotion, tint: Rgb<u8>) {
+    for y in block.y_offset..block.y_offset + block.height {
+        for x in block.x_offset..block.x_offset + block.width {
+            let pixel = image.get_pixel_mut(x, y);
+            for channel in 0..3 {
+                pixel[channel] = f64::round(
+                    pixel[channel] as f64 * (1.0 - COST_TINT_OPACITY)
+                        + tint[channel] as f64 * COST_TINT_OPACITY,
+                ) as u8;
+            }
+        }
+    }
+}
+
+fn draw_block_outline(image: &mut RgbImage, block: &BlockMotion) {
+    for x in block.x_offset..block.x_offset + block.width {
+        image.put_pixel(x, block.y_offset, GRID_COLOUR);
+    }
+
+    for y in block.y_offset..block.y_offset + block.height {
+        image.put_pixel(block.x_offset, y, GRID_COLOUR);
+    }
+}
+
+fn draw_arrow(image: &mut RgbImage, start: (f64, f64), end: (f64, f64), colour: Rgb<u8>) {
+    let (dx, dy) = (end.0 - start.0, end.1 - start.1);
+    let length = f64::hypot(dx, dy);
+
+    if length == 0.0 {
+        draw_line(image, start, start, colour);
+        return;
+    }
+
+    draw_line(image, start, end, colour);
+
+    let head_length = (0.3 * length).clamp(2.0, 5.0);
+    let angle = f64::atan2(dy, dx);
+
+    for head_angle in [angle + PI - ARROW_HEAD_ANGLE, angle + PI + ARROW_HEAD_ANGLE] {
+        let head_end = (
+            end.0 + head_length * head_angle.cos(),
+            end.1 + head_length * head_angle.sin(),
+        );
+        draw_line(image, end, head_end, colour);
+    }
+}
+
+fn draw_line(image: &mut RgbImage, start: (f64, f64), end: (f64, f64), colour: Rgb<u8>) {
+    let steps = f64::max((end.0 - start.0).abs(), (end.1 - start.1).abs()).ceil() as u32;
+
+    for step in 0..=steps {
+        let t = if steps == 0 {
+            0.0
+        } else {
+            step as f64 / steps as f64
+        };
+        let x = (start.0 + t * (end.0 - start.0)).floor();
+        let y = (start.1 + t * (end.1 - start.1)).floor();
+
+        if x >= 0.0 && y >= 0.0 && (x as u32) < image.width() && (y as u32) < image.height() {
+            image.put_pixel(x as u32, y as u32, colour);
+        }
+    }
+}
+
+fn colour_wheel() -> Vec<[f64; 3]> {
+    let [red_yellow, yellow_green, green_cyan, cyan_blue, blue_magenta, magenta_red] =
+        WHEEL_SEGMENTS;
+    let ramp = |index: usize, length: usize| 255.0 * index as f64 / length as f64;
+
+    let mut wheel = Vec::with_capacity(WHEEL_SEGMENTS.iter().sum());
+    wheel.extend((0..red_yellow).map(|i| [255.0, ramp(i, red_yellow), 0.0]));
+    wheel.extend((0..yellow_green).map(|i| [255.0 - ramp(i, yellow_green), 255.0, 0.0]));
+    wheel.extend((0..green_cyan).map(|i| [0.0, 255.0, ramp(i, green_cyan)]));
+    wheel.extend((0..cyan_blue).map(|i| [0.0, 255.0 - ramp(i, cyan_blue), 255.0]));
+    wheel.extend((0..blue_magenta).map(|i| [ramp(i, blue_magenta), 0.0, 255.0]));
+    wheel.extend((0..magenta_red).map(|i| [255.0, 0.0, 255.0 - ramp(i, magenta_red)]));
+
+    wheel
+}
+
+fn flow_colour(wheel: &[[f64; 3]], u: f64, v: f64) -> Rgb<u8> {
+    let radius = f64::hypot(u, v);
+    let angle = f64::atan2(-v, -u) / PI;
+
+    let position = (angle + 1.0) / 2.0 * (wheel.len() - 1) as f64;
+    let lower = position.floor() as usize;
+    let upper = (lower + 1) % wheel.len();
+    let fraction = position - lower as f64;
+
+    let mut colour = [0; 3];
+    for channel in 0..3 {
+        let hue =
+            ((1.0 - fraction) * wheel[lower][channel] + fraction * wheel[upper][channel]) / 255.0;
+
+        // Desaturate towards white for short vectors, darken out of range ones
+        let value = if radius <= 1.0 {
+            1.0 - radius * (1.0 - hue)
+        } else {
+            hue * 0.75
+        };
+
+        colour[channel] = f64::round(255.0 * value) as u8;
+    }
+
+    Rgb(colour)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{test_utils::temp_folder, types::MotionVector};
+
+    fn block_motion(x_offset: u32, dx: i32, dy: i32, cost: f64) -> BlockMotion {
+        BlockMotion {
+            x_offset,
+            y_offset: 0,
+            width: 16,
+            height: 16,
+            vector: MotionVector {
+                dx,
+                dy,
+                cost,
+                points_evaluated: 1,
+                points_pruned: 0,
+            },
+        }
+    }
+
+    #[test]
+    fn arrows_point_from_block_centres_to_their_match() {
+        let frame = DynamicImage::new_luma8(32, 16);
+        let blocks = [block_motion(0, 6, 0, 0.0), block_motion(16, 0, -5, 0.0)];
+        let arrow_colour = Rgb([255, 0, 255]);
+        let overlay = VectorOverlay::new()
+            .with_grid(false)
+            .with_cost_colouring(false)
+            .with_arrow_colour(arrow_colour)
+            .render(&frame, &blocks);
+
+        for (x, y) in [(8, 8), (11, 8), (14, 8), (24, 8), (24, 3)] {
+            assert_eq!(*overlay.get_pixel(x, y), arrow_colour, "({}, {})", x, y);
+        }
+        // Nothing else is drawn
+        assert_eq!(*overlay.get_pixel(0, 0), Rgb([0, 0, 0]));
+        assert_eq!(*overlay.get_pixel(24, 12), Rgb([0, 0, 0]));
+    }
+
+    #[test]
+    fn grids_outline_the_top_left_of_each_block() {
+        let frame = DynamicImage::new_luma8(32, 16);
+        let overlay = VectorOverlay::new().with_cost_colouring(false).render(
+            &frame,
+            &[block_motion(0, 0, 0, 0.0), block_motion(16, 0, 0, 0.0)],
+        );
+
+        for (x, y) in [(0, 0), (5, 0), (0, 15), (16, 7), (31, 0)] {
+            assert_eq!(*overlay.get_pixel(x, y), GRID_COLOUR, "({}, {})", x, y);
+        }
+        assert_eq!(*overlay.get_pixel(4, 4), Rgb([0, 0, 0]));
+    }
+
+    #[test]
+    fn costs_tint_blocks_from_green_to_red() {
+        let frame = DynamicImage::new_luma8(32, 16);
+        let overlay = VectorOverlay::new().with_grid(false).render(
+            &frame,
+            &[block_motion(0, 0, 0, 0.0), block_motion(16, 0, 0, 10.0)],
+        );
+
+        assert_eq!(*overlay.get_pixel(2, 2), Rgb([0, 89, 0]));
+        assert_eq!(*overlay.get_pixel(18, 2), Rgb([89, 0, 0]));
+        assert_eq!(cost_colour(0.5), Rgb([255, 255, 0]));
+    }
+
+    #[test]
+    fn flow_colours_follow_the_middlebury_wheel() {
+        let mut flow = FlowField::new(4, 1);
+        flow.set(1, 0, (1.0, 0.0));
+        flow.set(2, 0, (0.5, 0.0));
+        flow.set(3, 0, (f32::NAN, 0.0));
+
+        let image = render_flow(&flow, None);
+        assert_eq!(*image.get_pixel(0, 0), Rgb([255, 255, 255]));
+        // Rightward motion is red, half as long vectors are half as saturated
+        assert_eq!(*image.get_pixel(1, 0), Rgb([255, 0, 0]));
+        assert_eq!(*image.get_pixel(2, 0), Rgb([255, 128, 128]));
+        assert_eq!(*image.get_pixel(3, 0), Rgb([255, 255, 255]));
+
+        // Vectors beyond the given maximum are darkened
+        let image = render_flow(&flow, Some(0.5));
+        assert_eq!(*image.get_pixel(1, 0), Rgb([191, 0, 0]));
+    }
+
+    #[test]
+    fn animations_are_encoded_as_gifs() {
+        let mut gif = Vec::new();
+        {
+            let mut animation = GifAnimation::new(&mut gif, 100).unwrap();
+            animation.add_frame(&RgbImage::new(4, 4)).unwrap();
+            animation
+                .add_frame(&RgbImage::from_pixel(4, 4, Rgb([255, 0, 0])))
+                .unwrap();
+        }
+
+        assert!(gif.starts_with(b"GIF89a"));
+    }
+
+    #[test]
+    fn visualisations_are_saved_per_pair_and_animated() {
+        let output_folder = temp_folder("visualisation");
+        let frame = |name: &str| Arc::new((name.to_owned(), DynamicImage::new_luma8(32, 16)));
+        let pair = FramePair {
+            anchor_index: 0,
+            target_index: 1,
+            anchor: frame("0001.png"),
+            target: frame("0002.png"),
+        };
+
+        {
+            let mut visualisation = Visualisation::new(
+                output_folder.to_str().unwrap(),
+                VectorOverlay::new(),
+                8.0,
+                Some(100),
+            )
+            .unwrap();
+            visualisation
+                .export(
+                    &pair,
+                    &[block_motion(0, 2, 1, 0.0), block_motion(16, 0, 0, 1.0)],
+                )
+                .unwrap();
+            visualisation
+                .export_flow(&pair, &FlowField::new(32, 16), 16)
+                .unwrap();
+        }
+
+        for file_name in [
+            "0001_0002_vectors.png",
+            "0001_0002_flow.png",
+            "vectors.gif",
+            "flow.gif",
+        ] {
+            assert!(output_folder.join(file_name).is_file(), "{}", file_name);
+        }
+        let flow = image::open(output_folder.join("0001_0002_flow.png"))
+            .unwrap()
+            .to_rgb8();
+        assert_eq!(flow.dimensions(), (32, 16));
+    }
+}